@@ -0,0 +1,254 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::protocol::{self, ConfigKey, Frame, Readings, Status};
+
+// Talks to the monitor over its serial port: binary frames for readings and
+// settings, the text interface for the history dump
+
+// How long to wait for a reply before sending the request again
+const REPLY_TIMEOUT: Duration = Duration::from_millis(500);
+
+// Requests are sent this many times before giving up
+const ATTEMPTS: u32 = 3;
+
+// The history dump is about 2 kB, which takes 2 s at 9600 baud
+const HISTORY_TIMEOUT: Duration = Duration::from_secs(10);
+const HISTORY_HEADER: &str = "uptime_min,";
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Timeout,          // No reply after all attempts
+    Rejected(Status), // The device answered with an error status
+    Protocol(String), // A reply that does not fit the request
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "serial port: {}", error),
+            Error::Timeout => write!(f, "no reply from the monitor"),
+            Error::Rejected(status) => write!(f, "rejected by the monitor ({:?})", status),
+            Error::Protocol(message) => write!(f, "unexpected reply: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+// pH calibration points: ADC readings and the pH of the buffers they were
+// taken in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    pub adc_1: u16,
+    pub ph_1: f32,
+    pub adc_2: u16,
+    pub ph_2: f32,
+}
+
+// The port must have a short read timeout (e.g. 100 ms), so that waiting
+// for a reply can give up
+pub struct Device<P> {
+    port: P,
+    sequence: u8,
+    received: VecDeque<u8>, // Read from the port but not yet used
+    reply_timeout: Duration,
+}
+
+impl<P: Read + Write> Device<P> {
+    pub fn new(port: P) -> Self {
+        Device {
+            port,
+            sequence: 0,
+            received: VecDeque::new(),
+            reply_timeout: REPLY_TIMEOUT,
+        }
+    }
+
+    pub fn set_reply_timeout(&mut self, timeout: Duration) {
+        self.reply_timeout = timeout;
+    }
+
+    // Current sensor values
+    pub fn readings(&mut self) -> Result<Readings, Error> {
+        let reply = self.request(protocol::GET_READINGS, &[], protocol::READINGS)?;
+        Readings::from_payload(&reply.payload)
+            .ok_or_else(|| Error::Protocol(format!("READINGS with {} bytes", reply.payload.len())))
+    }
+
+    pub fn get_config(&mut self, key: ConfigKey) -> Result<u16, Error> {
+        let reply = self.request(protocol::GET_CONFIG, &[key as u8], protocol::CONFIG)?;
+        match reply.payload[..] {
+            [reply_key, low, high] if reply_key == key as u8 => Ok(u16::from_le_bytes([low, high])),
+            _ => Err(Error::Protocol(format!("CONFIG {:?}", reply.payload))),
+        }
+    }
+
+    pub fn set_config(&mut self, key: ConfigKey, value: u16) -> Result<(), Error> {
+        let value = value.to_le_bytes();
+        self.request(protocol::SET_CONFIG, &[key as u8, value[0], value[1]], protocol::ACK)?;
+        Ok(())
+    }
+
+    // Set the aeration schedule. The period goes first, as the device
+    // checks the duration against it.
+    pub fn set_schedule(&mut self, period_min: u16, duration_s: u16) -> Result<(), Error> {
+        self.set_config(ConfigKey::AerationPeriod, period_min)?;
+        self.set_config(ConfigKey::AerationDuration, duration_s)
+    }
+
+    // Send a calibration, which the device checks and saves as a whole
+    pub fn calibrate(&mut self, calibration: &Calibration) -> Result<(), Error> {
+        let mut payload = Vec::with_capacity(8);
+        payload.extend_from_slice(&calibration.adc_1.to_le_bytes());
+        payload.extend_from_slice(&((calibration.ph_1 * 100.0).round() as u16).to_le_bytes());
+        payload.extend_from_slice(&calibration.adc_2.to_le_bytes());
+        payload.extend_from_slice(&((calibration.ph_2 * 100.0).round() as u16).to_le_bytes());
+        self.request(protocol::SET_CALIBRATION, &payload, protocol::ACK)?;
+        Ok(())
+    }
+
+    // Set the device clock to a Unix time
+    pub fn set_clock(&mut self, unix_time: u32) -> Result<(), Error> {
+        self.request(protocol::SET_CLOCK, &unix_time.to_le_bytes(), protocol::ACK)?;
+        Ok(())
+    }
+
+    // The device's Unix time, if it has been set since the last reset
+    pub fn clock(&mut self) -> Result<Option<u32>, Error> {
+        let reply = self.request(protocol::GET_CLOCK, &[], protocol::CLOCK)?;
+        match reply.payload[..] {
+            [b0, b1, b2, b3] => {
+                let unix_time = u32::from_le_bytes([b0, b1, b2, b3]);
+                Ok(if unix_time == 0 { None } else { Some(unix_time) })
+            },
+            _ => Err(Error::Protocol(format!("CLOCK {:?}", reply.payload))),
+        }
+    }
+
+    // The history dump as CSV lines, header first. This uses the text
+    // interface, which also switches the device back to text telemetry.
+    pub fn history(&mut self) -> Result<Vec<String>, Error> {
+        self.port.write_all(b"history\r\n")?;
+        self.port.flush()?;
+
+        let deadline = Instant::now() + HISTORY_TIMEOUT;
+        let mut lines = Vec::new();
+        loop {
+            let line = self.read_line(deadline)?.ok_or(Error::Timeout)?;
+            if lines.is_empty() {
+                // Skip telemetry and frames sent before the dump
+                if line.starts_with(HISTORY_HEADER) {
+                    lines.push(line);
+                } else if line == "error" {
+                    return Err(Error::Rejected(Status::Rejected));
+                }
+            } else if line == "ok" {
+                return Ok(lines);
+            } else {
+                lines.push(line);
+            }
+        }
+    }
+
+    // Send a request and wait for its reply, sending it again on timeout.
+    // An ACK with an error status ends the request whatever reply was
+    // expected.
+    fn request(&mut self, message_type: u8, payload: &[u8], reply_type: u8) -> Result<Frame, Error> {
+        let sequence = self.sequence;
+        self.sequence = self.sequence.wrapping_add(1);
+        let request = Frame::new(message_type, sequence, payload).encode();
+
+        for _ in 0..ATTEMPTS {
+            self.port.write_all(&request)?;
+            self.port.flush()?;
+
+            let deadline = Instant::now() + self.reply_timeout;
+            while let Some(frame) = self.read_frame(deadline)? {
+                if frame.sequence != sequence {
+                    continue; // Readings or events sent on the device's own
+                }
+                if frame.message_type == protocol::ACK {
+                    match frame.payload[..] {
+                        [acked, status] if acked == message_type => {
+                            let status = Status::from_u8(status);
+                            if status != Status::Ok {
+                                return Err(Error::Rejected(status));
+                            }
+                            if reply_type == protocol::ACK {
+                                return Ok(frame);
+                            }
+                        },
+                        _ => {},
+                    }
+                } else if frame.message_type == reply_type {
+                    return Ok(frame);
+                }
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    // The next frame with a good CRC, skipping text and damaged frames
+    fn read_frame(&mut self, deadline: Instant) -> Result<Option<Frame>, Error> {
+        let mut encoded = Vec::new();
+        while let Some(byte) = self.read_byte(deadline)? {
+            if byte != 0 {
+                encoded.push(byte);
+            } else if !encoded.is_empty() {
+                if let Some(frame) = Frame::decode(&encoded) {
+                    return Ok(Some(frame));
+                }
+                encoded.clear();
+            }
+        }
+        Ok(None)
+    }
+
+    // The next text line without its line ending
+    fn read_line(&mut self, deadline: Instant) -> Result<Option<String>, Error> {
+        let mut line = Vec::new();
+        while let Some(byte) = self.read_byte(deadline)? {
+            match byte {
+                b'\n' => return Ok(Some(String::from_utf8_lossy(&line).trim().to_string())),
+                b'\r' => {},
+                _ => line.push(byte),
+            }
+        }
+        Ok(None)
+    }
+
+    fn read_byte(&mut self, deadline: Instant) -> Result<Option<u8>, Error> {
+        while self.received.is_empty() {
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+
+            let mut chunk = [0; 64];
+            match self.port.read(&mut chunk) {
+                Ok(0) => thread::sleep(Duration::from_millis(10)),
+                Ok(count) => self.received.extend(&chunk[..count]),
+                Err(error) if is_timeout(&error) => {},
+                Err(error) => return Err(error.into()),
+            }
+        }
+        Ok(self.received.pop_front())
+    }
+}
+
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+    )
+}
@@ -0,0 +1,12 @@
+// Host companion for the Algae Medium Monitor
+//
+// Talks to the firmware over its serial port, using the binary protocol for
+// readings and settings and the text commands for the history dump.
+
+pub mod device;
+pub mod log;
+pub mod metrics;
+pub mod mqtt;
+pub mod protocol;
+
+pub use device::{Calibration, Device, Error};
@@ -0,0 +1,82 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::protocol::Readings;
+
+// Formatting readings for the terminal and for CSV log files
+
+pub const CSV_HEADER: &str =
+    "time,uptime_s,temp_c,ph,ph_mv,vcc_v,temp_stable,ph_stable,temp_fault,alarm,electrode";
+
+// One CSV row, in the columns of CSV_HEADER
+pub fn csv_row(unix_time: u64, readings: &Readings) -> String {
+    format!(
+        "{},{},{:.1},{:.2},{:.1},{:.2},{},{},{},{},{}",
+        format_time(unix_time),
+        readings.uptime_s,
+        readings.temperature,
+        readings.ph,
+        readings.ph_mv,
+        readings.supply_voltage,
+        readings.temperature_stable as u8,
+        readings.ph_stable as u8,
+        readings.temperature_fault as u8,
+        readings.alarm,
+        readings.electrode_health_name(),
+    )
+}
+
+// One line for the live readout, like the firmware's telemetry line
+pub fn readout(unix_time: u64, readings: &Readings) -> String {
+    let temperature = if readings.temperature_fault {
+        "----".to_string()
+    } else {
+        format!("{:.1}", readings.temperature)
+    };
+    format!(
+        "{} temp={} ph={:.2} mv={:.1} vcc={:.2} electrode={} temp_stable={} ph_stable={} alarm={}",
+        format_time(unix_time),
+        temperature,
+        readings.ph,
+        readings.ph_mv,
+        readings.supply_voltage,
+        readings.electrode_health_name(),
+        readings.temperature_stable as u8,
+        readings.ph_stable as u8,
+        readings.alarm,
+    )
+}
+
+// Seconds since the Unix epoch on this computer
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+// ISO 8601 UTC time, e.g. 2024-05-01T12:00:00Z
+pub fn format_time(unix_time: u64) -> String {
+    let days = (unix_time / 86_400) as i64;
+    let seconds = unix_time % 86_400;
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
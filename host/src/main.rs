@@ -0,0 +1,339 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use algae_host::metrics::{self, Metrics};
+use algae_host::mqtt;
+use algae_host::protocol::ConfigKey;
+use algae_host::{log, Calibration, Device, Error};
+use clap::{Parser, Subcommand};
+use rumqttc::MqttOptions;
+use serialport::SerialPort;
+use tiny_http::Server;
+
+// Command line companion for the Algae Medium Monitor
+
+#[derive(Parser)]
+#[command(version, about = "Read, log and configure an Algae Medium Monitor over serial")]
+struct Options {
+    /// Serial port of the monitor, e.g. /dev/ttyUSB0 or COM3
+    #[arg(short, long, env = "ALGAE_PORT")]
+    port: String,
+
+    #[arg(long, default_value_t = 9600)]
+    baud: u32,
+
+    /// Opening the port resets an Arduino; wait this long for it to start
+    #[arg(long, default_value_t = 2500)]
+    settle_ms: u64,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Show the readings live
+    Watch {
+        /// Seconds between readings
+        #[arg(short, long, default_value_t = 2)]
+        interval: u64,
+    },
+    /// Append readings to a CSV file
+    Log {
+        file: String,
+        /// Seconds between readings
+        #[arg(short, long, default_value_t = 10)]
+        interval: u64,
+        /// Stop after this many readings
+        #[arg(short, long)]
+        count: Option<u64>,
+    },
+    /// Dump the history kept in EEPROM as CSV
+    History {
+        /// Write to a file instead of the terminal
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Show one setting, or all of them
+    Get { key: Option<String> },
+    /// Change a setting (saved on the monitor)
+    Set { key: String, value: u16 },
+    /// Send a pH calibration
+    Calibrate {
+        /// ADC reading in the first buffer
+        #[arg(long)]
+        adc1: u16,
+        /// pH of the first buffer
+        #[arg(long)]
+        ph1: f32,
+        /// ADC reading in the second buffer
+        #[arg(long)]
+        adc2: u16,
+        /// pH of the second buffer
+        #[arg(long)]
+        ph2: f32,
+    },
+    /// Set the aeration schedule
+    Schedule {
+        /// Minutes from the start of one aeration to the next
+        #[arg(long)]
+        period: u16,
+        /// Seconds the bubbles run each period
+        #[arg(long)]
+        duration: u16,
+    },
+    /// Set the monitor's clock, to this computer's time by default
+    Clock {
+        /// Unix time to set instead
+        #[arg(long)]
+        unix: Option<u32>,
+    },
+    /// Serve the readings as Prometheus metrics on /metrics
+    Exporter {
+        /// Address and port to listen on
+        #[arg(short, long, default_value = "127.0.0.1:9464")]
+        listen: String,
+        /// Value of the device label, the serial port by default
+        #[arg(short, long)]
+        name: Option<String>,
+        /// Seconds between polls of the monitor
+        #[arg(short, long, default_value_t = 5)]
+        interval: u64,
+    },
+    /// Publish the readings to an MQTT broker and take settings from it
+    Mqtt {
+        /// Host name or address of the broker
+        #[arg(short, long, default_value = "localhost")]
+        broker: String,
+        #[arg(long, default_value_t = 1883)]
+        broker_port: u16,
+        /// User name on the broker
+        #[arg(short, long, env = "ALGAE_MQTT_USER")]
+        user: Option<String>,
+        #[arg(long, env = "ALGAE_MQTT_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+        /// Name in the topics (algae/<name>/...) and in Home Assistant
+        #[arg(short, long, default_value = "monitor", value_parser = parse_name)]
+        name: String,
+        /// Home Assistant discovery prefix
+        #[arg(long, default_value = "homeassistant")]
+        discovery_prefix: String,
+        /// Seconds between polls of the monitor
+        #[arg(short, long, default_value_t = 10)]
+        interval: u64,
+    },
+}
+
+fn main() -> ExitCode {
+    let options = Options::parse();
+    match run(options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(options: Options) -> Result<(), Box<dyn std::error::Error>> {
+    // These keep running, and open the port again if it goes away
+    match options.command {
+        Command::Exporter { listen, name, interval } => {
+            let name = name.unwrap_or_else(|| options.port.clone());
+            return export(&options.port, options.baud, options.settle_ms, &listen, &name, interval);
+        },
+        Command::Mqtt {
+            broker,
+            broker_port,
+            user,
+            password,
+            name,
+            discovery_prefix,
+            interval,
+        } => {
+            let mut mqtt = MqttOptions::new(format!("algae-{}", name), broker, broker_port);
+            mqtt.set_keep_alive(Duration::from_secs(30));
+            if let Some(user) = user {
+                mqtt.set_credentials(user, password.unwrap_or_default());
+            }
+            let interval = Duration::from_secs(interval);
+            return bridge(&options.port, options.baud, options.settle_ms, mqtt, &name, &discovery_prefix, interval);
+        },
+        _ => {},
+    }
+    let mut device = open(&options.port, options.baud, options.settle_ms)?;
+
+    match options.command {
+        Command::Watch { interval } => loop {
+            match device.readings() {
+                Ok(readings) => println!("{}", log::readout(log::now(), &readings)),
+                Err(error) => eprintln!("{}", error),
+            }
+            thread::sleep(Duration::from_secs(interval));
+        },
+        Command::Log { file, interval, count } => {
+            let mut output = OpenOptions::new().create(true).append(true).open(&file)?;
+            if output.metadata()?.len() == 0 {
+                writeln!(output, "{}", log::CSV_HEADER)?;
+            }
+
+            let mut logged = 0;
+            while count.is_none_or(|count| logged < count) {
+                // A missed reading leaves a gap rather than ending the log
+                match device.readings() {
+                    Ok(readings) => {
+                        writeln!(output, "{}", log::csv_row(log::now(), &readings))?;
+                        output.flush()?;
+                        logged += 1;
+                    },
+                    Err(Error::Io(error)) => return Err(error.into()),
+                    Err(error) => eprintln!("{}", error),
+                }
+                if count.is_none_or(|count| logged < count) {
+                    thread::sleep(Duration::from_secs(interval));
+                }
+            }
+        },
+        Command::History { output } => {
+            let lines = device.history()?;
+            let mut writer: Box<dyn Write> = match output {
+                Some(path) => Box::new(File::create(path)?),
+                None => Box::new(io::stdout()),
+            };
+            for line in lines {
+                writeln!(writer, "{}", line)?;
+            }
+        },
+        Command::Get { key: None } => {
+            for key in ConfigKey::ALL {
+                println!("{}={}", key.name(), device.get_config(key)?);
+            }
+        },
+        Command::Get { key: Some(name) } => {
+            let key = parse_key(&name)?;
+            println!("{}={}", key.name(), device.get_config(key)?);
+        },
+        Command::Set { key, value } => {
+            device.set_config(parse_key(&key)?, value)?;
+        },
+        Command::Calibrate { adc1, ph1, adc2, ph2 } => {
+            device.calibrate(&Calibration {
+                adc_1: adc1,
+                ph_1: ph1,
+                adc_2: adc2,
+                ph_2: ph2,
+            })?;
+        },
+        Command::Schedule { period, duration } => {
+            device.set_schedule(period, duration)?;
+        },
+        Command::Clock { unix } => {
+            let unix_time = unix.unwrap_or(log::now() as u32);
+            device.set_clock(unix_time)?;
+            if let Some(unix_time) = device.clock()? {
+                println!("{}", log::format_time(unix_time as u64));
+            }
+        },
+        Command::Exporter { .. } | Command::Mqtt { .. } => unreachable!(),
+    }
+    Ok(())
+}
+
+fn open(port: &str, baud: u32, settle_ms: u64) -> Result<Device<Box<dyn SerialPort>>, String> {
+    let port = serialport::new(port, baud)
+        .timeout(Duration::from_millis(100))
+        .open()
+        .map_err(|error| format!("{}: {}", port, error))?;
+    thread::sleep(Duration::from_millis(settle_ms));
+    Ok(Device::new(port))
+}
+
+// Poll the monitor in the background and serve the last readings. A port
+// that goes away, e.g. an unplugged USB cable, is opened again on the next
+// poll, and the device shows as down until it answers.
+fn export(
+    port: &str,
+    baud: u32,
+    settle_ms: u64,
+    listen: &str,
+    name: &str,
+    interval: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut device = Some(open(port, baud, settle_ms)?);
+    let server = Server::http(listen).map_err(|error| format!("{}: {}", listen, error))?;
+    let metrics = Arc::new(Mutex::new(Metrics::new(name)));
+
+    let polled = Arc::clone(&metrics);
+    let port = port.to_string();
+    thread::spawn(move || loop {
+        if device.is_none() {
+            device = open(&port, baud, settle_ms).map_err(|error| eprintln!("{}", error)).ok();
+        }
+        let readings = match device.as_mut().map(|device| device.readings()) {
+            Some(Ok(readings)) => Some(readings),
+            Some(Err(error)) => {
+                eprintln!("{}", error);
+                if let Error::Io(_) = error {
+                    device = None;
+                }
+                None
+            },
+            None => None,
+        };
+        polled.lock().unwrap().update(readings, log::now());
+        thread::sleep(Duration::from_secs(interval));
+    });
+
+    metrics::serve(&server, &metrics)?;
+    Ok(())
+}
+
+// Run the MQTT bridge until its connection thread stops
+fn bridge(
+    port: &str,
+    baud: u32,
+    settle_ms: u64,
+    options: MqttOptions,
+    name: &str,
+    discovery_prefix: &str,
+    interval: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut device = open(port, baud, settle_ms)?;
+    let (mut bridge, messages) = mqtt::connect(options, name, discovery_prefix);
+    loop {
+        match bridge.run(&mut device, &messages, interval) {
+            Ok(()) => return Ok(()),
+            Err(error) => {
+                eprintln!("{}", error);
+                bridge.set_available(false);
+                device = loop {
+                    thread::sleep(interval);
+                    match open(port, baud, settle_ms) {
+                        Ok(device) => break device,
+                        Err(error) => eprintln!("{}", error),
+                    }
+                };
+            },
+        }
+    }
+}
+
+// Names go into MQTT topics and Home Assistant ids
+fn parse_name(name: &str) -> Result<String, String> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if name.is_empty() || !name.chars().all(valid) {
+        return Err("use only letters, digits, - and _".to_string());
+    }
+    Ok(name.to_string())
+}
+
+fn parse_key(name: &str) -> Result<ConfigKey, String> {
+    ConfigKey::from_name(name).ok_or_else(|| {
+        let names: Vec<&str> = ConfigKey::ALL.iter().map(|key| key.name()).collect();
+        format!("unknown setting {}, expected one of {}", name, names.join(", "))
+    })
+}
@@ -0,0 +1,177 @@
+use std::fmt::Write as _;
+use std::io;
+use std::sync::Mutex;
+
+use tiny_http::{Header, Response, Server};
+
+use crate::protocol::Readings;
+
+// Prometheus metrics for one monitor, served on /metrics
+//
+// Every series has a `device` label, so several exporters can feed one
+// Prometheus. Sensor series also have a `probe` label: `ds18b20` for the
+// temperature probe and `ph` for the pH electrode.
+
+const PROMETHEUS_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+const OPENMETRICS_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+// Alarm codes and names, as the firmware's alarm.rs numbers them
+const ALARMS: [(u8, &str); 4] = [(1, "temp_probe"), (2, "low_slope"), (3, "ph_offset"), (4, "slow_ph")];
+
+// Electrode health codes and names, as in the READINGS frame
+const ELECTRODE_STATES: [(u8, &str); 4] = [(0, "ok"), (1, "slope"), (2, "offset"), (3, "slow")];
+
+// What the exporter knows about a monitor
+pub struct Metrics {
+    device: String,
+    readings: Option<Readings>, // From the last poll that got an answer
+    up: bool,                   // Whether the last poll got an answer
+    updated: u64,               // Unix time of the last answer
+    errors: u64,                // Polls that got no answer
+}
+
+impl Metrics {
+    pub fn new(device: &str) -> Self {
+        Metrics {
+            device: device.to_string(),
+            readings: None,
+            up: false,
+            updated: 0,
+            errors: 0,
+        }
+    }
+
+    // Record a poll of the monitor. Failed polls keep the last readings but
+    // mark the device down.
+    pub fn update(&mut self, readings: Option<Readings>, unix_time: u64) {
+        match readings {
+            Some(readings) => {
+                self.readings = Some(readings);
+                self.up = true;
+                self.updated = unix_time;
+            },
+            None => {
+                self.up = false;
+                self.errors += 1;
+            },
+        }
+    }
+
+    // The metrics in the Prometheus text format, or in OpenMetrics
+    pub fn render(&self, openmetrics: bool) -> String {
+        let mut output = Output {
+            text: String::new(),
+            device: escape(&self.device),
+            openmetrics,
+        };
+
+        output.family("algae_up", "gauge", "Whether the last poll of the monitor got an answer");
+        output.sample("algae_up", "", self.up as u8);
+        output.family("algae_poll_errors_total", "counter", "Polls of the monitor that got no answer");
+        output.sample("algae_poll_errors_total", "", self.errors);
+
+        // Nothing more until the monitor has answered once
+        let readings = match &self.readings {
+            Some(readings) => readings,
+            None => return output.finish(),
+        };
+
+        output.family("algae_last_reading_timestamp_seconds", "gauge", "Unix time of the last readings");
+        output.sample("algae_last_reading_timestamp_seconds", "", self.updated);
+        output.family("algae_uptime_seconds", "gauge", "Time since the monitor was reset");
+        output.sample("algae_uptime_seconds", "", readings.uptime_s);
+        output.family("algae_supply_volts", "gauge", "Supply voltage measured by the monitor");
+        output.sample("algae_supply_volts", "", readings.supply_voltage);
+
+        output.family("algae_temperature_celsius", "gauge", "Culture temperature");
+        if !readings.temperature_fault {
+            output.sample("algae_temperature_celsius", "probe=\"ds18b20\"", readings.temperature);
+        }
+        output.family("algae_ph", "gauge", "Culture pH");
+        output.sample("algae_ph", "probe=\"ph\"", readings.ph);
+        output.family("algae_ph_electrode_millivolts", "gauge", "Raw pH electrode potential");
+        output.sample("algae_ph_electrode_millivolts", "probe=\"ph\"", readings.ph_mv);
+
+        output.family("algae_probe_stable", "gauge", "Whether the probe reading has settled");
+        output.sample("algae_probe_stable", "probe=\"ds18b20\"", readings.temperature_stable as u8);
+        output.sample("algae_probe_stable", "probe=\"ph\"", readings.ph_stable as u8);
+        output.family("algae_probe_fault", "gauge", "Whether the probe is not responding");
+        output.sample("algae_probe_fault", "probe=\"ds18b20\"", readings.temperature_fault as u8);
+
+        output.family("algae_electrode_health", "gauge", "pH electrode diagnosis, 1 for the current state");
+        for (code, state) in ELECTRODE_STATES {
+            let labels = format!("probe=\"ph\",state=\"{}\"", state);
+            output.sample("algae_electrode_health", &labels, (readings.electrode_health == code) as u8);
+        }
+
+        output.family("algae_alarm_code", "gauge", "Alarm code shown on the monitor, 0 for none");
+        output.sample("algae_alarm_code", "", readings.alarm);
+        output.family("algae_alarm", "gauge", "Whether each alarm condition is active");
+        for (code, alarm) in ALARMS {
+            let labels = format!("alarm=\"{}\"", alarm);
+            output.sample("algae_alarm", &labels, (readings.alarm == code) as u8);
+        }
+
+        output.finish()
+    }
+}
+
+// Answer scrapes until the server fails. /metrics is answered in
+// OpenMetrics when the scraper asks for it, and in the Prometheus text
+// format otherwise.
+pub fn serve(server: &Server, metrics: &Mutex<Metrics>) -> io::Result<()> {
+    for request in server.incoming_requests() {
+        let path = request.url().split('?').next().unwrap_or("");
+        if path != "/metrics" {
+            let response = Response::from_string("see /metrics\n").with_status_code(404);
+            request.respond(response)?;
+            continue;
+        }
+
+        let openmetrics = request.headers().iter().any(|header| {
+            header.field.equiv("Accept") && header.value.as_str().contains("application/openmetrics-text")
+        });
+        let text = metrics.lock().unwrap().render(openmetrics);
+        let content_type = if openmetrics { OPENMETRICS_TYPE } else { PROMETHEUS_TYPE };
+        let header = Header::from_bytes("Content-Type", content_type).unwrap();
+        request.respond(Response::from_string(text).with_header(header))?;
+    }
+    Ok(())
+}
+
+// Builds the exposition text
+struct Output {
+    text: String,
+    device: String, // Escaped label value
+    openmetrics: bool,
+}
+
+impl Output {
+    // HELP and TYPE lines. OpenMetrics names a counter family without the
+    // _total suffix of its sample.
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let name = match name.strip_suffix("_total") {
+            Some(family) if self.openmetrics => family,
+            _ => name,
+        };
+        writeln!(self.text, "# HELP {} {}", name, help).unwrap();
+        writeln!(self.text, "# TYPE {} {}", name, kind).unwrap();
+    }
+
+    fn sample(&mut self, name: &str, labels: &str, value: impl std::fmt::Display) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        writeln!(self.text, "{}{{device=\"{}\"{}{}}} {}", name, self.device, separator, labels, value).unwrap();
+    }
+
+    fn finish(mut self) -> String {
+        if self.openmetrics {
+            self.text.push_str("# EOF\n");
+        }
+        self.text
+    }
+}
+
+// A label value with backslashes, quotes and line feeds escaped
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
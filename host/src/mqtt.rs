@@ -0,0 +1,396 @@
+use std::io::{Read, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+use serde_json::{json, Value};
+
+use crate::device::{Device, Error};
+use crate::protocol::{ConfigKey, Readings};
+
+// MQTT bridge: publishes the monitor's readings and settings, and passes
+// values sent to the command topics on to the monitor
+//
+// Topics, under algae/<name>:
+//
+//   availability       online or offline (offline is also the last will)
+//   <measurement>      one topic per entry of MEASUREMENTS
+//   config/<setting>   current value of a setting, e.g. config/brightness
+//   set/<setting>      a new value for a setting, from other clients
+//
+// All but set/ are retained. Home Assistant discovery configs are published
+// under <prefix>/<component>/algae_<name>/<object>/config, so the monitor
+// shows up as a device with sensors and number entities for its settings.
+
+const AVAILABLE: &str = "online";
+const UNAVAILABLE: &str = "offline";
+
+// Wait between attempts when the broker cannot be reached
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+// A value published from the readings
+struct Measurement {
+    object: &'static str, // Topic and Home Assistant object id
+    name: &'static str,
+    binary: bool, // ON/OFF binary_sensor rather than a sensor
+    unit: Option<&'static str>,
+    device_class: Option<&'static str>,
+    value: fn(&Readings) -> Option<String>, // None leaves the last value
+}
+
+const MEASUREMENTS: [Measurement; 11] = [
+    Measurement {
+        object: "temperature",
+        name: "Temperature",
+        binary: false,
+        unit: Some("°C"),
+        device_class: Some("temperature"),
+        value: |readings| (!readings.temperature_fault).then(|| format!("{:.1}", readings.temperature)),
+    },
+    Measurement {
+        object: "ph",
+        name: "pH",
+        binary: false,
+        unit: None,
+        device_class: Some("ph"),
+        value: |readings| Some(format!("{:.2}", readings.ph)),
+    },
+    Measurement {
+        object: "ph_mv",
+        name: "Electrode potential",
+        binary: false,
+        unit: Some("mV"),
+        device_class: Some("voltage"),
+        value: |readings| Some(format!("{:.1}", readings.ph_mv)),
+    },
+    Measurement {
+        object: "supply_voltage",
+        name: "Supply voltage",
+        binary: false,
+        unit: Some("V"),
+        device_class: Some("voltage"),
+        value: |readings| Some(format!("{:.2}", readings.supply_voltage)),
+    },
+    Measurement {
+        object: "uptime",
+        name: "Uptime",
+        binary: false,
+        unit: Some("s"),
+        device_class: Some("duration"),
+        value: |readings| Some(readings.uptime_s.to_string()),
+    },
+    Measurement {
+        object: "alarm_code",
+        name: "Alarm code",
+        binary: false,
+        unit: None,
+        device_class: None,
+        value: |readings| Some(readings.alarm.to_string()),
+    },
+    Measurement {
+        object: "electrode",
+        name: "Electrode health",
+        binary: false,
+        unit: None,
+        device_class: None,
+        value: |readings| Some(readings.electrode_health_name().to_string()),
+    },
+    Measurement {
+        object: "alarm",
+        name: "Alarm",
+        binary: true,
+        unit: None,
+        device_class: Some("problem"),
+        value: |readings| Some(on_off(readings.alarm != 0)),
+    },
+    Measurement {
+        object: "temperature_fault",
+        name: "Temperature probe fault",
+        binary: true,
+        unit: None,
+        device_class: Some("problem"),
+        value: |readings| Some(on_off(readings.temperature_fault)),
+    },
+    Measurement {
+        object: "temperature_stable",
+        name: "Temperature stable",
+        binary: true,
+        unit: None,
+        device_class: None,
+        value: |readings| Some(on_off(readings.temperature_stable)),
+    },
+    Measurement {
+        object: "ph_stable",
+        name: "pH stable",
+        binary: true,
+        unit: None,
+        device_class: None,
+        value: |readings| Some(on_off(readings.ph_stable)),
+    },
+];
+
+// Settings offered to Home Assistant as number entities: key, name, range
+// and unit. The others can still be set through their set/ topic.
+const NUMBERS: [(ConfigKey, &str, u16, u16, Option<&str>); 4] = [
+    (ConfigKey::Brightness, "Display brightness", 0, 8, None),
+    (ConfigKey::AerationPeriod, "Aeration period", 1, 240, Some("min")),
+    (ConfigKey::AerationDuration, "Aeration duration", 0, 900, Some("s")),
+    (ConfigKey::HistoryInterval, "History interval", 5, 1440, Some("min")),
+];
+
+// What the connection thread passes on to the bridge
+pub enum Message {
+    Connected,                                   // Also after reconnecting
+    Publish { topic: String, payload: Vec<u8> }, // On a subscribed topic
+}
+
+pub struct Bridge {
+    client: Client,
+    name: String,
+    base: String, // algae/<name>
+    discovery_prefix: String,
+    available: Option<bool>, // Last availability published
+}
+
+// Connect to the broker in the background. The name goes into topics and
+// ids, so it should be made of letters, digits, - and _.
+pub fn connect(mut options: MqttOptions, name: &str, discovery_prefix: &str) -> (Bridge, Receiver<Message>) {
+    let base = format!("algae/{}", name);
+    options.set_last_will(LastWill::new(
+        format!("{}/availability", base),
+        UNAVAILABLE,
+        QoS::AtLeastOnce,
+        true,
+    ));
+    let (client, mut connection) = Client::new(options, 16);
+
+    let (sender, messages) = mpsc::channel();
+    thread::spawn(move || {
+        for event in connection.iter() {
+            let message = match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => Message::Connected,
+                Ok(Event::Incoming(Packet::Publish(publish))) => Message::Publish {
+                    topic: publish.topic,
+                    payload: publish.payload.to_vec(),
+                },
+                Ok(_) => continue,
+                Err(error) => {
+                    // The next iteration connects again
+                    eprintln!("mqtt: {}", error);
+                    thread::sleep(RECONNECT_DELAY);
+                    continue;
+                },
+            };
+            if sender.send(message).is_err() {
+                return; // The bridge is gone
+            }
+        }
+    });
+
+    let bridge = Bridge {
+        client,
+        name: name.to_string(),
+        base,
+        discovery_prefix: discovery_prefix.to_string(),
+        available: None,
+    };
+    (bridge, messages)
+}
+
+impl Bridge {
+    // Poll the monitor every interval and handle messages in between.
+    // Returns when the connection thread has stopped, or with an error when
+    // the serial port fails; the monitor not answering is not an error.
+    pub fn run<P: Read + Write>(
+        &mut self,
+        device: &mut Device<P>,
+        messages: &Receiver<Message>,
+        interval: Duration,
+    ) -> Result<(), Error> {
+        let mut next_poll = Instant::now();
+        loop {
+            let wait = next_poll.saturating_duration_since(Instant::now());
+            match messages.recv_timeout(wait) {
+                Ok(Message::Connected) => self.announce(device)?,
+                Ok(Message::Publish { topic, payload }) => self.command(device, &topic, &payload)?,
+                Err(RecvTimeoutError::Timeout) => {
+                    self.poll(device)?;
+                    next_poll = Instant::now() + interval;
+                },
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+
+    // Mark the monitor available or not, when that changes
+    pub fn set_available(&mut self, available: bool) {
+        if self.available != Some(available) {
+            self.available = Some(available);
+            self.publish("availability", if available { AVAILABLE } else { UNAVAILABLE });
+        }
+    }
+
+    // After (re)connecting: subscribe and publish everything retained
+    fn announce<P: Read + Write>(&mut self, device: &mut Device<P>) -> Result<(), Error> {
+        if let Err(error) = self.client.subscribe(format!("{}/set/+", self.base), QoS::AtLeastOnce) {
+            eprintln!("mqtt: {}", error);
+        }
+        for (topic, payload) in self.discovery() {
+            self.publish_to(&topic, &payload);
+        }
+
+        // Whatever the broker last had may be out of date
+        if let Some(available) = self.available.take() {
+            self.set_available(available);
+        }
+        for key in ConfigKey::ALL {
+            self.publish_config(device, key)?;
+        }
+        Ok(())
+    }
+
+    fn poll<P: Read + Write>(&mut self, device: &mut Device<P>) -> Result<(), Error> {
+        match answered(device.readings())? {
+            Some(readings) => {
+                for measurement in &MEASUREMENTS {
+                    if let Some(value) = (measurement.value)(&readings) {
+                        self.publish(measurement.object, &value);
+                    }
+                }
+                self.set_available(true);
+            },
+            None => self.set_available(false),
+        }
+        Ok(())
+    }
+
+    // Pass a value from set/<setting> on to the monitor, then publish what
+    // the setting is now, whether the monitor took the value or not
+    fn command<P: Read + Write>(&mut self, device: &mut Device<P>, topic: &str, payload: &[u8]) -> Result<(), Error> {
+        let name = match topic.strip_prefix(&self.base).and_then(|rest| rest.strip_prefix("/set/")) {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        let key = match ConfigKey::from_name(name) {
+            Some(key) => key,
+            None => {
+                eprintln!("mqtt: unknown setting {}", name);
+                return Ok(());
+            },
+        };
+        let value = match parse_value(payload) {
+            Some(value) => value,
+            None => {
+                eprintln!("mqtt: bad value for {}: {:?}", name, String::from_utf8_lossy(payload));
+                return self.publish_config(device, key);
+            },
+        };
+
+        answered(device.set_config(key, value))?;
+        self.publish_config(device, key)
+    }
+
+    fn publish_config<P: Read + Write>(&mut self, device: &mut Device<P>, key: ConfigKey) -> Result<(), Error> {
+        if let Some(value) = answered(device.get_config(key))? {
+            self.publish(&format!("config/{}", key.name()), &value.to_string());
+        }
+        Ok(())
+    }
+
+    // Discovery topics and configs for Home Assistant
+    fn discovery(&self) -> Vec<(String, String)> {
+        let device = json!({
+            "identifiers": [format!("algae_{}", self.name)],
+            "name": format!("Algae monitor {}", self.name),
+            "model": "Algae Medium Monitor",
+        });
+        let availability_topic = format!("{}/availability", self.base);
+        let mut configs = Vec::new();
+
+        for measurement in &MEASUREMENTS {
+            let component = if measurement.binary { "binary_sensor" } else { "sensor" };
+            let mut config = json!({
+                "name": measurement.name,
+                "unique_id": format!("algae_{}_{}", self.name, measurement.object),
+                "state_topic": format!("{}/{}", self.base, measurement.object),
+                "availability_topic": availability_topic,
+                "device": device,
+            });
+            if let Some(unit) = measurement.unit {
+                config["unit_of_measurement"] = Value::from(unit);
+            }
+            if let Some(device_class) = measurement.device_class {
+                config["device_class"] = Value::from(device_class);
+            }
+            if !measurement.binary && (measurement.unit.is_some() || measurement.object == "ph") {
+                config["state_class"] = Value::from("measurement");
+            }
+            configs.push((self.discovery_topic(component, measurement.object), config.to_string()));
+        }
+
+        for (key, name, min, max, unit) in NUMBERS {
+            let object = key.name().replace('-', "_");
+            let mut config = json!({
+                "name": name,
+                "unique_id": format!("algae_{}_{}", self.name, object),
+                "state_topic": format!("{}/config/{}", self.base, key.name()),
+                "command_topic": format!("{}/set/{}", self.base, key.name()),
+                "availability_topic": availability_topic,
+                "min": min,
+                "max": max,
+                "step": 1,
+                "mode": "box",
+                "entity_category": "config",
+                "device": device,
+            });
+            if let Some(unit) = unit {
+                config["unit_of_measurement"] = Value::from(unit);
+            }
+            configs.push((self.discovery_topic("number", &object), config.to_string()));
+        }
+        configs
+    }
+
+    fn discovery_topic(&self, component: &str, object: &str) -> String {
+        format!("{}/{}/algae_{}/{}/config", self.discovery_prefix, component, self.name, object)
+    }
+
+    // Publish under algae/<name>
+    fn publish(&self, subtopic: &str, payload: &str) {
+        self.publish_to(&format!("{}/{}", self.base, subtopic), payload);
+    }
+
+    fn publish_to(&self, topic: &str, payload: &str) {
+        if let Err(error) = self.client.publish(topic, QoS::AtLeastOnce, true, payload) {
+            eprintln!("mqtt: {}", error);
+        }
+    }
+}
+
+// A setting value as text. Home Assistant may send whole numbers as e.g.
+// "5.0".
+fn parse_value(payload: &[u8]) -> Option<u16> {
+    let value: f64 = std::str::from_utf8(payload).ok()?.trim().parse().ok()?;
+    if value.fract() != 0.0 || !(0.0..=u16::MAX as f64).contains(&value) {
+        return None;
+    }
+    Some(value as u16)
+}
+
+// The result of a request, or None when the monitor did not take it. Only a
+// failing serial port is passed on as an error.
+fn answered<T>(result: Result<T, Error>) -> Result<Option<T>, Error> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(Error::Io(error)) => Err(Error::Io(error)),
+        Err(error) => {
+            eprintln!("{}", error);
+            Ok(None)
+        },
+    }
+}
+
+fn on_off(on: bool) -> String {
+    if on { "ON" } else { "OFF" }.to_string()
+}
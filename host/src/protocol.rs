@@ -0,0 +1,252 @@
+// The firmware's binary serial protocol (see src/protocol.rs in the firmware)
+//
+// Frames are COBS encoded between zero bytes:
+//
+//   0x00  COBS(type, sequence, payload..., crc_hi, crc_lo)  0x00
+//
+// with a CRC-16/CCITT-FALSE over type, sequence and payload. Values are
+// little endian.
+
+// Message types
+pub const GET_READINGS: u8 = 0x01;
+pub const GET_CONFIG: u8 = 0x02;
+pub const SET_CONFIG: u8 = 0x03;
+pub const SET_CLOCK: u8 = 0x04;
+pub const GET_CLOCK: u8 = 0x05;
+pub const SET_CALIBRATION: u8 = 0x06;
+pub const ACK: u8 = 0x80;
+pub const READINGS: u8 = 0x81;
+pub const CONFIG: u8 = 0x82;
+pub const EVENT: u8 = 0x83;
+pub const CLOCK: u8 = 0x84;
+
+// A decoded frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub message_type: u8,
+    pub sequence: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(message_type: u8, sequence: u8, payload: &[u8]) -> Self {
+        Frame {
+            message_type,
+            sequence,
+            payload: payload.to_vec(),
+        }
+    }
+
+    // The frame with its CRC, COBS encoded and delimited, ready to send
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = vec![self.message_type, self.sequence];
+        data.extend_from_slice(&self.payload);
+        let crc = crc16(&data);
+        data.extend_from_slice(&crc.to_be_bytes());
+
+        let mut encoded = vec![0];
+        for block in data.split(|&byte| byte == 0) {
+            // Frames are far shorter than the 254 byte COBS block limit
+            encoded.push(block.len() as u8 + 1);
+            encoded.extend_from_slice(block);
+        }
+        encoded.push(0);
+        encoded
+    }
+
+    // Decode the bytes between two zero delimiters. Returns None for
+    // anything that is not a frame with a good CRC, such as text.
+    pub fn decode(encoded: &[u8]) -> Option<Frame> {
+        let mut data = Vec::with_capacity(encoded.len());
+        let mut read = 0;
+        while read < encoded.len() {
+            let code = encoded[read] as usize;
+            if code == 0 || read + code > encoded.len() {
+                return None;
+            }
+            data.extend_from_slice(&encoded[read + 1..read + code]);
+            read += code;
+            if code < 0xFF && read < encoded.len() {
+                data.push(0);
+            }
+        }
+
+        if data.len() < 4 {
+            return None;
+        }
+        let (body, crc) = data.split_at(data.len() - 2);
+        if crc16(body) != u16::from_be_bytes([crc[0], crc[1]]) {
+            return None;
+        }
+        Some(Frame::new(body[0], body[1], &body[2..]))
+    }
+}
+
+// ACK status codes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Ok,
+    UnknownType,
+    BadLength,
+    UnknownKey,
+    Rejected,
+    Other(u8),
+}
+
+impl Status {
+    pub fn from_u8(code: u8) -> Status {
+        match code {
+            0 => Status::Ok,
+            1 => Status::UnknownType,
+            2 => Status::BadLength,
+            3 => Status::UnknownKey,
+            4 => Status::Rejected,
+            code => Status::Other(code),
+        }
+    }
+}
+
+// Settings that can be read and written, as in the firmware's config.rs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigKey {
+    Brightness = 1,
+    AerationPeriod = 2,
+    AerationDuration = 3,
+    HistoryInterval = 4,
+    CalibrationAdc1 = 5,
+    CalibrationPh1 = 6,
+    CalibrationAdc2 = 7,
+    CalibrationPh2 = 8,
+    ModbusAddress = 9,
+    PostPort = 10,
+}
+
+impl ConfigKey {
+    pub const ALL: [ConfigKey; 10] = [
+        ConfigKey::Brightness,
+        ConfigKey::AerationPeriod,
+        ConfigKey::AerationDuration,
+        ConfigKey::HistoryInterval,
+        ConfigKey::CalibrationAdc1,
+        ConfigKey::CalibrationPh1,
+        ConfigKey::CalibrationAdc2,
+        ConfigKey::CalibrationPh2,
+        ConfigKey::ModbusAddress,
+        ConfigKey::PostPort,
+    ];
+
+    // Name used on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            ConfigKey::Brightness => "brightness",
+            ConfigKey::AerationPeriod => "aeration-period",
+            ConfigKey::AerationDuration => "aeration-duration",
+            ConfigKey::HistoryInterval => "history-interval",
+            ConfigKey::CalibrationAdc1 => "cal-adc1",
+            ConfigKey::CalibrationPh1 => "cal-ph1",
+            ConfigKey::CalibrationAdc2 => "cal-adc2",
+            ConfigKey::CalibrationPh2 => "cal-ph2",
+            ConfigKey::ModbusAddress => "modbus-address",
+            ConfigKey::PostPort => "post-port",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ConfigKey> {
+        ConfigKey::ALL.iter().copied().find(|key| key.name() == name)
+    }
+
+    pub fn from_u8(code: u8) -> Option<ConfigKey> {
+        ConfigKey::ALL.iter().copied().find(|key| *key as u8 == code)
+    }
+}
+
+// Contents of a READINGS frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct Readings {
+    pub temperature: f32, // °C
+    pub ph: f32,
+    pub ph_mv: f32,
+    pub supply_voltage: f32, // V
+    pub temperature_stable: bool,
+    pub ph_stable: bool,
+    pub temperature_fault: bool,
+    pub alarm: u8,
+    pub electrode_health: u8,
+    pub uptime_s: u32,
+}
+
+impl Readings {
+    pub fn from_payload(payload: &[u8]) -> Option<Readings> {
+        if payload.len() < 15 {
+            return None;
+        }
+        let i16_at = |offset: usize| i16::from_le_bytes([payload[offset], payload[offset + 1]]);
+        let u16_at = |offset: usize| u16::from_le_bytes([payload[offset], payload[offset + 1]]);
+        let flags = payload[8];
+
+        Some(Readings {
+            temperature: i16_at(0) as f32 / 10.0,
+            ph: u16_at(2) as f32 / 100.0,
+            ph_mv: i16_at(4) as f32 / 10.0,
+            supply_voltage: u16_at(6) as f32 / 1000.0,
+            temperature_stable: flags & 1 != 0,
+            ph_stable: flags & 2 != 0,
+            temperature_fault: flags & 4 != 0,
+            alarm: payload[9],
+            electrode_health: payload[10],
+            uptime_s: u32::from_le_bytes([payload[11], payload[12], payload[13], payload[14]]),
+        })
+    }
+
+    // The READINGS payload, as the firmware sends it
+    pub fn to_payload(&self) -> Vec<u8> {
+        let mut flags = 0;
+        if self.temperature_stable {
+            flags |= 1;
+        }
+        if self.ph_stable {
+            flags |= 2;
+        }
+        if self.temperature_fault {
+            flags |= 4;
+        }
+
+        let mut payload = Vec::with_capacity(15);
+        payload.extend_from_slice(&(((self.temperature * 10.0).round()) as i16).to_le_bytes());
+        payload.extend_from_slice(&(((self.ph * 100.0).round()) as u16).to_le_bytes());
+        payload.extend_from_slice(&(((self.ph_mv * 10.0).round()) as i16).to_le_bytes());
+        payload.extend_from_slice(&(((self.supply_voltage * 1000.0).round()) as u16).to_le_bytes());
+        payload.push(flags);
+        payload.push(self.alarm);
+        payload.push(self.electrode_health);
+        payload.extend_from_slice(&self.uptime_s.to_le_bytes());
+        payload
+    }
+
+    // Electrode health as the telemetry line names it
+    pub fn electrode_health_name(&self) -> &'static str {
+        match self.electrode_health {
+            0 => "ok",
+            1 => "slope",
+            2 => "offset",
+            3 => "slow",
+            _ => "unknown",
+        }
+    }
+}
+
+// CRC-16/CCITT-FALSE
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
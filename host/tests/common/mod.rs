@@ -0,0 +1,214 @@
+// A fake monitor on the other end of a pseudo-terminal, shared by the tests
+#![cfg(unix)]
+#![allow(dead_code)]
+
+use std::io::{Read, Write};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use algae_host::protocol::{self, ConfigKey, Frame, Readings};
+use algae_host::Device;
+use serialport::{SerialPort, TTYPort};
+
+pub const READINGS: Readings = Readings {
+    temperature: 24.5,
+    ph: 7.12,
+    ph_mv: -6.8,
+    supply_voltage: 4.98,
+    temperature_stable: true,
+    ph_stable: false,
+    temperature_fault: false,
+    alarm: 0,
+    electrode_health: 0,
+    uptime_s: 3600,
+};
+
+// What the fake monitor does besides answering
+#[derive(Clone, Copy, Default)]
+pub struct Behaviour {
+    pub drop_first: bool,    // Ignore the first request, so the host retries
+    pub chatter: bool,       // Send text and a READINGS frame before each reply
+    pub ignore_frames: bool, // Never answer frames
+}
+
+// Settings and calibration as the firmware keeps them
+pub struct Monitor {
+    pub behaviour: Behaviour,
+    pub settings: [u16; 11],
+    pub calibration: [u16; 4],
+    pub clock: u32,
+    pub requests: u32,
+}
+
+impl Monitor {
+    fn new(behaviour: Behaviour) -> Self {
+        let mut settings = [0; 11];
+        settings[ConfigKey::Brightness as usize] = 5;
+        settings[ConfigKey::AerationPeriod as usize] = 60;
+        settings[ConfigKey::AerationDuration as usize] = 300;
+        settings[ConfigKey::HistoryInterval as usize] = 30;
+        settings[ConfigKey::ModbusAddress as usize] = 1;
+        Monitor {
+            behaviour,
+            settings,
+            calibration: [1020, 200, 650, 1400],
+            clock: 0,
+            requests: 0,
+        }
+    }
+
+    // The reply frames for a request frame
+    fn answer(&mut self, request: &Frame) -> Vec<Frame> {
+        let ack = |status: u8| Frame::new(protocol::ACK, request.sequence, &[request.message_type, status]);
+        let payload = &request.payload[..];
+
+        let reply = match (request.message_type, payload) {
+            (protocol::GET_READINGS, []) => Frame::new(protocol::READINGS, request.sequence, &READINGS.to_payload()),
+            (protocol::GET_CONFIG, [key]) => match ConfigKey::from_u8(*key) {
+                Some(key) => {
+                    let value = self.get(key).to_le_bytes();
+                    Frame::new(protocol::CONFIG, request.sequence, &[key as u8, value[0], value[1]])
+                },
+                None => ack(3),
+            },
+            (protocol::SET_CONFIG, [key, low, high]) => match ConfigKey::from_u8(*key) {
+                Some(key) => ack(if self.set(key, u16::from_le_bytes([*low, *high])) { 0 } else { 4 }),
+                None => ack(3),
+            },
+            (protocol::SET_CLOCK, [b0, b1, b2, b3]) => {
+                self.clock = u32::from_le_bytes([*b0, *b1, *b2, *b3]);
+                ack(0)
+            },
+            (protocol::GET_CLOCK, []) => Frame::new(protocol::CLOCK, request.sequence, &self.clock.to_le_bytes()),
+            (protocol::SET_CALIBRATION, [_, _, _, _, _, _, _, _]) => {
+                let mut calibration = [0; 4];
+                for (value, bytes) in calibration.iter_mut().zip(payload.chunks(2)) {
+                    *value = u16::from_le_bytes([bytes[0], bytes[1]]);
+                }
+                if calibration_is_valid(calibration) {
+                    self.calibration = calibration;
+                    ack(0)
+                } else {
+                    ack(4)
+                }
+            },
+            (0x01..=0x06, _) => ack(2),
+            _ => ack(1),
+        };
+
+        let mut replies = Vec::new();
+        if self.behaviour.chatter {
+            // Telemetry from the device's own sequence, which never matches
+            replies.push(Frame::new(protocol::READINGS, request.sequence.wrapping_add(100), &READINGS.to_payload()));
+        }
+        replies.push(reply);
+        replies
+    }
+
+    // Answer a request frame, as the behaviour allows
+    fn receive(&mut self, port: &mut TTYPort, request: &Frame) {
+        self.requests += 1;
+        let dropped = self.behaviour.drop_first && self.requests == 1;
+        if dropped || self.behaviour.ignore_frames {
+            return;
+        }
+        if self.behaviour.chatter {
+            port.write_all(b"temp=24.5 ph=7.12\r\n").unwrap();
+        }
+        for reply in self.answer(request) {
+            port.write_all(&reply.encode()).unwrap();
+        }
+    }
+
+    fn get(&self, key: ConfigKey) -> u16 {
+        match key {
+            ConfigKey::CalibrationAdc1 => self.calibration[0],
+            ConfigKey::CalibrationPh1 => self.calibration[1],
+            ConfigKey::CalibrationAdc2 => self.calibration[2],
+            ConfigKey::CalibrationPh2 => self.calibration[3],
+            key => self.settings[key as usize],
+        }
+    }
+
+    // The firmware's checks, for the settings the tests change
+    fn set(&mut self, key: ConfigKey, value: u16) -> bool {
+        let accepted = match key {
+            ConfigKey::AerationPeriod => (1..=240).contains(&value),
+            ConfigKey::AerationDuration => {
+                value <= 900 && value as u32 <= self.settings[ConfigKey::AerationPeriod as usize] as u32 * 60
+            },
+            ConfigKey::ModbusAddress => (1..=247).contains(&value),
+            _ => true,
+        };
+        if accepted {
+            self.settings[key as usize] = value;
+        }
+        accepted
+    }
+}
+
+// As ph::Calibration::is_valid in the firmware, after its range checks
+fn calibration_is_valid([adc_1, ph_1, adc_2, ph_2]: [u16; 4]) -> bool {
+    if adc_1 > 1023 || adc_2 > 1023 || ph_1 > 1400 || ph_2 > 1400 {
+        return false;
+    }
+    let (low_adc, high_adc) = if ph_1 < ph_2 { (adc_2, adc_1) } else { (adc_1, adc_2) };
+    ph_1 != ph_2 && high_adc >= low_adc + 20
+}
+
+// A Device on one end of a pseudo-terminal and a fake monitor on the other.
+// The monitor stops when the device is dropped.
+pub fn connect(behaviour: Behaviour) -> (Device<TTYPort>, JoinHandle<Monitor>) {
+    let (mut master, mut slave) = TTYPort::pair().expect("pseudo-terminal");
+    master.set_timeout(Duration::from_millis(20)).unwrap();
+    slave.set_timeout(Duration::from_millis(20)).unwrap();
+
+    let monitor = thread::spawn(move || run_monitor(master, Monitor::new(behaviour)));
+    let mut device = Device::new(slave);
+    device.set_reply_timeout(Duration::from_millis(200));
+    (device, monitor)
+}
+
+// Splits frames from text lines as the firmware's CommandReader does: a
+// zero opens a frame and the next one ends it, so frame bytes that happen
+// to be line feeds are not taken for line endings
+fn run_monitor(mut port: TTYPort, mut monitor: Monitor) -> Monitor {
+    let mut frame: Option<Vec<u8>> = None;
+    let mut line = Vec::new();
+    let mut chunk = [0; 64];
+    loop {
+        let count = match port.read(&mut chunk) {
+            Ok(0) => return monitor,
+            Ok(count) => count,
+            Err(error) if error.kind() == std::io::ErrorKind::TimedOut => continue,
+            Err(_) => return monitor, // The host end was closed
+        };
+
+        for &byte in &chunk[..count] {
+            match (byte, &mut frame) {
+                (0, Some(encoded)) if !encoded.is_empty() => {
+                    if let Some(request) = Frame::decode(encoded) {
+                        monitor.receive(&mut port, &request);
+                    }
+                    frame = None;
+                },
+                (0, _) => {
+                    frame = Some(Vec::new());
+                    line.clear();
+                },
+                (_, Some(encoded)) => encoded.push(byte),
+                (b'\n', None) => {
+                    if line == b"history\r" {
+                        port.write_all(b"temp=24.5 ph=7.12\r\n").unwrap();
+                        port.write_all(b"uptime_min,temp_min,temp_max,temp_avg,ph_min,ph_max,ph_avg\r\n").unwrap();
+                        port.write_all(b"30,24.1,24.6,24.3,7.05,7.15,7.10\r\n").unwrap();
+                        port.write_all(b"60,24.2,24.8,24.5,7.08,7.20,7.13\r\n").unwrap();
+                        port.write_all(b"ok\r\n").unwrap();
+                    }
+                    line.clear();
+                },
+                (_, None) => line.push(byte),
+            }
+        }
+    }
+}
@@ -0,0 +1,167 @@
+// Device against a fake monitor on the other end of a pseudo-terminal
+// (Unix only: serialport has no pseudo-terminals on Windows)
+#![cfg(unix)]
+
+mod common;
+
+use algae_host::protocol::{self, ConfigKey, Frame, Readings, Status};
+use algae_host::{Calibration, Error};
+use common::{connect, Behaviour, READINGS};
+
+#[test]
+fn frame_matches_firmware_encoding() {
+    // GET_CONFIG for key 1 with sequence 0: the zero sequence byte is
+    // replaced by a COBS code
+    let encoded = Frame::new(protocol::GET_CONFIG, 0, &[1]).encode();
+    let crc = protocol::crc16(&[0x02, 0x00, 0x01]).to_be_bytes();
+    assert_eq!(encoded, vec![0x00, 0x02, 0x02, 0x04, 0x01, crc[0], crc[1], 0x00]);
+    assert_eq!(protocol::crc16(b"123456789"), 0x29B1);
+
+    let frame = Frame::decode(&encoded[1..encoded.len() - 1]).unwrap();
+    assert_eq!(frame, Frame::new(protocol::GET_CONFIG, 0, &[1]));
+}
+
+#[test]
+fn text_is_not_a_frame() {
+    assert_eq!(Frame::decode(b"temp=24.5 ph=7.12\r\n"), None);
+
+    let mut encoded = Frame::new(protocol::GET_READINGS, 7, &[]).encode();
+    encoded[3] ^= 0x10;
+    assert_eq!(Frame::decode(&encoded[1..encoded.len() - 1]), None);
+}
+
+#[test]
+fn readings_round_trip() {
+    let payload = READINGS.to_payload();
+    assert_eq!(payload.len(), 15);
+    assert_eq!(Readings::from_payload(&payload), Some(READINGS));
+    assert_eq!(Readings::from_payload(&payload[..14]), None);
+}
+
+#[test]
+fn reads_readings_and_settings() {
+    let (mut device, monitor) = connect(Behaviour::default());
+
+    assert_eq!(device.readings().unwrap(), READINGS);
+    assert_eq!(device.get_config(ConfigKey::AerationPeriod).unwrap(), 60);
+    device.set_config(ConfigKey::Brightness, 3).unwrap();
+    assert_eq!(device.get_config(ConfigKey::Brightness).unwrap(), 3);
+
+    drop(device);
+    assert_eq!(monitor.join().unwrap().requests, 4);
+}
+
+#[test]
+fn skips_telemetry_before_the_reply() {
+    let behaviour = Behaviour {
+        chatter: true,
+        ..Behaviour::default()
+    };
+    let (mut device, _monitor) = connect(behaviour);
+
+    assert_eq!(device.get_config(ConfigKey::HistoryInterval).unwrap(), 30);
+    assert_eq!(device.readings().unwrap(), READINGS);
+}
+
+#[test]
+fn retries_a_lost_request() {
+    let behaviour = Behaviour {
+        drop_first: true,
+        ..Behaviour::default()
+    };
+    let (mut device, monitor) = connect(behaviour);
+
+    assert_eq!(device.get_config(ConfigKey::ModbusAddress).unwrap(), 1);
+    drop(device);
+    assert_eq!(monitor.join().unwrap().requests, 2);
+}
+
+#[test]
+fn times_out_without_replies() {
+    let behaviour = Behaviour {
+        ignore_frames: true,
+        ..Behaviour::default()
+    };
+    let (mut device, monitor) = connect(behaviour);
+
+    assert!(matches!(device.readings(), Err(Error::Timeout)));
+    drop(device);
+    assert_eq!(monitor.join().unwrap().requests, 3);
+}
+
+#[test]
+fn reports_rejected_settings() {
+    let (mut device, _monitor) = connect(Behaviour::default());
+
+    assert!(matches!(
+        device.set_config(ConfigKey::ModbusAddress, 300),
+        Err(Error::Rejected(Status::Rejected))
+    ));
+    // Longer than the current 60 minute period allows
+    assert!(matches!(
+        device.set_schedule(60, 3601),
+        Err(Error::Rejected(Status::Rejected))
+    ));
+}
+
+#[test]
+fn sets_the_schedule_period_first() {
+    let (mut device, _monitor) = connect(Behaviour::default());
+
+    // 20 minutes only fits once the period is 30 minutes
+    device.set_schedule(30, 900).unwrap();
+    assert_eq!(device.get_config(ConfigKey::AerationPeriod).unwrap(), 30);
+    assert_eq!(device.get_config(ConfigKey::AerationDuration).unwrap(), 900);
+}
+
+#[test]
+fn sends_the_calibration_at_once() {
+    let (mut device, monitor) = connect(Behaviour::default());
+
+    // Swaps which point has the higher pH, which a key at a time could not
+    let calibration = Calibration {
+        adc_1: 700,
+        ph_1: 9.18,
+        adc_2: 900,
+        ph_2: 4.01,
+    };
+    device.calibrate(&calibration).unwrap();
+    assert_eq!(device.get_config(ConfigKey::CalibrationPh1).unwrap(), 918);
+    assert_eq!(device.get_config(ConfigKey::CalibrationAdc2).unwrap(), 900);
+
+    // Higher pH with the higher ADC reading
+    let inverted = Calibration {
+        adc_1: 700,
+        ph_1: 4.01,
+        adc_2: 900,
+        ph_2: 9.18,
+    };
+    assert!(matches!(device.calibrate(&inverted), Err(Error::Rejected(Status::Rejected))));
+
+    drop(device);
+    assert_eq!(monitor.join().unwrap().calibration, [700, 918, 900, 401]);
+}
+
+#[test]
+fn sets_and_reads_the_clock() {
+    let (mut device, _monitor) = connect(Behaviour::default());
+
+    assert_eq!(device.clock().unwrap(), None);
+    device.set_clock(1_714_564_800).unwrap();
+    assert_eq!(device.clock().unwrap(), Some(1_714_564_800));
+}
+
+#[test]
+fn dumps_history() {
+    let (mut device, _monitor) = connect(Behaviour::default());
+
+    let lines = device.history().unwrap();
+    assert_eq!(
+        lines,
+        vec![
+            "uptime_min,temp_min,temp_max,temp_avg,ph_min,ph_max,ph_avg",
+            "30,24.1,24.6,24.3,7.05,7.15,7.10",
+            "60,24.2,24.8,24.5,7.08,7.20,7.13",
+        ]
+    );
+}
@@ -0,0 +1,150 @@
+// Prometheus exposition of the readings and the /metrics endpoint
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use algae_host::metrics::{self, Metrics};
+use algae_host::protocol::Readings;
+use tiny_http::Server;
+
+const READINGS: Readings = Readings {
+    temperature: 24.5,
+    ph: 7.12,
+    ph_mv: -6.8,
+    supply_voltage: 4.98,
+    temperature_stable: true,
+    ph_stable: false,
+    temperature_fault: false,
+    alarm: 2,
+    electrode_health: 1,
+    uptime_s: 3600,
+};
+
+// The sample lines, without HELP and TYPE
+fn samples(text: &str) -> Vec<&str> {
+    text.lines().filter(|line| !line.starts_with('#')).collect()
+}
+
+#[test]
+fn only_up_before_the_first_answer() {
+    let mut metrics = Metrics::new("tank1");
+    metrics.update(None, 1_714_564_800);
+
+    assert_eq!(
+        samples(&metrics.render(false)),
+        vec!["algae_up{device=\"tank1\"} 0", "algae_poll_errors_total{device=\"tank1\"} 1"]
+    );
+}
+
+#[test]
+fn labels_each_probe() {
+    let mut metrics = Metrics::new("tank1");
+    metrics.update(Some(READINGS), 1_714_564_800);
+    let text = metrics.render(false);
+    let samples = samples(&text);
+
+    for sample in [
+        "algae_up{device=\"tank1\"} 1",
+        "algae_last_reading_timestamp_seconds{device=\"tank1\"} 1714564800",
+        "algae_uptime_seconds{device=\"tank1\"} 3600",
+        "algae_supply_volts{device=\"tank1\"} 4.98",
+        "algae_temperature_celsius{device=\"tank1\",probe=\"ds18b20\"} 24.5",
+        "algae_ph{device=\"tank1\",probe=\"ph\"} 7.12",
+        "algae_ph_electrode_millivolts{device=\"tank1\",probe=\"ph\"} -6.8",
+        "algae_probe_stable{device=\"tank1\",probe=\"ds18b20\"} 1",
+        "algae_probe_stable{device=\"tank1\",probe=\"ph\"} 0",
+        "algae_probe_fault{device=\"tank1\",probe=\"ds18b20\"} 0",
+        "algae_electrode_health{device=\"tank1\",probe=\"ph\",state=\"ok\"} 0",
+        "algae_electrode_health{device=\"tank1\",probe=\"ph\",state=\"slope\"} 1",
+        "algae_alarm_code{device=\"tank1\"} 2",
+        "algae_alarm{device=\"tank1\",alarm=\"temp_probe\"} 0",
+        "algae_alarm{device=\"tank1\",alarm=\"low_slope\"} 1",
+    ] {
+        assert!(samples.contains(&sample), "missing {}", sample);
+    }
+    assert!(text.contains("# TYPE algae_poll_errors_total counter\n"));
+    assert!(!text.contains("# EOF"));
+}
+
+#[test]
+fn keeps_the_last_readings_when_down() {
+    let mut metrics = Metrics::new("tank1");
+    metrics.update(Some(READINGS), 1_714_564_800);
+    metrics.update(None, 1_714_564_805);
+    let text = metrics.render(false);
+    let samples = samples(&text);
+
+    assert!(samples.contains(&"algae_up{device=\"tank1\"} 0"));
+    assert!(samples.contains(&"algae_last_reading_timestamp_seconds{device=\"tank1\"} 1714564800"));
+    assert!(samples.contains(&"algae_ph{device=\"tank1\",probe=\"ph\"} 7.12"));
+}
+
+#[test]
+fn no_temperature_from_a_faulty_probe() {
+    let readings = Readings {
+        temperature_fault: true,
+        ..READINGS
+    };
+    let mut metrics = Metrics::new("tank1");
+    metrics.update(Some(readings), 1_714_564_800);
+    let text = metrics.render(false);
+
+    assert!(!text.contains("algae_temperature_celsius{"));
+    assert!(text.contains("algae_probe_fault{device=\"tank1\",probe=\"ds18b20\"} 1\n"));
+}
+
+#[test]
+fn openmetrics_format() {
+    let mut metrics = Metrics::new("tank1");
+    metrics.update(Some(READINGS), 1_714_564_800);
+    let text = metrics.render(true);
+
+    assert!(text.contains("# TYPE algae_poll_errors counter\n"));
+    assert!(text.contains("algae_poll_errors_total{device=\"tank1\"} 0\n"));
+    assert!(text.ends_with("# EOF\n"));
+}
+
+#[test]
+fn escapes_the_device_name() {
+    let metrics = Metrics::new("C:\\tank \"1\"");
+    assert!(metrics.render(false).contains("algae_up{device=\"C:\\\\tank \\\"1\\\"\"} 0\n"));
+}
+
+// Send a GET and return the whole response
+fn get(address: std::net::SocketAddr, path: &str, accept: &str) -> String {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\nAccept: {}\r\nConnection: close\r\n\r\n", path, accept).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn serves_metrics_over_http() {
+    let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+    let address = server.server_addr().to_ip().unwrap();
+    let metrics = Arc::new(Mutex::new(Metrics::new("tank1")));
+    metrics.lock().unwrap().update(Some(READINGS), 1_714_564_800);
+    {
+        let server = Arc::clone(&server);
+        let metrics = Arc::clone(&metrics);
+        thread::spawn(move || metrics::serve(&server, &metrics));
+    }
+
+    let response = get(address, "/metrics", "text/plain");
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.contains("text/plain; version=0.0.4"));
+    assert!(response.contains("algae_ph{device=\"tank1\",probe=\"ph\"} 7.12\n"));
+
+    let response = get(address, "/metrics", "application/openmetrics-text;version=1.0.0,text/plain;q=0.5");
+    assert!(response.contains("application/openmetrics-text"));
+    assert!(response.ends_with("# EOF\n"));
+
+    // Scrapes see new readings
+    metrics.lock().unwrap().update(None, 1_714_564_805);
+    assert!(get(address, "/metrics", "*/*").contains("algae_up{device=\"tank1\"} 0\n"));
+
+    assert!(get(address, "/", "*/*").starts_with("HTTP/1.1 404"));
+}
@@ -0,0 +1,93 @@
+// The firmware's Modbus request parser, built for the host
+
+#[allow(dead_code)]
+#[path = "../../src/modbus_request.rs"]
+mod modbus_request;
+
+use modbus_request::{parse, crc16, Exception, Request, HOLDING_REGISTER_COUNT, INPUT_REGISTER_COUNT};
+use modbus_request::{READ_HOLDING_REGISTERS, READ_INPUT_REGISTERS, WRITE_MULTIPLE_REGISTERS, WRITE_SINGLE_REGISTER};
+
+fn words(words: &[u16]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_be_bytes()).collect()
+}
+
+#[test]
+fn crc_matches_the_standard() {
+    assert_eq!(crc16(b"123456789"), 0x4B37);
+}
+
+#[test]
+fn reads_within_the_map() {
+    assert_eq!(
+        parse(READ_INPUT_REGISTERS, &words(&[0, INPUT_REGISTER_COUNT as u16])),
+        Ok(Request::ReadInput { start: 0, count: INPUT_REGISTER_COUNT })
+    );
+    assert_eq!(
+        parse(READ_HOLDING_REGISTERS, &words(&[HOLDING_REGISTER_COUNT as u16 - 1, 1])),
+        Ok(Request::ReadHolding { start: HOLDING_REGISTER_COUNT - 1, count: 1 })
+    );
+}
+
+#[test]
+fn rejects_reads_past_the_map() {
+    let past = [
+        [INPUT_REGISTER_COUNT as u16, 1],
+        [1, INPUT_REGISTER_COUNT as u16],
+        // start + count wraps to 0 in 16 bits
+        [0xFFFF, 1],
+        [0xFF85, 123],
+    ];
+    for request in &past {
+        assert_eq!(parse(READ_INPUT_REGISTERS, &words(request)), Err(Exception::OutsideMap));
+        assert_eq!(parse(READ_HOLDING_REGISTERS, &words(request)), Err(Exception::OutsideMap));
+    }
+}
+
+#[test]
+fn rejects_bad_counts() {
+    assert_eq!(parse(READ_INPUT_REGISTERS, &words(&[0, 0])), Err(Exception::BadValue));
+    assert_eq!(parse(READ_INPUT_REGISTERS, &words(&[0, 126])), Err(Exception::BadValue));
+    assert_eq!(parse(READ_INPUT_REGISTERS, &words(&[0])), Err(Exception::BadValue));
+}
+
+#[test]
+fn writes_one_register() {
+    let mut values = [0; HOLDING_REGISTER_COUNT];
+    values[0] = 720;
+    assert_eq!(
+        parse(WRITE_SINGLE_REGISTER, &words(&[2, 720])),
+        Ok(Request::Write { start: 2, count: 1, values })
+    );
+    assert_eq!(
+        parse(WRITE_SINGLE_REGISTER, &words(&[HOLDING_REGISTER_COUNT as u16, 1])),
+        Err(Exception::OutsideMap)
+    );
+    assert_eq!(parse(WRITE_SINGLE_REGISTER, &words(&[0xFFFF, 1])), Err(Exception::OutsideMap));
+}
+
+#[test]
+fn writes_several_registers() {
+    let mut data = words(&[1, 2]);
+    data.push(4);
+    data.extend(words(&[10, 20]));
+    let mut values = [0; HOLDING_REGISTER_COUNT];
+    values[..2].copy_from_slice(&[10, 20]);
+    assert_eq!(parse(WRITE_MULTIPLE_REGISTERS, &data), Ok(Request::Write { start: 1, count: 2, values }));
+
+    // start + count wraps to 1 in 16 bits
+    let mut data = words(&[0xFFFF, 2]);
+    data.push(4);
+    data.extend(words(&[10, 20]));
+    assert_eq!(parse(WRITE_MULTIPLE_REGISTERS, &data), Err(Exception::OutsideMap));
+
+    // The byte count must match the register count
+    let mut data = words(&[0, 2]);
+    data.push(2);
+    data.extend(words(&[10]));
+    assert_eq!(parse(WRITE_MULTIPLE_REGISTERS, &data), Err(Exception::BadValue));
+}
+
+#[test]
+fn rejects_other_functions() {
+    assert_eq!(parse(0x01, &words(&[0, 1])), Err(Exception::UnknownFunction));
+}
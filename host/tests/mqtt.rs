@@ -0,0 +1,212 @@
+// The MQTT bridge between a fake monitor and a minimal local broker
+// (Unix only: serialport has no pseudo-terminals on Windows)
+#![cfg(unix)]
+
+mod common;
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use algae_host::mqtt;
+use common::{connect, Behaviour};
+use rumqttc::MqttOptions;
+use serde_json::Value;
+
+// Just enough of an MQTT 3.1.1 broker for one client: it accepts the
+// connection and subscriptions, keeps what the client publishes and can
+// publish to the client
+struct Broker {
+    stream: TcpStream,
+    connect: Vec<u8>,                  // The CONNECT packet
+    subscriptions: Vec<String>,
+    retained: HashMap<String, String>, // Last payload on each topic
+}
+
+impl Broker {
+    fn accept(listener: &TcpListener) -> Broker {
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+        let mut broker = Broker {
+            stream,
+            connect: Vec::new(),
+            subscriptions: Vec::new(),
+            retained: HashMap::new(),
+        };
+
+        let (kind, body) = broker.read_packet(Instant::now() + Duration::from_secs(5)).expect("CONNECT");
+        assert_eq!(kind >> 4, 1);
+        broker.connect = body;
+        broker.stream.write_all(&[0x20, 2, 0, 0]).unwrap(); // CONNACK, accepted
+        broker
+    }
+
+    // Handle packets until the topic has the payload, or fail after a while
+    fn wait_for(&mut self, topic: &str, payload: &str) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while self.retained.get(topic).map(String::as_str) != Some(payload) {
+            assert!(Instant::now() < deadline, "no {} on {} (have {:?})", payload, topic, self.retained.get(topic));
+            self.handle(deadline);
+        }
+    }
+
+    fn handle(&mut self, deadline: Instant) {
+        let (kind, body) = match self.read_packet(deadline) {
+            Some(packet) => packet,
+            None => return,
+        };
+        match kind >> 4 {
+            3 => {
+                // PUBLISH
+                let qos = (kind >> 1) & 3;
+                let topic_length = u16::from_be_bytes([body[0], body[1]]) as usize;
+                let topic = String::from_utf8(body[2..2 + topic_length].to_vec()).unwrap();
+                let mut payload_start = 2 + topic_length;
+                if qos > 0 {
+                    let id = &body[payload_start..payload_start + 2];
+                    self.stream.write_all(&[0x40, 2, id[0], id[1]]).unwrap(); // PUBACK
+                    payload_start += 2;
+                }
+                let payload = String::from_utf8(body[payload_start..].to_vec()).unwrap();
+                self.retained.insert(topic, payload);
+            },
+            8 => {
+                // SUBSCRIBE: packet id, then filters with their QoS
+                let mut read = 2;
+                while read < body.len() {
+                    let length = u16::from_be_bytes([body[read], body[read + 1]]) as usize;
+                    self.subscriptions.push(String::from_utf8(body[read + 2..read + 2 + length].to_vec()).unwrap());
+                    read += 2 + length + 1;
+                }
+                self.stream.write_all(&[0x90, 3, body[0], body[1], 1]).unwrap(); // SUBACK
+            },
+            12 => self.stream.write_all(&[0xD0, 0]).unwrap(), // PINGRESP
+            _ => {},
+        }
+    }
+
+    // Publish with QoS 0
+    fn publish(&mut self, topic: &str, payload: &str) {
+        let mut packet = vec![0x30, (2 + topic.len() + payload.len()) as u8];
+        packet.extend_from_slice(&(topic.len() as u16).to_be_bytes());
+        packet.extend_from_slice(topic.as_bytes());
+        packet.extend_from_slice(payload.as_bytes());
+        self.stream.write_all(&packet).unwrap();
+    }
+
+    // The next packet's first byte and body
+    fn read_packet(&mut self, deadline: Instant) -> Option<(u8, Vec<u8>)> {
+        let kind = self.read_byte(deadline)?;
+        let mut length = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_byte(deadline)?;
+            length |= ((byte & 0x7F) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; length];
+        for byte in body.iter_mut() {
+            *byte = self.read_byte(deadline)?;
+        }
+        Some((kind, body))
+    }
+
+    fn read_byte(&mut self, deadline: Instant) -> Option<u8> {
+        let mut byte = [0];
+        while Instant::now() < deadline {
+            match self.stream.read(&mut byte) {
+                Ok(1) => return Some(byte[0]),
+                Ok(_) => panic!("the bridge disconnected"),
+                Err(_) => {}, // Read timeout
+            }
+        }
+        None
+    }
+}
+
+fn start(name: &str) -> Broker {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (mut device, _monitor) = connect(Behaviour::default());
+
+    let (mut bridge, messages) = mqtt::connect(MqttOptions::new("algae-test", "127.0.0.1", port), name, "homeassistant");
+    thread::spawn(move || bridge.run(&mut device, &messages, Duration::from_millis(200)));
+    Broker::accept(&listener)
+}
+
+#[test]
+fn publishes_readings_and_settings() {
+    let mut broker = start("tank1");
+
+    for (topic, payload) in [
+        ("algae/tank1/availability", "online"),
+        ("algae/tank1/ph", "7.12"),
+        ("algae/tank1/temperature", "24.5"),
+        ("algae/tank1/ph_mv", "-6.8"),
+        ("algae/tank1/supply_voltage", "4.98"),
+        ("algae/tank1/uptime", "3600"),
+        ("algae/tank1/alarm", "OFF"),
+        ("algae/tank1/electrode", "ok"),
+        ("algae/tank1/temperature_stable", "ON"),
+        ("algae/tank1/ph_stable", "OFF"),
+        ("algae/tank1/config/aeration-period", "60"),
+        ("algae/tank1/config/history-interval", "30"),
+        ("algae/tank1/config/cal-ph2", "1400"),
+    ] {
+        broker.wait_for(topic, payload);
+    }
+
+    assert_eq!(broker.subscriptions, vec!["algae/tank1/set/+"]);
+    // The last will marks the monitor offline if the bridge goes away
+    let will = b"algae/tank1/availability";
+    assert!(broker.connect.windows(will.len()).any(|window| window == will));
+}
+
+#[test]
+fn announces_to_home_assistant() {
+    let mut broker = start("tank1");
+    // Published after the discovery configs
+    broker.wait_for("algae/tank1/config/modbus-address", "1");
+
+    let config: Value = serde_json::from_str(&broker.retained["homeassistant/sensor/algae_tank1/ph/config"]).unwrap();
+    assert_eq!(config["state_topic"], "algae/tank1/ph");
+    assert_eq!(config["unique_id"], "algae_tank1_ph");
+    assert_eq!(config["device_class"], "ph");
+    assert_eq!(config["availability_topic"], "algae/tank1/availability");
+    assert_eq!(config["device"]["identifiers"][0], "algae_tank1");
+
+    let config: Value =
+        serde_json::from_str(&broker.retained["homeassistant/binary_sensor/algae_tank1/alarm/config"]).unwrap();
+    assert_eq!(config["device_class"], "problem");
+
+    let config: Value =
+        serde_json::from_str(&broker.retained["homeassistant/number/algae_tank1/aeration_period/config"]).unwrap();
+    assert_eq!(config["command_topic"], "algae/tank1/set/aeration-period");
+    assert_eq!(config["state_topic"], "algae/tank1/config/aeration-period");
+    assert_eq!(config["max"], 240);
+}
+
+#[test]
+fn forwards_settings_to_the_monitor() {
+    let mut broker = start("tank1");
+    broker.wait_for("algae/tank1/config/brightness", "5");
+
+    broker.publish("algae/tank1/set/brightness", "3");
+    broker.wait_for("algae/tank1/config/brightness", "3");
+    broker.publish("algae/tank1/set/aeration-duration", "600.0");
+    broker.wait_for("algae/tank1/config/aeration-duration", "600");
+
+    // Rejected by the monitor: the setting is published as it stays
+    broker.retained.remove("algae/tank1/config/modbus-address");
+    broker.publish("algae/tank1/set/modbus-address", "300");
+    broker.wait_for("algae/tank1/config/modbus-address", "1");
+
+    broker.retained.remove("algae/tank1/config/history-interval");
+    broker.publish("algae/tank1/set/history-interval", "soon");
+    broker.wait_for("algae/tank1/config/history-interval", "30");
+}
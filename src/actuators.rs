@@ -0,0 +1,181 @@
+use ruduino::Pin;
+use ruduino::cores::current::port::D5;
+use crate::sensor_manager::SensorValues;
+use crate::watchdog;
+
+// Actuator safety
+//
+// Every output that drives a load goes through here. The schedule only
+// requests an output; it is switched on when nothing locks it out, and held
+// in its safe state:
+//
+// - from boot until the main loop runs, so the display test and sensor
+//   start-up cannot leave it on (before initialize the pin floats, so the
+//   driver needs a pull-down)
+// - for RESET_HOLD_MS after a watchdog or brownout reset, so a reset loop
+//   does not keep cycling the load
+// - while the supply is below MIN_SUPPLY_V (until it is back above
+//   SUPPLY_RECOVERED_V)
+// - while the temperature probe cannot be read (a failed pH electrode is
+//   not detected, so it does not lock the outputs out)
+// - for its rest time once it has been on for its maximum on-time
+//
+//   Output     Pin  Safe state  Max on   Rest
+//   Air pump   D5   low (off)   20 min   2 min
+//
+// The other pins (display, SPI, serial, RS-485 direction) only talk to
+// chips on the board and are set up by their drivers.
+
+const RESET_HOLD_MS: u64 = 60_000;
+const MIN_SUPPLY_V: f32 = 4.4;
+const SUPPLY_RECOVERED_V: f32 = 4.5;
+
+// Outputs, indexing OUTPUTS
+#[derive(PartialEq, Copy, Clone)]
+pub enum Output {
+    AirPump = 0,
+}
+
+const OUTPUT_COUNT: usize = 1;
+
+// Safety policy of one output
+struct Policy {
+    safe_high: bool, // Pin level of the safe state
+    max_on_ms: u64,  // Longest continuous on-time
+    rest_ms: u64,    // Forced off-time after reaching max_on_ms
+}
+
+const OUTPUTS: [Policy; OUTPUT_COUNT] = [
+    // Longer than the longest aeration run the menu allows (15 minutes), so
+    // only a continuous schedule is cut short
+    Policy { safe_high: false, max_on_ms: 1_200_000, rest_ms: 120_000 },
+];
+
+// Why an output is held in its safe state
+#[derive(PartialEq, Copy, Clone)]
+pub enum Lockout {
+    None,
+    Reset,            // Recovering from a watchdog or brownout reset
+    Supply,           // Supply voltage too low
+    TemperatureProbe, // The temperature probe has failed
+    Rest,             // Resting after the maximum on-time
+}
+
+impl Lockout {
+    // Short code for telemetry and the display
+    pub fn code(self) -> &'static str {
+        match self {
+            Lockout::None => "none",
+            Lockout::Reset => "reset",
+            Lockout::Supply => "power",
+            Lockout::TemperatureProbe => "temp",
+            Lockout::Rest => "limit",
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct State {
+    requested: bool,
+    on: bool,
+    on_since: u64,   // When it was last switched on
+    rest_until: u64, // Held off until then after a long run
+    lockout: Lockout,
+}
+
+static mut STATES: [State; OUTPUT_COUNT] = [State {
+    requested: false,
+    on: false,
+    on_since: 0,
+    rest_until: 0,
+    lockout: Lockout::None,
+}; OUTPUT_COUNT];
+
+// The supply dropped below MIN_SUPPLY_V and has not recovered yet
+static mut SUPPLY_LOW: bool = false;
+
+// Put every output in its safe state; first thing at boot
+pub fn initialize() {
+    // The level first, so the pin never drives the other way
+    for index in 0..OUTPUT_COUNT {
+        drive(index, OUTPUTS[index].safe_high);
+    }
+    D5::set_output();
+}
+
+// Ask for an output to be on or off; update switches it
+pub fn request(output: Output, on: bool) {
+    unsafe {
+        STATES[output as usize].requested = on;
+    }
+}
+
+// Has the supply dropped below MIN_SUPPLY_V, and not recovered yet?
+pub fn supply_low() -> bool {
+    unsafe { SUPPLY_LOW }
+}
+
+// Is the output on?
+pub fn is_on(output: Output) -> bool {
+    unsafe { STATES[output as usize].on }
+}
+
+// Why the output is held in its safe state, if it is
+pub fn lockout(output: Output) -> Lockout {
+    unsafe { STATES[output as usize].lockout }
+}
+
+// Apply the lockouts and on-time limits and switch the outputs
+pub fn update(values: &SensorValues, current_time: u64) {
+    let supply_low = unsafe {
+        SUPPLY_LOW = if SUPPLY_LOW {
+            values.supply_voltage < SUPPLY_RECOVERED_V
+        } else {
+            values.supply_voltage < MIN_SUPPLY_V
+        };
+        SUPPLY_LOW
+    };
+    
+    let lockout = if watchdog::reset_cause().is_fault() && current_time < RESET_HOLD_MS {
+        Lockout::Reset
+    } else if supply_low {
+        Lockout::Supply
+    } else if values.temperature_fault {
+        Lockout::TemperatureProbe
+    } else {
+        Lockout::None
+    };
+    
+    for index in 0..OUTPUT_COUNT {
+        let policy = &OUTPUTS[index];
+        let state = unsafe { &mut STATES[index] };
+        
+        state.lockout = lockout;
+        if state.lockout == Lockout::None {
+            if state.on && current_time - state.on_since >= policy.max_on_ms {
+                state.rest_until = current_time + policy.rest_ms;
+            }
+            if current_time < state.rest_until {
+                state.lockout = Lockout::Rest;
+            }
+        }
+        
+        let on = state.requested && state.lockout == Lockout::None;
+        if on && !state.on {
+            state.on_since = current_time;
+        }
+        state.on = on;
+        drive(index, on != policy.safe_high);
+    }
+}
+
+// Set an output pin level
+fn drive(index: usize, high: bool) {
+    if index == Output::AirPump as usize {
+        if high {
+            D5::set_high();
+        } else {
+            D5::set_low();
+        }
+    }
+}
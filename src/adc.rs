@@ -38,7 +38,7 @@ pub const ADPS1: u8 = 1 << 1;  // ADC Prescaler Select Bit 1
 pub const ADPS0: u8 = 1 << 0;  // ADC Prescaler Select Bit 0
 
 // ADMUX bits
-// pub const REFS1: u8 = 1 << 7;  // Reference Selection Bit 1
+pub const REFS1: u8 = 1 << 7;  // Reference Selection Bit 1
 pub const REFS0: u8 = 1 << 6;  // Reference Selection Bit 0
 // pub const ADLAR: u8 = 1 << 5;  // ADC Left Adjust Result
 // pub const MUX3: u8 = 1 << 3;   // Analog Channel Selection Bit 3
@@ -48,11 +48,40 @@ pub const REFS0: u8 = 1 << 6;  // Reference Selection Bit 0
 
 // ADC channel constants
 pub const ADC0: u8 = 0;  // For pH sensor (Po)
+#[cfg(feature = "light-sensor")]
+pub const ADC1: u8 = 1;  // For the ambient light sensor (LDR divider)
+pub const BANDGAP: u8 = 0x0E; // Internal 1.1V bandgap reference (MUX3..0 = 1110)
+
+// ADC voltage reference options
+#[allow(dead_code)]
+#[derive(PartialEq, Copy, Clone)]
+pub enum Reference {
+    Avcc,        // AVCC with external capacitor at AREF pin
+    Internal1V1, // Internal 1.1V reference with external capacitor at AREF pin
+    External,    // External voltage applied to the AREF pin
+}
+
+// Reference used for all readings. Only switch to External when a reference
+// is actually wired to AREF, otherwise it is shorted against AVCC.
+pub const REFERENCE: Reference = Reference::Avcc;
+
+// Voltage on the AREF pin when using the external reference (mV)
+pub const EXTERNAL_AREF_MV: u32 = 4096;
+
+// Bandgap voltage (mV). Nominally 1100 but varies 1.0-1.2V between chips;
+// measure it on AREF with the internal reference selected and adjust here.
+pub const BANDGAP_MV: u32 = 1100;
+
+// Supply voltage the pH calibration constants were taken at (mV)
+pub const NOMINAL_SUPPLY_MV: u32 = 5000;
+
+// Full-scale ADC count
+const ADC_MAX: u32 = 1023;
 
 // Initialize the ADC
 pub fn initialize() {
-    // Set reference voltage to AVCC with external capacitor at AREF pin
-    ADMUX::write(REFS0); 
+    // Set the configured reference voltage
+    ADMUX::write(reference_bits(REFERENCE));
     
     // Enable ADC and set prescaler to 128 (16MHz/128 = 125KHz)
     // ADC requires an input clock frequency between 50KHz and 200KHz for maximum resolution
@@ -61,9 +90,9 @@ pub fn initialize() {
 
 // Read from the specified ADC channel
 pub fn read(channel: u8) -> u16 {
-    // Select ADC channel with safety mask (0x07 ensures we only affect the MUX bits)
+    // Select ADC channel with safety mask (0x0F ensures we only affect the MUX bits)
     let admux = ADMUX::read() & 0xF0; // Clear the lower 4 bits for channel selection
-    ADMUX::write(admux | (channel & 0x07));
+    ADMUX::write(admux | (channel & 0x0F));
     
     // Start ADC conversion
     let adcsra = ADCSRA::read();
@@ -79,3 +108,47 @@ pub fn read(channel: u8) -> u16 {
     // Combine the two bytes
     ((high as u16) << 8) | (low as u16)
 }
+
+// REFS1..0 bits for a reference selection
+fn reference_bits(reference: Reference) -> u8 {
+    match reference {
+        Reference::Avcc => REFS0,
+        Reference::Internal1V1 => REFS1 | REFS0,
+        Reference::External => 0,
+    }
+}
+
+// Measure the supply voltage (AVCC) in millivolts
+//
+// The 1.1V bandgap is read against AVCC, so the count shrinks as the supply
+// rises: AVCC = BANDGAP_MV * 1023 / count. Only meaningful with the AVCC
+// reference; with the other references the nominal supply is returned.
+pub fn measure_supply_mv() -> u16 {
+    if REFERENCE != Reference::Avcc {
+        return NOMINAL_SUPPLY_MV as u16;
+    }
+
+    // The bandgap needs time to settle after the multiplexer switches to it,
+    // so discard the first conversion
+    read(BANDGAP);
+    ruduino::delay::delay_us(250);
+    let bandgap = read(BANDGAP) as u32;
+
+    if bandgap == 0 {
+        return NOMINAL_SUPPLY_MV as u16;
+    }
+
+    ((BANDGAP_MV * ADC_MAX) / bandgap) as u16
+}
+
+// Scale a raw reading to what it would have been with a reference of exactly
+// NOMINAL_SUPPLY_MV, so calibration constants stay valid when the supply drifts
+pub fn compensate(raw: u16, supply_mv: u16) -> u16 {
+    let reference_mv = match REFERENCE {
+        Reference::Avcc => supply_mv as u32,
+        Reference::Internal1V1 => BANDGAP_MV,
+        Reference::External => EXTERNAL_AREF_MV,
+    };
+
+    ((raw as u32 * reference_mv) / NOMINAL_SUPPLY_MV) as u16
+}
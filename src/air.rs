@@ -1,14 +1,65 @@
-use ruduino::Pin;
-use ruduino::cores::current::port::D5;
+use crate::actuators::{self, Output};
 
-pub fn initialize() {
-    D5::set_output();
+// Default aeration schedule
+pub const AERATION_PERIOD_MS: u64 = 600_000;  // Start aeration every 10 minutes
+pub const AERATION_DURATION_MS: u64 = 30_000; // Bubble for 30 seconds each time
+
+// Schedule in use, changed from the menu and restored from the settings
+static mut PERIOD_MS: u64 = AERATION_PERIOD_MS;
+static mut DURATION_MS: u64 = AERATION_DURATION_MS;
+
+// Added to the uptime to place it in the schedule, so a restored checkpoint
+// carries on mid-cycle
+static mut PHASE_OFFSET_MS: u64 = 0;
+
+// Change the schedule; the duration is limited to the period
+pub fn set_schedule(period_ms: u64, duration_ms: u64) {
+    unsafe {
+        PERIOD_MS = period_ms;
+        DURATION_MS = if duration_ms > period_ms { period_ms } else { duration_ms };
+    }
+}
+
+// Time between the start of two aeration runs (ms)
+pub fn period_ms() -> u64 {
+    unsafe { PERIOD_MS }
+}
+
+// Length of each aeration run (ms)
+pub fn duration_ms() -> u64 {
+    unsafe { DURATION_MS }
+}
+
+// Time since the current aeration cycle started (ms)
+pub fn phase(current_time: u64) -> u64 {
+    (current_time + unsafe { PHASE_OFFSET_MS }) % period_ms()
+}
+
+// Carry on the schedule from a saved phase
+pub fn set_phase(phase_ms: u64, current_time: u64) {
+    let period = period_ms();
+    unsafe {
+        PHASE_OFFSET_MS = (phase_ms % period + period - current_time % period) % period;
+    }
+}
+
+// Should the bubbles be on at this time?
+pub fn is_scheduled(current_time: u64) -> bool {
+    phase(current_time) < duration_ms()
 }
 
-pub fn activate_bubbles() {
-    D5::set_high();
+// Ask for the pump according to the schedule (the actuator layer has the
+// last word)
+pub fn update(current_time: u64) {
+    actuators::request(Output::AirPump, is_scheduled(current_time));
 }
 
-pub fn deactivate_bubbles() {
-    D5::set_low();
+// Time until the pump next switches on or off (ms)
+pub fn time_until_change(current_time: u64) -> u64 {
+    let phase = phase(current_time);
+    if phase < duration_ms() {
+        duration_ms() - phase
+    } else {
+        period_ms() - phase
+    }
 }
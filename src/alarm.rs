@@ -0,0 +1,97 @@
+use crate::ph::ElectrodeHealth;
+use crate::sensor_manager::SensorValues;
+
+// Alarm conditions, in order of priority
+#[derive(PartialEq, Copy, Clone)]
+pub enum Alarm {
+    None,
+    TemperatureSensor, // DS18B20 not responding
+    ElectrodeSlope,    // pH electrode slope too low
+    ElectrodeOffset,   // pH electrode offset too large
+    ElectrodeResponse, // pH electrode too slow to settle
+}
+
+impl Alarm {
+    // Numeric code shown on the display and sent over serial (0 = no alarm)
+    pub fn code(self) -> u8 {
+        match self {
+            Alarm::None => 0,
+            Alarm::TemperatureSensor => 1,
+            Alarm::ElectrodeSlope => 2,
+            Alarm::ElectrodeOffset => 3,
+            Alarm::ElectrodeResponse => 4,
+        }
+    }
+    
+    // The alarm with the given code, None for an unknown one
+    pub fn from_code(code: u8) -> Alarm {
+        match code {
+            1 => Alarm::TemperatureSensor,
+            2 => Alarm::ElectrodeSlope,
+            3 => Alarm::ElectrodeOffset,
+            4 => Alarm::ElectrodeResponse,
+            _ => Alarm::None,
+        }
+    }
+    
+    // Message shown on the display, starting with the code
+    pub fn message(self) -> &'static str {
+        match self {
+            Alarm::None => "none",
+            Alarm::TemperatureSensor => "1 temp probe",
+            Alarm::ElectrodeSlope => "2 low slope",
+            Alarm::ElectrodeOffset => "3 pH offset",
+            Alarm::ElectrodeResponse => "4 slow pH",
+        }
+    }
+}
+
+// Alarm acknowledged from the menu; it stays quiet until the condition
+// changes, so a cleared and returning fault is announced again
+static mut ACKNOWLEDGED: Alarm = Alarm::None;
+
+// The highest priority alarm condition for the current sensor values,
+// whether or not it has been acknowledged
+pub fn condition(values: &SensorValues) -> Alarm {
+    if values.temperature_fault {
+        return Alarm::TemperatureSensor;
+    }
+    
+    match values.electrode_health {
+        ElectrodeHealth::Ok => Alarm::None,
+        ElectrodeHealth::LowSlope => Alarm::ElectrodeSlope,
+        ElectrodeHealth::Offset => Alarm::ElectrodeOffset,
+        ElectrodeHealth::SlowResponse => Alarm::ElectrodeResponse,
+    }
+}
+
+// The alarm to announce: the current condition unless it was acknowledged
+pub fn active(values: &SensorValues) -> Alarm {
+    let alarm = condition(values);
+    unsafe {
+        if alarm != ACKNOWLEDGED {
+            ACKNOWLEDGED = Alarm::None; // Condition changed, forget the acknowledgement
+            return alarm;
+        }
+    }
+    Alarm::None
+}
+
+// Silence the current alarm condition
+pub fn acknowledge(values: &SensorValues) {
+    unsafe {
+        ACKNOWLEDGED = condition(values);
+    }
+}
+
+// The acknowledged alarm, for the checkpoint
+pub fn acknowledged() -> Alarm {
+    unsafe { ACKNOWLEDGED }
+}
+
+// Keep an acknowledgement from before a reset
+pub fn restore_acknowledged(alarm: Alarm) {
+    unsafe {
+        ACKNOWLEDGED = alarm;
+    }
+}
@@ -0,0 +1,173 @@
+use ruduino::Pin;
+use ruduino::cores::current::port::D6;
+#[cfg(not(feature = "rotary-encoder"))]
+use ruduino::cores::current::port::{C2, D7};
+#[cfg(feature = "rotary-encoder")]
+use crate::encoder::Encoder;
+
+// Push buttons for the local menu, each wired between its pin and ground
+// (the internal pull-ups are used, so a pressed button reads low). With the
+// rotary-encoder feature, turning the encoder replaces UP and DOWN.
+pub type MenuPin = D6; // Open the menu / select, hold to leave
+#[cfg(not(feature = "rotary-encoder"))]
+pub type UpPin = D7;   // Next item / increase
+#[cfg(not(feature = "rotary-encoder"))]
+pub type DownPin = C2; // Previous item / decrease (A2)
+
+// Timing
+const DEBOUNCE_MS: u64 = 20;          // Input must be steady this long
+const HOLD_MS: u64 = 1000;            // Press length that counts as held
+const REPEAT_DELAY_MS: u64 = 500;     // Up/Down start repeating after this
+const REPEAT_INTERVAL_MS: u64 = 150;  // Time between repeats
+
+// What the user did
+#[derive(PartialEq, Copy, Clone)]
+pub enum Event {
+    Menu,     // Menu pressed and released
+    MenuHeld, // Menu held for HOLD_MS
+    #[cfg(not(feature = "rotary-encoder"))]
+    Up,       // Up pressed (repeats while held)
+    #[cfg(not(feature = "rotary-encoder"))]
+    Down,     // Down pressed (repeats while held)
+    #[cfg(feature = "rotary-encoder")]
+    Turn(i16), // Encoder turned by this many (accelerated) clicks
+}
+
+// Debounced state changes of one button
+#[derive(PartialEq, Copy, Clone)]
+enum Change {
+    None,
+    Pressed,
+    Released, // After a press shorter than HOLD_MS
+    Held,     // Once, HOLD_MS after the press
+    Repeated, // Every REPEAT_INTERVAL_MS after REPEAT_DELAY_MS
+}
+
+struct Button {
+    reading: bool,     // Last raw reading (true = pressed)
+    reading_time: u64, // When the raw reading last changed
+    pressed: bool,     // Debounced state
+    pressed_time: u64,
+    held: bool,
+    next_repeat_time: u64,
+}
+
+impl Button {
+    fn new() -> Self {
+        Button {
+            reading: false,
+            reading_time: 0,
+            pressed: false,
+            pressed_time: 0,
+            held: false,
+            next_repeat_time: 0,
+        }
+    }
+    
+    fn update(&mut self, reading: bool, current_time: u64) -> Change {
+        if reading != self.reading {
+            self.reading = reading;
+            self.reading_time = current_time;
+        }
+        
+        if self.reading != self.pressed && current_time >= self.reading_time + DEBOUNCE_MS {
+            self.pressed = self.reading;
+            if self.pressed {
+                self.pressed_time = current_time;
+                self.held = false;
+                self.next_repeat_time = current_time + REPEAT_DELAY_MS;
+                return Change::Pressed;
+            }
+            return if self.held { Change::None } else { Change::Released };
+        }
+        
+        if self.pressed {
+            if !self.held && current_time >= self.pressed_time + HOLD_MS {
+                self.held = true;
+                return Change::Held;
+            }
+            if current_time >= self.next_repeat_time {
+                self.next_repeat_time = current_time + REPEAT_INTERVAL_MS;
+                return Change::Repeated;
+            }
+        }
+        
+        Change::None
+    }
+}
+
+pub struct Buttons {
+    menu: Button,
+    #[cfg(not(feature = "rotary-encoder"))]
+    up: Button,
+    #[cfg(not(feature = "rotary-encoder"))]
+    down: Button,
+    #[cfg(feature = "rotary-encoder")]
+    encoder: Encoder,
+}
+
+impl Buttons {
+    pub fn new() -> Self {
+        Buttons {
+            menu: Button::new(),
+            #[cfg(not(feature = "rotary-encoder"))]
+            up: Button::new(),
+            #[cfg(not(feature = "rotary-encoder"))]
+            down: Button::new(),
+            #[cfg(feature = "rotary-encoder")]
+            encoder: Encoder::new(),
+        }
+    }
+    
+    // Configure the pins as inputs with pull-ups
+    pub fn initialize(&self) {
+        MenuPin::set_input();
+        MenuPin::set_high();
+        
+        #[cfg(not(feature = "rotary-encoder"))]
+        {
+            UpPin::set_input();
+            UpPin::set_high();
+            DownPin::set_input();
+            DownPin::set_high();
+        }
+        
+        #[cfg(feature = "rotary-encoder")]
+        self.encoder.initialize();
+    }
+    
+    // Sample the buttons, call once per main loop iteration
+    pub fn poll(&mut self, current_time: u64) -> Option<Event> {
+        // Update all of them every time so none misses its debounce timing
+        let menu = self.menu.update(!MenuPin::is_high(), current_time);
+        #[cfg(not(feature = "rotary-encoder"))]
+        let up = self.up.update(!UpPin::is_high(), current_time);
+        #[cfg(not(feature = "rotary-encoder"))]
+        let down = self.down.update(!DownPin::is_high(), current_time);
+        match menu {
+            Change::Released => return Some(Event::Menu),
+            Change::Held => return Some(Event::MenuHeld),
+            _ => {}
+        }
+        
+        #[cfg(not(feature = "rotary-encoder"))]
+        {
+            if up == Change::Pressed || up == Change::Repeated {
+                return Some(Event::Up);
+            }
+            if down == Change::Pressed || down == Change::Repeated {
+                return Some(Event::Down);
+            }
+        }
+        
+        #[cfg(feature = "rotary-encoder")]
+        {
+            let clicks = self.encoder.poll(current_time);
+            if clicks != 0 {
+                return Some(Event::Turn(clicks));
+            }
+        }
+        
+        None
+    }
+}
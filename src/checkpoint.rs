@@ -0,0 +1,132 @@
+use crate::actuators;
+use crate::air;
+use crate::alarm::{self, Alarm};
+use crate::eeprom;
+use crate::record_ring::{self, RecordRing};
+use crate::sensor_manager::SensorValues;
+use crate::statistics::TOTALS_SIZE;
+
+// State checkpoints in EEPROM
+//
+// So a power cut (or a brownout or watchdog reset) does not restart the
+// aeration cycle or lose the statistics, the state is saved every
+// INTERVAL_MS to a record_ring and restored at boot:
+//
+//   0-3    time into the aeration cycle (ms)
+//   4      acknowledged alarm code
+//   5-14   temperature statistics since reset (see statistics::TOTALS_SIZE)
+//   15-24  pH statistics since reset
+//
+// The rolling 1 h and 24 h windows do not fit and start over. How long the
+// power was off is unknown, so the aeration cycle resumes where it stopped.
+// An acknowledged alarm stays acknowledged if it is still present after the
+// first measurement cycle.
+//
+// When the supply starts to sag one checkpoint is taken straight away, in
+// case the power is going. Those early checkpoints are earned, one per
+// EARLY_CREDIT_MS of uptime with at most EARLY_CREDIT_MAX saved up, so a
+// flickering supply or a reset loop cannot wear out the EEPROM.
+//
+// Wear: the ring has 6 slots and writes each cell at most once per record,
+// so regular checkpoints write a cell every 3 hours, under 30,000 times in
+// ten years. With every early checkpoint used as well it is every 2 hours,
+// under 44,000 times, within the rated 100,000.
+
+const INTERVAL_MS: u64 = 1_800_000;     // 30 minutes
+const EARLY_CREDIT_MS: u64 = 3_600_000; // 1 hour
+const EARLY_CREDIT_MAX: u8 = 2;
+
+const DATA_SIZE: usize = 5 + 2 * TOTALS_SIZE;
+const RECORD_SIZE: usize = DATA_SIZE + record_ring::OVERHEAD;
+
+pub struct Checkpoint {
+    last_save: u64,
+    supply_low: bool,            // The supply was low at the last update
+    early_credit: u8,            // Early checkpoints that may be taken
+    credit_time: u64,            // When the last one was earned
+    resumed: bool,               // Restored at boot
+    acknowledged: Option<Alarm>, // Restored once the sensors have been read
+    ring: RecordRing<RECORD_SIZE>,
+}
+
+impl Checkpoint {
+    pub fn new() -> Self {
+        Checkpoint {
+            last_save: 0,
+            supply_low: false,
+            early_credit: 0,
+            credit_time: 0,
+            resumed: false,
+            acknowledged: None,
+            ring: RecordRing::new(eeprom::CHECKPOINT_ADDRESS, eeprom::CHECKPOINT_SIZE),
+        }
+    }
+    
+    // Find the newest checkpoint and carry on from it. Call at boot, after
+    // the aeration schedule has been set.
+    pub fn restore(&mut self, values: &mut SensorValues) {
+        let mut record = [0u8; DATA_SIZE];
+        if !self.ring.initialize() || !self.ring.read(0, &mut record) {
+            return;
+        }
+        
+        let phase = u32::from_le_bytes([record[0], record[1], record[2], record[3]]);
+        air::set_phase(phase as u64, 0);
+        self.acknowledged = Some(Alarm::from_code(record[4]));
+        values.temperature_statistics.restore_totals(&record[5..5 + TOTALS_SIZE]);
+        values.ph_statistics.restore_totals(&record[5 + TOTALS_SIZE..]);
+        self.resumed = true;
+    }
+    
+    // Was a checkpoint restored at boot?
+    pub fn resumed(&self) -> bool {
+        self.resumed
+    }
+    
+    // Save when one is due and write checkpoints in the background
+    pub fn update(&mut self, values: &SensorValues, current_time: u64) {
+        // The alarm condition is known after the first measurement cycle
+        if values.cycles > 0 {
+            if let Some(alarm) = self.acknowledged.take() {
+                if alarm != Alarm::None && alarm == alarm::condition(values) {
+                    alarm::restore_acknowledged(alarm);
+                }
+            }
+        }
+        
+        if self.ring.is_writing() {
+            self.ring.update();
+            return;
+        }
+        
+        while current_time >= self.credit_time + EARLY_CREDIT_MS {
+            self.credit_time += EARLY_CREDIT_MS;
+            self.early_credit = (self.early_credit + 1).min(EARLY_CREDIT_MAX);
+        }
+        
+        // Once per sag of the supply, in case the power is going
+        let mut early = false;
+        if actuators::supply_low() != self.supply_low {
+            self.supply_low = !self.supply_low;
+            early = self.supply_low && self.early_credit > 0;
+        }
+        
+        if early || current_time >= self.last_save + INTERVAL_MS {
+            if early {
+                self.early_credit -= 1;
+            }
+            self.start_record(values, current_time);
+            self.last_save = current_time;
+        }
+    }
+    
+    // Encode the state and queue it for writing
+    fn start_record(&mut self, values: &SensorValues, current_time: u64) {
+        let mut record = [0u8; DATA_SIZE];
+        record[0..4].copy_from_slice(&(air::phase(current_time) as u32).to_le_bytes());
+        record[4] = alarm::acknowledged().code();
+        record[5..5 + TOTALS_SIZE].copy_from_slice(&values.temperature_statistics.save_totals());
+        record[5 + TOTALS_SIZE..].copy_from_slice(&values.ph_statistics.save_totals());
+        self.ring.start(&record);
+    }
+}
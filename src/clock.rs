@@ -0,0 +1,26 @@
+// Wall clock time, set by a host
+//
+// There is no real-time clock, so the time a host sets (with the clock
+// command or a SET_CLOCK frame) is carried forward with the millisecond
+// timer. A reset leaves the clock unset until a host sets it again.
+
+// Unix time when set, and the uptime it was set at
+static mut SET_TO_S: u32 = 0;
+static mut SET_AT_MS: u64 = 0;
+
+// Set the clock to a Unix time in seconds (0 unsets it)
+pub fn set(unix_time: u32, current_time: u64) {
+    unsafe {
+        SET_TO_S = unix_time;
+        SET_AT_MS = current_time;
+    }
+}
+
+// The current Unix time, once a host has set the clock
+pub fn now(current_time: u64) -> Option<u32> {
+    let (set_to, set_at) = unsafe { (SET_TO_S, SET_AT_MS) };
+    if set_to == 0 {
+        return None;
+    }
+    Some(set_to + ((current_time - set_at) / 1000) as u32)
+}
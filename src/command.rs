@@ -0,0 +1,199 @@
+use crate::uart;
+use crate::history;
+use crate::config::ConfigKey;
+use crate::ph::Calibration;
+use crate::protocol::{self, Request, Status};
+use crate::settings::{self, Text};
+
+// Serial command interface
+//
+// Commands are lines of text terminated by CR or LF: a name followed by
+// space separated arguments, e.g. "brightness 3". Every line is answered
+// with "ok" or "error" so a host script can tell whether it was applied.
+// Binary frames (see protocol) arrive on the same port and are told apart
+// by their zero delimiters.
+
+// Longest accepted command line, enough for "post-host" and a full host
+const LINE_SIZE: usize = 36;
+
+// Commands understood over serial
+pub enum Command {
+    Brightness(u8),       // brightness <1-8>
+    #[cfg(feature = "light-sensor")]
+    AutoBrightness,       // brightness auto
+    History,              // history
+    HistoryInterval(u16), // history-interval <minutes>
+    Statistics,           // stats
+    ResetStatistics,      // stats reset
+    Endpoint,             // post
+    SetText(Text, [u8; settings::TEXT_SIZE], u8), // post-host <host>, post-path <path>
+    GetReadings(Request),          // GET_READINGS frame, answered with the readings
+    GetConfig(Request, ConfigKey), // GET_CONFIG frame, answered with the value
+    SetConfig(ConfigKey, u16),     // SET_CONFIG frame
+    SetClock(u32),                 // clock <Unix time>, or a SET_CLOCK frame
+    GetClock(Request),             // GET_CLOCK frame, answered with the time
+    SetCalibration(Calibration),   // SET_CALIBRATION frame
+}
+
+// Where a command came from, so it is answered the same way
+#[derive(Copy, Clone)]
+pub enum Source {
+    Text,
+    Frame(Request),
+}
+
+// Collects received bytes into lines or frames and parses them
+pub struct CommandReader {
+    line: [u8; LINE_SIZE],
+    length: usize,
+    overflow: bool, // Current line was too long and will be rejected
+    frame: bool,    // Receiving a binary frame rather than a line
+}
+
+impl CommandReader {
+    pub fn new() -> Self {
+        CommandReader {
+            line: [0; LINE_SIZE],
+            length: 0,
+            overflow: false,
+            frame: false,
+        }
+    }
+    
+    // Process received bytes, returning a command once a valid line or frame
+    // is complete. Invalid lines and frames are answered here; the caller
+    // answers valid commands with `reply` once it has applied them.
+    pub fn poll(&mut self) -> Option<(Command, Source)> {
+        while let Some(byte) = uart::read_byte() {
+            match byte {
+                0 if self.frame && self.length > 0 => {
+                    // Closing delimiter
+                    let received = if self.overflow {
+                        None
+                    } else {
+                        protocol::receive(&mut self.line[..self.length])
+                    };
+                    self.frame = false;
+                    self.length = 0;
+                    self.overflow = false;
+                    
+                    // Corrupted frames get no answer; the host retries
+                    if let Some((request, result)) = received {
+                        protocol::set_active(true);
+                        match result {
+                            Ok(command) => return Some((command, Source::Frame(request))),
+                            Err(status) => protocol::send_ack(request, status),
+                        }
+                    }
+                },
+                0 => {
+                    // Opening delimiter, dropping any partial line
+                    self.frame = true;
+                    self.length = 0;
+                    self.overflow = false;
+                },
+                b'\r' | b'\n' if !self.frame => {
+                    if self.length == 0 && !self.overflow {
+                        continue; // Blank line or the LF of a CRLF
+                    }
+                    
+                    let command = if self.overflow {
+                        None
+                    } else {
+                        parse(&self.line[..self.length])
+                    };
+                    self.length = 0;
+                    self.overflow = false;
+                    protocol::set_active(false);
+                    
+                    match command {
+                        Some(command) => return Some((command, Source::Text)),
+                        None => reply_error(),
+                    }
+                },
+                _ => {
+                    if self.length < LINE_SIZE {
+                        self.line[self.length] = byte;
+                        self.length += 1;
+                    } else {
+                        self.overflow = true;
+                    }
+                }
+            }
+        }
+        
+        None
+    }
+}
+
+// Parse a command line
+fn parse(line: &[u8]) -> Option<Command> {
+    let line = core::str::from_utf8(line).ok()?;
+    let mut words = line.split_whitespace();
+    let name = words.next()?;
+    let argument = words.next();
+    
+    // No command takes more than one argument
+    if words.next().is_some() {
+        return None;
+    }
+    
+    match (name, argument) {
+        #[cfg(feature = "light-sensor")]
+        ("brightness", Some("auto")) => Some(Command::AutoBrightness),
+        ("brightness", Some(level)) => {
+            let level = level.parse::<u8>().ok()?;
+            if level >= 1 && level <= crate::display::MAX_BRIGHTNESS {
+                Some(Command::Brightness(level))
+            } else {
+                None
+            }
+        },
+        ("history", None) => Some(Command::History),
+        ("stats", None) => Some(Command::Statistics),
+        ("stats", Some("reset")) => Some(Command::ResetStatistics),
+        ("clock", Some(seconds)) => Some(Command::SetClock(seconds.parse::<u32>().ok()?)),
+        ("history-interval", Some(minutes)) => {
+            let minutes = minutes.parse::<u16>().ok()?;
+            if minutes >= history::MIN_INTERVAL_MIN && minutes <= history::MAX_INTERVAL_MIN {
+                Some(Command::HistoryInterval(minutes))
+            } else {
+                None
+            }
+        },
+        ("post", None) => Some(Command::Endpoint),
+        ("post-host", Some(host)) => text_setting(Text::PostHost, host),
+        ("post-path", Some(path)) => text_setting(Text::PostPath, path),
+        ("post-port", Some("default")) => Some(Command::SetConfig(ConfigKey::PostPort, 0)),
+        ("post-port", Some(port)) => Some(Command::SetConfig(ConfigKey::PostPort, port.parse::<u16>().ok()?)),
+        _ => None,
+    }
+}
+
+// Change a text setting, or clear it with "default"
+fn text_setting(text: Text, value: &str) -> Option<Command> {
+    let value = if value == "default" { "" } else { value };
+    if !text.accepts(value.as_bytes()) {
+        return None;
+    }
+    let mut bytes = [0; settings::TEXT_SIZE];
+    bytes[..value.len()].copy_from_slice(value.as_bytes());
+    Some(Command::SetText(text, bytes, value.len() as u8))
+}
+
+// Answer a command, as "ok"/"error" or an ACK frame
+pub fn reply(source: Source, accepted: bool) {
+    match source {
+        Source::Text if accepted => uart::send_string("ok\r\n"),
+        Source::Text => reply_error(),
+        Source::Frame(request) => {
+            let status = if accepted { Status::Ok } else { Status::Rejected };
+            protocol::send_ack(request, status);
+        },
+    }
+}
+
+// Reject a text command
+fn reply_error() {
+    uart::send_string("error\r\n");
+}
\ No newline at end of file
@@ -0,0 +1,175 @@
+use crate::display;
+use crate::history::{self, History};
+use crate::menu;
+use crate::ph::Calibration;
+use crate::sensor_manager::SensorManager;
+use crate::settings::{self, Settings};
+use crate::Display;
+
+// Settings a host can read and change remotely, numbered the same for the
+// binary protocol (key) and Modbus (holding register = key - 1)
+
+// Calibration limits: the 10-bit ADC, and pH 14 (* 100)
+const MAX_ADC: u16 = 1023;
+const MAX_PH: u16 = 1400;
+
+#[derive(Copy, Clone)]
+pub enum ConfigKey {
+    Brightness = 1,       // 1 to display::MAX_BRIGHTNESS, 0 = automatic
+    AerationPeriod = 2,   // Minutes
+    AerationDuration = 3, // Seconds
+    HistoryInterval = 4,  // Minutes
+    CalibrationAdc1 = 5,  // Calibration points (see ph::Calibration)
+    CalibrationPh1 = 6,
+    CalibrationAdc2 = 7,
+    CalibrationPh2 = 8,
+    ModbusAddress = 9,    // Used with the modbus feature
+    PostPort = 10,        // Used with the wifi feature, 0 = the build's default
+}
+
+impl ConfigKey {
+    // The calibration keys, reported together when the menu recalibrates
+    pub const CALIBRATION: [ConfigKey; 4] = [
+        ConfigKey::CalibrationAdc1,
+        ConfigKey::CalibrationPh1,
+        ConfigKey::CalibrationAdc2,
+        ConfigKey::CalibrationPh2,
+    ];
+    
+    pub fn from_u8(key: u8) -> Option<ConfigKey> {
+        match key {
+            1 => Some(ConfigKey::Brightness),
+            2 => Some(ConfigKey::AerationPeriod),
+            3 => Some(ConfigKey::AerationDuration),
+            4 => Some(ConfigKey::HistoryInterval),
+            5 => Some(ConfigKey::CalibrationAdc1),
+            6 => Some(ConfigKey::CalibrationPh1),
+            7 => Some(ConfigKey::CalibrationAdc2),
+            8 => Some(ConfigKey::CalibrationPh2),
+            9 => Some(ConfigKey::ModbusAddress),
+            10 => Some(ConfigKey::PostPort),
+            _ => None,
+        }
+    }
+}
+
+// Current value of a setting
+pub fn get(key: ConfigKey, display_controller: &Display, settings: &Settings) -> u16 {
+    match key {
+        ConfigKey::Brightness => display_controller.brightness() as u16,
+        ConfigKey::AerationPeriod => settings.aeration_period_min,
+        ConfigKey::AerationDuration => settings.aeration_duration_s,
+        ConfigKey::HistoryInterval => settings.history_interval_min,
+        ConfigKey::CalibrationAdc1 => settings.calibration.adc_1,
+        ConfigKey::CalibrationPh1 => settings.calibration.ph_1,
+        ConfigKey::CalibrationAdc2 => settings.calibration.adc_2,
+        ConfigKey::CalibrationPh2 => settings.calibration.ph_2,
+        ConfigKey::ModbusAddress => settings.modbus_address,
+        ConfigKey::PostPort => settings.post_port,
+    }
+}
+
+// Change a setting and save it. Returns false, changing nothing, if the
+// value is out of range.
+pub fn set(
+    key: ConfigKey,
+    value: u16,
+    display_controller: &mut Display,
+    sensor_manager: &mut SensorManager,
+    history: &mut History,
+    settings: &mut Settings,
+    current_time: u64,
+) -> bool {
+    match key {
+        ConfigKey::Brightness => {
+            // Not kept in EEPROM, like the brightness command
+            #[cfg(feature = "light-sensor")]
+            {
+                if value == 0 {
+                    display_controller.set_auto_brightness();
+                    return true;
+                }
+            }
+            if !(1..=display::MAX_BRIGHTNESS as u16).contains(&value) {
+                return false;
+            }
+            display_controller.set_brightness(value as u8);
+            return true;
+        },
+        ConfigKey::AerationPeriod => {
+            if !(menu::MIN_PERIOD_MIN..=menu::MAX_PERIOD_MIN).contains(&value) {
+                return false;
+            }
+            settings.aeration_period_min = value;
+            // A shorter period also shortens the duration
+            settings.aeration_duration_s = settings.aeration_duration_s.min(value * 60);
+            settings::apply_aeration(settings);
+        },
+        ConfigKey::AerationDuration => {
+            if value > menu::MAX_DURATION_S.min(settings.aeration_period_min * 60) {
+                return false;
+            }
+            settings.aeration_duration_s = value;
+            settings::apply_aeration(settings);
+        },
+        ConfigKey::HistoryInterval => {
+            if !(history::MIN_INTERVAL_MIN..=history::MAX_INTERVAL_MIN).contains(&value) {
+                return false;
+            }
+            settings.history_interval_min = value;
+            history.set_interval(value, current_time);
+        },
+        ConfigKey::CalibrationAdc1
+        | ConfigKey::CalibrationPh1
+        | ConfigKey::CalibrationAdc2
+        | ConfigKey::CalibrationPh2 => {
+            // One point at a time, so each step must leave a usable line
+            let mut calibration = settings.calibration;
+            return set_calibration_point(&mut calibration, key, value)
+                && set_calibration(calibration, sensor_manager, settings);
+        },
+        ConfigKey::ModbusAddress => {
+            if !(1..=settings::MAX_MODBUS_ADDRESS).contains(&value) {
+                return false;
+            }
+            settings.modbus_address = value;
+        },
+        ConfigKey::PostPort => settings.post_port = value,
+    }
+    settings::save(settings);
+    true
+}
+
+// Change one value of a calibration, to be checked by set_calibration.
+// Returns false for a key that is not a calibration point.
+pub fn set_calibration_point(calibration: &mut Calibration, key: ConfigKey, value: u16) -> bool {
+    match key {
+        ConfigKey::CalibrationAdc1 => calibration.adc_1 = value,
+        ConfigKey::CalibrationPh1 => calibration.ph_1 = value,
+        ConfigKey::CalibrationAdc2 => calibration.adc_2 = value,
+        ConfigKey::CalibrationPh2 => calibration.ph_2 = value,
+        _ => return false,
+    }
+    true
+}
+
+// Use and save a calibration, unless a reading or pH is out of range or
+// the line would be unusable
+pub fn set_calibration(
+    calibration: Calibration,
+    sensor_manager: &mut SensorManager,
+    settings: &mut Settings,
+) -> bool {
+    if calibration.adc_1 > MAX_ADC
+        || calibration.adc_2 > MAX_ADC
+        || calibration.ph_1 > MAX_PH
+        || calibration.ph_2 > MAX_PH
+        || !calibration.is_valid()
+    {
+        return false;
+    }
+    settings.calibration = calibration;
+    sensor_manager.set_calibration(calibration);
+    settings::save(settings);
+    true
+}
@@ -0,0 +1,320 @@
+use crate::actuators::{self, Lockout, Output};
+use crate::air;
+use crate::alarm::{self, Alarm};
+use crate::format::Buffer;
+use crate::lcd::{self, Lcd};
+use crate::menu::View;
+use crate::ph::ElectrodeHealth;
+use crate::sensor_manager::SensorValues;
+use crate::watchdog;
+#[cfg(feature = "wifi")]
+use crate::wifi;
+
+// Dashboard on a character LCD
+//
+// Shows every reading at once instead of cycling pages. On a 20x4 LCD:
+//
+//   T 25.3°C*  pH 7.01*
+//   E -12.3mV  S 98.6%ok
+//   Air* on 25s    4.98V
+//   *2 low slope   12.3h
+//
+// The bottom right corner shows the uptime in hours, or the Wi-Fi uplink's
+// state (e.g. "sent") in builds with the uplink.
+//
+// The * are custom icons: a check mark for a settled reading (an hourglass
+// while it is still moving), bubbles while aerating, and a bell in front of
+// an active alarm.
+//
+// A 16x2 LCD gets a compact layout, where an active alarm, or else an uplink
+// problem, takes the place of the aeration status:
+//
+//   25.3°C*  pH7.01*
+//   Air* on 25s
+//
+// It is chosen from lcd::COLUMNS and lcd::ROWS, which have to be changed by
+// hand for a 16x2 LCD.
+
+// Time between dashboard redraws (ms); only changed rows are sent
+const REFRESH_INTERVAL_MS: u64 = 500;
+
+// Ambient light level above which the backlight is switched on in auto mode
+#[cfg(feature = "light-sensor")]
+const BACKLIGHT_LIGHT_THRESHOLD: u16 = 200;
+
+// Custom characters
+const ICON_STABLE: u8 = 0;
+const ICON_SETTLING: u8 = 1;
+const ICON_AIR: u8 = 2;
+const ICON_ALARM: u8 = 3;
+const DEGREE: u8 = 0xDF; // In the HD44780 A00 character ROM
+
+const ICONS: [[u8; 8]; 4] = [
+    [0x00, 0x01, 0x03, 0x16, 0x1C, 0x08, 0x00, 0x00], // Check mark
+    [0x1F, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x1F, 0x00], // Hourglass
+    [0x00, 0x06, 0x09, 0x06, 0x00, 0x0C, 0x12, 0x0C], // Bubbles
+    [0x04, 0x0E, 0x0E, 0x0E, 0x1F, 0x00, 0x04, 0x00], // Bell
+];
+
+// One row of text being composed
+type Line = Buffer<{ lcd::COLUMNS }>;
+
+// Icon for a settled or still moving reading
+fn stability_icon(stable: bool) -> u8 {
+    if stable { ICON_STABLE } else { ICON_SETTLING }
+}
+
+pub struct LcdDashboard {
+    lcd: Lcd,
+    shown: [[u8; lcd::COLUMNS]; lcd::ROWS], // What is on the LCD now
+    last_refresh_time: Option<u64>,
+    brightness: u8, // Last level set, for reading it back
+    #[cfg(feature = "light-sensor")]
+    auto_brightness: bool, // Follow the ambient light sensor
+}
+
+impl LcdDashboard {
+    pub fn new() -> Self {
+        LcdDashboard {
+            lcd: Lcd::new(),
+            shown: [[b' '; lcd::COLUMNS]; lcd::ROWS],
+            last_refresh_time: None,
+            brightness: crate::display::MAX_BRIGHTNESS,
+            #[cfg(feature = "light-sensor")]
+            auto_brightness: false,
+        }
+    }
+    
+    // Initialize the LCD and load the status icons
+    pub fn initialize(&mut self) {
+        self.lcd.initialize();
+        for (code, icon) in ICONS.iter().enumerate() {
+            self.lcd.create_char(code as u8, icon);
+        }
+    }
+    
+    // The backlight can only be switched, so the lowest level turns it off
+    pub fn set_brightness(&mut self, level: u8) {
+        #[cfg(feature = "light-sensor")]
+        {
+            self.auto_brightness = false;
+        }
+        self.brightness = level;
+        self.lcd.set_backlight(level > 1);
+    }
+    
+    // Let the ambient light sensor switch the backlight
+    #[cfg(feature = "light-sensor")]
+    pub fn set_auto_brightness(&mut self) {
+        self.auto_brightness = true;
+    }
+    
+    // The fixed brightness level, or 0 while the light sensor controls it
+    pub fn brightness(&self) -> u8 {
+        #[cfg(feature = "light-sensor")]
+        {
+            if self.auto_brightness {
+                return 0;
+            }
+        }
+        self.brightness
+    }
+    
+    // Redraw the dashboard if it is due
+    pub fn update_display(&mut self, sensor_values: &SensorValues, current_time: u64) {
+        if let Some(last_time) = self.last_refresh_time {
+            if current_time < last_time + REFRESH_INTERVAL_MS {
+                return;
+            }
+        }
+        self.last_refresh_time = Some(current_time);
+        
+        #[cfg(feature = "light-sensor")]
+        {
+            if self.auto_brightness {
+                self.lcd.set_backlight(sensor_values.light_level > BACKLIGHT_LIGHT_THRESHOLD);
+            }
+        }
+        
+        let lines = if lcd::ROWS >= 4 {
+            compose_large(sensor_values, current_time)
+        } else {
+            compose_small(sensor_values, current_time)
+        };
+        
+        self.show(&lines);
+    }
+    
+    // Only send rows that changed, each I2C character takes ~0.4ms
+    fn show(&mut self, lines: &[Line; 4]) {
+        for (row, line) in lines.iter().enumerate().take(lcd::ROWS) {
+            if line.padded() != &self.shown[row] {
+                self.lcd.write_at(0, row, line.padded());
+                self.shown[row] = *line.padded();
+            }
+        }
+    }
+    
+    // Show the menu on the top rows instead of the dashboard
+    pub fn show_menu(&mut self, view: View, _current_time: u64) {
+        let mut lines = [Line::new(), Line::new(), Line::new(), Line::new()];
+        lines[0].push_str("Menu");
+        match view {
+            View::Text(text) => lines[1].push_str(text),
+            View::Edit(value) => {
+                // Arrows mark a value that can be changed
+                lines[1].push_str("< ");
+                lines[1].push_integer(value as u32);
+                lines[1].push_str(" >");
+            },
+            View::Reading(value, stable) => {
+                lines[1].push_str("pH ");
+                lines[1].push_fixed(value, 2);
+                lines[1].push(stability_icon(stable));
+            }
+        }
+        self.show(&lines);
+        
+        // Redraw the dashboard straight after the menu closes
+        self.last_refresh_time = None;
+    }
+    
+    // All values are on screen at once, so there are no pages to step through
+    pub fn next_page(&mut self, _current_time: u64) {}
+    
+    pub fn previous_page(&mut self, _current_time: u64) {}
+    
+    pub fn check_mode_switch(&mut self, _current_time: u64) -> bool {
+        false
+    }
+}
+
+// 20x4 layout
+fn compose_large(values: &SensorValues, current_time: u64) -> [Line; 4] {
+    let mut lines = [Line::new(), Line::new(), Line::new(), Line::new()];
+    
+    lines[0].push_str("T ");
+    lines[0].push_fixed(values.temperature, 1);
+    lines[0].push(DEGREE);
+    lines[0].push(b'C');
+    lines[0].push(stability_icon(values.temperature_stable));
+    lines[0].pad_to(11);
+    lines[0].push_str("pH ");
+    lines[0].push_fixed(values.ph, 2);
+    lines[0].push(stability_icon(values.ph_stable));
+    
+    lines[1].push_str("E ");
+    lines[1].push_fixed(values.ph_mv, 1);
+    lines[1].push_str("mV");
+    lines[1].pad_to(11);
+    lines[1].push_str("S ");
+    lines[1].push_fixed(values.ph_slope, 1);
+    lines[1].push(b'%');
+    lines[1].push_str(electrode_status(values.electrode_health));
+    
+    push_aeration(&mut lines[2], current_time);
+    lines[2].pad_to(15);
+    lines[2].push_fixed(values.supply_voltage, 2);
+    lines[2].push(b'V');
+    
+    push_alarm(&mut lines[3], alarm::active(values));
+    #[cfg(not(feature = "wifi"))]
+    {
+        lines[3].pad_to(15);
+        lines[3].push_fixed(current_time as f32 / 3_600_000.0, 1);
+        lines[3].push(b'h');
+    }
+    #[cfg(feature = "wifi")]
+    {
+        lines[3].pad_to(16);
+        lines[3].push_str(wifi::status().code());
+    }
+    
+    lines
+}
+
+// 16x2 layout
+fn compose_small(values: &SensorValues, current_time: u64) -> [Line; 4] {
+    let mut lines = [Line::new(), Line::new(), Line::new(), Line::new()];
+    
+    lines[0].push_fixed(values.temperature, 1);
+    lines[0].push(DEGREE);
+    lines[0].push(b'C');
+    lines[0].push(stability_icon(values.temperature_stable));
+    lines[0].pad_to(9);
+    lines[0].push_str("pH");
+    lines[0].push_fixed(values.ph, 2);
+    lines[0].push(stability_icon(values.ph_stable));
+    
+    // An active alarm takes the place of the aeration status, and so does
+    // an uplink problem
+    let alarm = alarm::active(values);
+    if alarm != Alarm::None {
+        push_alarm(&mut lines[1], alarm);
+    } else if let Some(problem) = uplink_problem() {
+        lines[1].push_str("WiFi ");
+        lines[1].push_str(problem);
+    } else {
+        push_aeration(&mut lines[1], current_time);
+    }
+    
+    lines
+}
+
+// "Air" with the bubbles icon while running, state and seconds to the next
+// switch, or why the pump is held off (e.g. "Air off temp")
+fn push_aeration(line: &mut Line, current_time: u64) {
+    let active = actuators::is_on(Output::AirPump);
+    line.push_str("Air");
+    line.push(if active { ICON_AIR } else { b' ' });
+    line.push_str(if active { "on " } else { "off" });
+    line.push(b' ');
+    let lockout = actuators::lockout(Output::AirPump);
+    if lockout == Lockout::None {
+        line.push_integer(((air::time_until_change(current_time) + 999) / 1000) as u32);
+        line.push(b's');
+    } else {
+        line.push_str(lockout.code());
+    }
+}
+
+// Bell and alarm message, or "no alarm" (the cause of an unexpected reset
+// instead, e.g. "reset watchdog")
+fn push_alarm(line: &mut Line, alarm: Alarm) {
+    if alarm == Alarm::None {
+        let cause = watchdog::reset_cause();
+        if cause.is_fault() {
+            line.push_str("reset ");
+            line.push_str(cause.message());
+        } else {
+            line.push_str("no alarm");
+        }
+    } else {
+        line.push(ICON_ALARM);
+        line.push_str(alarm.message());
+    }
+}
+
+// What is wrong with the Wi-Fi uplink, if anything
+#[cfg(feature = "wifi")]
+fn uplink_problem() -> Option<&'static str> {
+    let status = wifi::status();
+    if status.is_problem() {
+        Some(status.message())
+    } else {
+        None
+    }
+}
+
+#[cfg(not(feature = "wifi"))]
+fn uplink_problem() -> Option<&'static str> {
+    None
+}
+
+// Short electrode status after the slope
+fn electrode_status(health: ElectrodeHealth) -> &'static str {
+    match health {
+        ElectrodeHealth::Ok => "ok",
+        _ => "!",
+    }
+}
@@ -1,27 +1,41 @@
-use ruduino::Pin;
-use ruduino::cores::current::port::{B0, B1, B2, B3, B4, D3, D4};
-use ruduino::delay;
-use core::sync::atomic::{AtomicBool, Ordering};
+// Only the brightness range is used with the character LCD
+#![cfg_attr(feature = "display-lcd", allow(dead_code))]
+
 use libm::roundf;
-use core::arch::asm;
 
-// Memory addresses for Timer0 registers
-const TCCR0B: *mut u8 = 0x25 as *mut u8;  // Timer/Counter0 Control Register B
-const TIMSK0: *mut u8 = 0x6E as *mut u8;  // Timer/Counter0 Interrupt Mask Register
+// Frame composition for 4-digit 7-segment displays
+//
+// The functions below format numbers and text into a back buffer of segment
+// patterns; `frame()` hands the finished frame to whichever `SegmentDisplay`
+// driver is fitted, so nothing half-written is ever shown.
 
-// Current display buffer and decimal point buffer
-static mut DISPLAY_BUFFER: [u8; 4] = [0, 0, 0, 0];
-static mut DP_BUFFER: [bool; 4] = [false, false, false, false];
-static mut CURRENT_DIGIT: u8 = 0;
+/// A 4-digit 7-segment display driver
+///
+/// Frames are four segment patterns, leftmost digit first, with segment a in
+/// bit 0 through g in bit 6 and the decimal point in bit 7.
+pub trait SegmentDisplay {
+    /// Set up the hardware (and run any power-on test)
+    fn initialize(&mut self);
+    
+    /// Show a complete frame
+    fn show(&mut self, frame: [u8; 4]);
+    
+    /// Set the brightness, from 1 (dimmest) to `MAX_BRIGHTNESS`
+    fn set_brightness(&mut self, level: u8);
+}
+
+// Number of brightness levels offered by every driver
+pub const MAX_BRIGHTNESS: u8 = 8;
 
-// Disable auto-update by default for direct control
-static AUTO_UPDATE_ENABLED: AtomicBool = AtomicBool::new(false);
+// Back buffer (segment patterns and decimal points), all 8's by default
+static mut DISPLAY_BUFFER: [u8; 4] = [0x7f, 0x7f, 0x7f, 0x7f];
+static mut DP_BUFFER: [bool; 4] = [false, false, false, false];
 
 // Constants for the different display patterns to show
-const PATTERN_ALL_ON: u8 = 0xFF;  // All segments on
+pub const PATTERN_ALL_ON: u8 = 0xFF;  // All segments on
 // const PATTERN_TEST: u8 = 0xD7;    // A unique test pattern
 
-// Segment patterns for digits 0-9 and some letters
+// Segment patterns for digits 0-9 and hex letters A-F
 const DIGIT_PATTERNS: [u8; 17] = [
     0x3f, // 0: 0b00111111
     0x06, // 1: 0b00000110
@@ -42,187 +56,199 @@ const DIGIT_PATTERNS: [u8; 17] = [
     0x00  // blank: 0b00000000
 ];
 
-/// Initialize the 7-segment display with auto-update capability
-pub fn initialize() {
-    // Set pins as outputs
-    B1::set_output(); // LATCH_PIN (STCP)
-    B2::set_output(); // CLOCK_PIN (SHCP)
-    B0::set_output(); // DATA_PIN (DS)
-    B3::set_output(); // DIGIT1_PIN
-    B4::set_output(); // DIGIT2_PIN
-    D3::set_output(); // DIGIT3_PIN
-    D4::set_output(); // DIGIT4_PIN
-    
-    // Initialize pins to low
-    B1::set_low(); // LATCH_PIN
-    B2::set_low(); // CLOCK_PIN
-    B0::set_low(); // DATA_PIN
-    
-    // Initialize digit pins to high (inactive for common cathode)
-    B3::set_high(); // DIGIT1_PIN
-    B4::set_high(); // DIGIT2_PIN
-    D3::set_high(); // DIGIT3_PIN
-    D4::set_high(); // DIGIT4_PIN
-    
-    // Add a brief delay after initialization
-    delay::delay_ms(50);
-    
-    // Initialize display buffer
-    unsafe {
-        DISPLAY_BUFFER = [8, 8, 8, 8]; // Display all 8's as default
-        DP_BUFFER = [false, false, false, false];
-        CURRENT_DIGIT = 0;
-        
-        // Enable Timer0 overflow interrupt
-        *TIMSK0 |= 1 << 0;
-        
-        // Set Timer0 prescaler to 1024
-        *TCCR0B = (*TCCR0B & !0x07) | 0x05;
-        
-        // Enable global interrupts
-        asm!("sei");
+// Index of the blank pattern
+const BLANK: u8 = 16;
+
+// Powers of ten used when formatting numbers
+const POWERS_OF_TEN: [u32; 5] = [1, 10, 100, 1000, 10000];
+
+// Time each scroll step is shown for (ms)
+pub const SCROLL_STEP_MS: u64 = 300;
+
+/// Segment pattern for a character
+///
+/// Letters without a usable uppercase form fall back to lowercase (and vice
+/// versa), so any ASCII letter can be shown; a few are approximations
+/// (K, M, V, W, X). Characters with no pattern are shown blank.
+pub fn glyph(c: char) -> u8 {
+    match c {
+        '0'..='9' => DIGIT_PATTERNS[c as usize - '0' as usize],
+        'A' => 0x77,
+        'a' => 0x5f,
+        'B' | 'b' => 0x7c,
+        'C' => 0x39,
+        'c' => 0x58,
+        'D' | 'd' => 0x5e,
+        'E' => 0x79,
+        'e' => 0x7b,
+        'F' | 'f' => 0x71,
+        'G' => 0x3d,
+        'g' => 0x6f,
+        'H' => 0x76,
+        'h' => 0x74,
+        'I' => 0x30,
+        'i' => 0x10,
+        'J' => 0x1e,
+        'j' => 0x0c,
+        'K' | 'k' => 0x75,
+        'L' => 0x38,
+        'l' => 0x30,
+        'M' | 'm' => 0x15,
+        'N' => 0x37,
+        'n' => 0x54,
+        'O' => 0x3f,
+        'o' => 0x5c,
+        'P' | 'p' => 0x73,
+        'Q' | 'q' => 0x67,
+        'R' => 0x33,
+        'r' => 0x50,
+        'S' | 's' => 0x6d,
+        'T' | 't' => 0x78,
+        'U' => 0x3e,
+        'u' => 0x1c,
+        'V' => 0x3e,
+        'v' => 0x1c,
+        'W' | 'w' => 0x2a,
+        'X' | 'x' => 0x76,
+        'Y' | 'y' => 0x6e,
+        'Z' | 'z' => 0x5b,
+        '-' => 0x40,
+        '_' => 0x08,
+        '=' => 0x48,
+        '°' => 0x63,
+        '\'' => 0x02,
+        '"' => 0x22,
+        '?' => 0x53,
+        '[' => 0x39,
+        ']' => 0x0f,
+        _ => 0x00,
     }
-    
-    // Do not auto-update by default - we'll use manual updates for testing
-    AUTO_UPDATE_ENABLED.store(false, Ordering::Relaxed);
-    
-    // Test each digit individually for debugging
-    test_all_segments();
 }
 
-// Test function that manually tests all display segments
-fn test_all_segments() {
-    // Test all digits with all segments lit
-    for digit in 0..4 {
-        // Turn off all digits
-        B3::set_high();
-        B4::set_high();
-        D3::set_high();
-        D4::set_high();
-        
-        // Send all segments on pattern
-        shift_out(PATTERN_ALL_ON);
-        
-        // Enable current digit only
-        match digit {
-            0 => B3::set_low(), // DIGIT1_PIN
-            1 => B4::set_low(), // DIGIT2_PIN
-            2 => D3::set_low(), // DIGIT3_PIN
-            3 => D4::set_low(), // DIGIT4_PIN
-            _ => {}
+/// The frame composed in the back buffer, decimal points included
+///
+/// Call this once the whole frame (number, text and decimal points) has
+/// been set and pass it to the display driver.
+pub fn frame() -> [u8; 4] {
+    let mut frame = [0; 4];
+    unsafe {
+        for position in 0..4 {
+            frame[position] = DISPLAY_BUFFER[position];
+            
+            // Add decimal point if needed (bit 7 controls DP segment)
+            if DP_BUFFER[position] {
+                frame[position] |= 0x80; // Set the DP bit (bit 7)
+            }
         }
-        
-        // Hold for a visible time
-        delay::delay_ms(200);
     }
-    
-    // Turn off all digits
-    B3::set_high();
-    B4::set_high();
-    D3::set_high();
-    D4::set_high();
+    frame
 }
 
-// Shift out data to the 74HC595 with more robust timing
-fn shift_out(data: u8) {
-    // Set latch low before shifting
-    B1::set_low(); // LATCH_PIN
-    delay::delay_us(5);
-    
-    // Shift out 8 bits MSB first
-    for i in (0..8).rev() {
-        // Set data bit
-        if (data & (1 << i)) != 0 {
-            B0::set_high(); // DATA_PIN
-        } else {
-            B0::set_low(); // DATA_PIN
-        }
-        
-        // Longer delay for setup time
-        delay::delay_us(5);
-        
-        // Clock pulse with longer delays
-        B2::set_high(); // CLOCK_PIN
-        delay::delay_us(10);
-        B2::set_low(); // CLOCK_PIN
-        delay::delay_us(5);
+// Set a specific digit value and its decimal point
+fn set_digit(position: u8, value: u8, decimal_point: bool) {
+    if (value as usize) < DIGIT_PATTERNS.len() {
+        set_segments(position, DIGIT_PATTERNS[value as usize], decimal_point);
     }
-    
-    // Set latch high to display with delay
-    delay::delay_us(5);
-    B1::set_high(); // LATCH_PIN
-    delay::delay_us(10);
 }
 
-// Display a single digit with optional decimal point
-fn display_digit(digit: u8, show_dp: bool) {
-    if digit > 16 {
-        return; // Invalid digit
+// Set the raw segment pattern of a digit and its decimal point
+fn set_segments(position: u8, pattern: u8, decimal_point: bool) {
+    if position < 4 {
+        unsafe {
+            DISPLAY_BUFFER[position as usize] = pattern;
+            DP_BUFFER[position as usize] = decimal_point;
+        }
     }
-    
-    let mut pattern = DIGIT_PATTERNS[digit as usize];
-    
-    // Add decimal point if needed (bit 7 controls DP segment)
-    if show_dp {
-        pattern |= 0x80; // Set the DP bit (bit 7)
+}
+
+// Segment pattern and decimal point of the nth character cell of a text.
+// A '.' lights the decimal point of the cell before it instead of taking a
+// cell of its own, unless it starts the text or follows another '.'.
+fn text_cell(text: &str, n: usize) -> Option<(u8, bool)> {
+    let mut chars = text.chars().peekable();
+    let mut index = 0;
+    while let Some(c) = chars.next() {
+        let dp = chars.peek() == Some(&'.') && c != '.';
+        if dp {
+            chars.next();
+        }
+        if index == n {
+            return Some((glyph(c), dp));
+        }
+        index += 1;
     }
-    
-    shift_out(pattern);
+    None
 }
 
-/// Timer0 overflow interrupt handler for auto-updating the display
-#[no_mangle]
-pub extern "avr-interrupt" fn __vector_16() {
-    if AUTO_UPDATE_ENABLED.load(Ordering::Relaxed) {
-        update_display_internal();
+// Number of character cells a text occupies
+fn text_cells(text: &str) -> usize {
+    let mut count = 0;
+    while text_cell(text, count).is_some() {
+        count += 1;
     }
+    count
 }
 
-/// Internal function to update the display (used by timer interrupt)
-fn update_display_internal() {
-    unsafe {
-        // Turn off all digits first
-        B3::set_high(); // DIGIT1_PIN
-        B4::set_high(); // DIGIT2_PIN
-        D3::set_high(); // DIGIT3_PIN
-        D4::set_high(); // DIGIT4_PIN
-        
-        // Display the digit for the current position with its decimal point
-        display_digit(DISPLAY_BUFFER[CURRENT_DIGIT as usize], DP_BUFFER[CURRENT_DIGIT as usize]);
-        
-        // Enable only the current digit position
-        match CURRENT_DIGIT {
-            0 => B3::set_low(), // DIGIT1_PIN
-            1 => B4::set_low(), // DIGIT2_PIN
-            2 => D3::set_low(), // DIGIT3_PIN
-            3 => D4::set_low(), // DIGIT4_PIN
-            _ => {}
-        }
-        
-        // Move to next digit position
-        CURRENT_DIGIT = (CURRENT_DIGIT + 1) % 4;
+/// Display text, left aligned, such as a label ("PH", "t") or message ("Err")
+///
+/// Only the first four character cells are shown; use `display_scrolling_text`
+/// for longer messages.
+pub fn display_text(text: &str) {
+    for position in 0..4 {
+        let (pattern, dp) = text_cell(text, position as usize).unwrap_or((0, false));
+        set_segments(position, pattern, dp);
     }
 }
 
-/// Update the display manually (only needed if auto-update is disabled)
-/// This function is kept for backward compatibility but is not needed in normal operation
-pub fn update() {
-    if !AUTO_UPDATE_ENABLED.load(Ordering::Relaxed) {
-        update_display_internal();
+/// Display text, scrolling it right to left if it does not fit in four digits
+///
+/// `elapsed_ms` is the time since the message was first shown; the text
+/// scrolls in from the right, moves one digit every `SCROLL_STEP_MS`, and
+/// starts over after it has left the display.
+pub fn display_scrolling_text(text: &str, elapsed_ms: u64) {
+    let cells = text_cells(text);
+    if cells <= 4 {
+        display_text(text);
+        return;
+    }
+    
+    // Offset of the text relative to the leftmost digit, starting fully off screen
+    let step = (elapsed_ms / SCROLL_STEP_MS) as usize % (cells + 4);
+    for position in 0..4 {
+        let index = (position + step) as isize - 4;
+        let (pattern, dp) = if index >= 0 {
+            text_cell(text, index as usize).unwrap_or((0, false))
+        } else {
+            (0, false)
+        };
+        set_segments(position as u8, pattern, dp);
     }
 }
 
-/// Enable or disable automatic display updates
-pub fn set_auto_update(enabled: bool) {
-    AUTO_UPDATE_ENABLED.store(enabled, Ordering::Relaxed);
+/// Display a whole number right aligned, without leading zeros
+///
+/// Values above 9999 are shown as 9999.
+pub fn display_integer(value: u16) {
+    let mut remaining = if value > 9999 { 9999 } else { value };
+    for position in (0..4).rev() {
+        // Always show at least the units digit
+        if remaining == 0 && position < 3 {
+            set_digit(position, BLANK, false);
+        } else {
+            set_digit(position, (remaining % 10) as u8, false);
+        }
+        remaining /= 10;
+    }
 }
 
-// Set a specific digit value and its decimal point
-fn set_digit(position: u8, value: u8, decimal_point: bool) {
-    if position < 4 && value <= 16 {
+/// Light or clear the decimal point of a single digit
+///
+/// Used as a status indicator, e.g. the rightmost point marks a settled reading.
+/// Call after `display()`, which sets the decimal points for the number format.
+pub fn set_decimal_point(position: u8, on: bool) {
+    if position < 4 {
         unsafe {
-            DISPLAY_BUFFER[position as usize] = value;
-            DP_BUFFER[position as usize] = decimal_point;
+            DP_BUFFER[position as usize] = on;
         }
     }
 }
@@ -235,70 +261,53 @@ fn set_digit(position: u8, value: u8, decimal_point: bool) {
 /// - Numbers 100-999: Format as ###.# (e.g., 123.4)
 /// - Numbers 1000-9999: Format as #### (e.g., 1234)
 ///
-/// Numbers are automatically rounded to fit the display format.
-pub fn display(mut num: f32) {
-    // Check if number is in valid range (0 to 9999.9999)
-    if num < 0.0 || num > 9999.9999 {
-        // Display "Err" for out of range
-        set_digit(0, 14, false); // E
-        set_digit(1, 16, false); // r
-        set_digit(2, 16, false); // r
-        set_digit(3, 16, false); // blank
+/// Negative numbers use the first digit for a minus sign and keep as many
+/// decimals as fit in the remaining three (e.g., -2.50, -12.5, -123).
+///
+/// Numbers are automatically rounded to fit the display format. Numbers too
+/// large to fit show "OL" (or "-OL" below -999), and NaN shows "Err".
+pub fn display(num: f32) {
+    if num.is_nan() {
+        display_text("Err");
+        return;
+    }
+    
+    // Values that round to zero are shown without a minus sign
+    let negative = num < 0.0 && roundf(num * 100.0) != 0.0;
+    let magnitude = if negative { -num } else { num };
+    if magnitude < 0.0 {
+        // -0.00x rounds to zero
+        display(0.0);
         return;
     }
     
-    // Format based on magnitude
-    if num < 10.0 {
-        // Format as #.###
-        num = roundf(num * 1000.0) / 1000.0;
-        
-        let d1 = (num as u16) % 10;
-        let d2 = ((num * 10.0) as u16) % 10;
-        let d3 = ((num * 100.0) as u16) % 10;
-        let d4 = ((num * 1000.0) as u16) % 10;
-        
-        set_digit(0, d1 as u8, true); // First digit with DP
-        set_digit(1, d2 as u8, false);
-        set_digit(2, d3 as u8, false);
-        set_digit(3, d4 as u8, false);
-    } else if num < 100.0 {
-        // Format as ##.##
-        num = roundf(num * 100.0) / 100.0;
-        
-        let d1 = ((num / 10.0) as u16) % 10;
-        let d2 = (num as u16) % 10;
-        let d3 = ((num * 10.0) as u16) % 10;
-        let d4 = ((num * 100.0) as u16) % 10;
-        
-        set_digit(0, d1 as u8, false);
-        set_digit(1, d2 as u8, true); // Second digit with DP
-        set_digit(2, d3 as u8, false);
-        set_digit(3, d4 as u8, false);
-    } else if num < 1000.0 {
-        // Format as ###.#
-        num = roundf(num * 10.0) / 10.0;
-        
-        let d1 = ((num / 100.0) as u16) % 10;
-        let d2 = ((num / 10.0) as u16) % 10;
-        let d3 = (num as u16) % 10;
-        let d4 = ((num * 10.0) as u16) % 10;
-        
-        set_digit(0, d1 as u8, false);
-        set_digit(1, d2 as u8, false);
-        set_digit(2, d3 as u8, true); // Third digit with DP
-        set_digit(3, d4 as u8, false);
-    } else {
-        // Format as ####
-        num = roundf(num);
-        
-        let d1 = ((num / 1000.0) as u16) % 10;
-        let d2 = ((num / 100.0) as u16) % 10;
-        let d3 = ((num / 10.0) as u16) % 10;
-        let d4 = (num as u16) % 10;
-        
-        set_digit(0, d1 as u8, false);
-        set_digit(1, d2 as u8, false);
-        set_digit(2, d3 as u8, false);
-        set_digit(3, d4 as u8, false);
+    // Digits available for the number itself
+    let available: usize = if negative { 3 } else { 4 };
+    
+    // Use as many decimals as fit, dropping one whenever rounding overflows
+    // the available digits (e.g., 9.9996 becomes 10.00 rather than 0.000)
+    let mut decimals = available - 1;
+    let mut scaled = roundf(magnitude * POWERS_OF_TEN[decimals] as f32);
+    while scaled >= POWERS_OF_TEN[available] as f32 && decimals > 0 {
+        decimals -= 1;
+        scaled = roundf(magnitude * POWERS_OF_TEN[decimals] as f32);
+    }
+    
+    if scaled >= POWERS_OF_TEN[available] as f32 {
+        // Too large for the display even without decimals
+        display_text(if negative { "-OL" } else { "OL" });
+        return;
+    }
+    
+    // Fill the digits from the right
+    let mut remaining = scaled as u32;
+    for position in (4 - available..4).rev() {
+        let decimal_point = decimals > 0 && position == 3 - decimals;
+        set_digit(position as u8, (remaining % 10) as u8, decimal_point);
+        remaining /= 10;
+    }
+    
+    if negative {
+        set_segments(0, glyph('-'), false);
     }
 }
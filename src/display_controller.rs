@@ -1,69 +1,294 @@
 use crate::display;
+use crate::display::SegmentDisplay;
+use crate::actuators::{self, Lockout, Output};
+use crate::air;
+use crate::alarm;
+use crate::menu::View;
 use crate::sensor_manager::SensorValues;
+use crate::statistics::Summary;
+use crate::watchdog;
+#[cfg(feature = "wifi")]
+use crate::wifi;
 
 // Display mode
 #[derive(PartialEq, Copy, Clone)]
 pub enum DisplayMode {
     PH,
-    Temperature
+    Temperature,
+    ElectrodeMv,       // Raw pH electrode potential (mV)
+    SupplyVoltage,     // Measured AVCC (V)
+    AerationCountdown, // Seconds until the pump next switches, or SAFE while held off
+    Uptime,            // Hours since power up
+    Alarm,             // Active alarm code and description
+    TemperatureDay,    // Temperature low, high and mean over the last day
+    PHDay,             // pH low, high and mean over the last day
+    Reset,             // Why the monitor last reset
+    #[cfg(feature = "wifi")]
+    Wifi,              // Wi-Fi uplink state
 }
 
-pub struct DisplayController {
+// One entry in the display rotation
+#[derive(Copy, Clone)]
+pub struct Page {
     pub mode: DisplayMode,
+    pub duration_ms: u64, // Total time on this page, label included
+}
+
+// How long the label is shown before the value on each page
+pub const LABEL_TIME_MS: u64 = 600;
+
+// Statistics pages show low, high and mean in turn, each for
+// STATISTIC_TIME_MS and starting with a short label
+pub const STATISTIC_TIME_MS: u64 = 2000;
+const STATISTIC_LABEL_MS: u64 = 500;
+
+// Values being edited in the menu blink: on for BLINK_ON_MS of every
+// BLINK_PERIOD_MS, but stay lit for EDIT_HOLD_MS after each change
+const BLINK_PERIOD_MS: u64 = 500;
+const BLINK_ON_MS: u64 = 300;
+const EDIT_HOLD_MS: u64 = 800;
+
+// Digit whose decimal point marks a settled (stable) reading. The number
+// formats never use the rightmost decimal point, so it is free for this.
+const STABLE_INDICATOR_DIGIT: u8 = 3;
+
+pub struct DisplayController<D: SegmentDisplay> {
+    driver: D,
+    pub mode: DisplayMode,
+    pages: &'static [Page],
+    page_index: usize,
     mode_switch_time: u64,
-    display_time_per_reading_ms: u64,
+    brightness: u8,
+    #[cfg(feature = "light-sensor")]
+    auto_brightness: bool, // Follow the ambient light sensor
+    edit_value: u16,       // Last value shown for editing
+    edit_time: u64,        // When it last changed
 }
 
-impl DisplayController {
-    // Create a new display controller
-    pub fn new(display_time_per_reading_ms: u64) -> Self {
+impl<D: SegmentDisplay> DisplayController<D> {
+    // Create a new display controller cycling through the given pages
+    pub fn new(driver: D, pages: &'static [Page]) -> Self {
         DisplayController {
-            mode: DisplayMode::Temperature, // Start with temperature
+            driver,
+            mode: pages[0].mode, // Start with the first page
+            pages,
+            page_index: 0,
             mode_switch_time: 0,
-            display_time_per_reading_ms,
+            brightness: display::MAX_BRIGHTNESS,
+            #[cfg(feature = "light-sensor")]
+            auto_brightness: false,
+            edit_value: 0,
+            edit_time: 0,
         }
     }
     
     // Initialize the display
-    pub fn initialize(&self) {
-        display::initialize();
-        display::set_auto_update(false);
+    pub fn initialize(&mut self) {
+        self.driver.initialize();
+    }
+    
+    // Set a fixed brightness level (1 to display::MAX_BRIGHTNESS)
+    pub fn set_brightness(&mut self, level: u8) {
+        #[cfg(feature = "light-sensor")]
+        {
+            self.auto_brightness = false;
+        }
+        self.apply_brightness(level);
+    }
+    
+    // Let the ambient light sensor control the brightness
+    #[cfg(feature = "light-sensor")]
+    pub fn set_auto_brightness(&mut self) {
+        self.auto_brightness = true;
+    }
+    
+    // The fixed brightness level, or 0 while the light sensor controls it
+    pub fn brightness(&self) -> u8 {
+        #[cfg(feature = "light-sensor")]
+        {
+            if self.auto_brightness {
+                return 0;
+            }
+        }
+        self.brightness
+    }
+    
+    // Change the hardware brightness if the level differs
+    fn apply_brightness(&mut self, level: u8) {
+        if level != self.brightness {
+            self.brightness = level;
+            self.driver.set_brightness(level);
+        }
     }
     
     // Update the display with current values
-    pub fn update_display(&self, sensor_values: &SensorValues) {
-        match self.mode {
+    pub fn update_display(&mut self, sensor_values: &SensorValues, current_time: u64) {
+        #[cfg(feature = "light-sensor")]
+        {
+            if self.auto_brightness {
+                // Dark room gives level 1, full light MAX_BRIGHTNESS
+                let range = display::MAX_BRIGHTNESS as u32 - 1;
+                let level = 1 + (sensor_values.light_level as u32 * range / 1023) as u8;
+                self.apply_brightness(level);
+            }
+        }
+        
+        if current_time < self.mode_switch_time + LABEL_TIME_MS {
+            display::display_text(label(self.mode));
+            self.driver.show(display::frame());
+            return;
+        }
+        
+        let stable = match self.mode {
             DisplayMode::Temperature => {
                 display::display(sensor_values.temperature);
+                sensor_values.temperature_stable
             },
             DisplayMode::PH => {
                 display::display(sensor_values.ph);
+                sensor_values.ph_stable
+            },
+            DisplayMode::ElectrodeMv => {
+                display::display(sensor_values.ph_mv);
+                sensor_values.ph_stable
+            },
+            DisplayMode::SupplyVoltage => {
+                display::display(sensor_values.supply_voltage);
+                false
+            },
+            DisplayMode::AerationCountdown => {
+                if actuators::lockout(Output::AirPump) == Lockout::None {
+                    let seconds = (air::time_until_change(current_time) + 999) / 1000;
+                    display::display_integer(seconds as u16);
+                } else {
+                    display::display_text("SAFE");
+                }
+                false
+            },
+            DisplayMode::Uptime => {
+                display::display(current_time as f32 / 3_600_000.0);
+                false
+            },
+            DisplayMode::Alarm => {
+                let elapsed = current_time - (self.mode_switch_time + LABEL_TIME_MS);
+                display::display_scrolling_text(alarm::active(sensor_values).message(), elapsed);
+                false
+            },
+            DisplayMode::TemperatureDay => {
+                let elapsed = current_time - (self.mode_switch_time + LABEL_TIME_MS);
+                display_summary(&sensor_values.temperature_statistics.last_day(), elapsed);
+                false
+            },
+            DisplayMode::PHDay => {
+                let elapsed = current_time - (self.mode_switch_time + LABEL_TIME_MS);
+                display_summary(&sensor_values.ph_statistics.last_day(), elapsed);
+                false
+            },
+            DisplayMode::Reset => {
+                let elapsed = current_time - (self.mode_switch_time + LABEL_TIME_MS);
+                display::display_scrolling_text(watchdog::reset_cause().message(), elapsed);
+                false
+            },
+            #[cfg(feature = "wifi")]
+            DisplayMode::Wifi => {
+                let elapsed = current_time - (self.mode_switch_time + LABEL_TIME_MS);
+                display::display_scrolling_text(wifi::status().message(), elapsed);
+                false
+            }
+        };
+        display::set_decimal_point(STABLE_INDICATOR_DIGIT, stable);
+        self.driver.show(display::frame()); // Hand the finished frame to the driver
+    }
+    
+    // Show the menu instead of the pages
+    pub fn show_menu(&mut self, view: View, current_time: u64) {
+        match view {
+            View::Text(text) => display::display_text(text),
+            View::Edit(value) => {
+                if value != self.edit_value {
+                    self.edit_value = value;
+                    self.edit_time = current_time;
+                }
+                
+                // Blink, unless the value was just changed
+                let since_change = current_time - self.edit_time;
+                if since_change < EDIT_HOLD_MS || since_change % BLINK_PERIOD_MS < BLINK_ON_MS {
+                    display::display_integer(value);
+                } else {
+                    display::display_text("");
+                }
+            },
+            View::Reading(value, stable) => {
+                display::display(value);
+                display::set_decimal_point(STABLE_INDICATOR_DIGIT, stable);
             }
         }
-        display::update(); // Refresh the display multiplexing
+        self.driver.show(display::frame());
+    }
+    
+    // Step to the next page by hand, starting with its label
+    pub fn next_page(&mut self, current_time: u64) {
+        self.show_page((self.page_index + 1) % self.pages.len(), current_time);
+    }
+    
+    // Step back to the previous page by hand
+    pub fn previous_page(&mut self, current_time: u64) {
+        self.show_page((self.page_index + self.pages.len() - 1) % self.pages.len(), current_time);
+    }
+    
+    fn show_page(&mut self, index: usize, current_time: u64) {
+        self.page_index = index;
+        self.mode = self.pages[index].mode;
+        self.mode_switch_time = current_time;
     }
     
     // Check and update display mode if needed
     pub fn check_mode_switch(&mut self, current_time: u64) -> bool {
         let mut switched = false;
         
-        if current_time >= self.mode_switch_time + self.display_time_per_reading_ms {
-            // Switch display mode
-            self.mode = match self.mode {
-                DisplayMode::Temperature => DisplayMode::PH,
-                DisplayMode::PH => DisplayMode::Temperature,
-            };
-            
-            // Reset switch timer
-            self.mode_switch_time = current_time;
+        if current_time >= self.mode_switch_time + self.pages[self.page_index].duration_ms {
+            // Move on to the next page (resets the switch timer)
+            self.next_page(current_time);
             switched = true;
         }
         
         switched
     }
+}
+
+// Short label flashed before each value
+fn label(mode: DisplayMode) -> &'static str {
+    match mode {
+        DisplayMode::Temperature => "t",
+        DisplayMode::PH => "PH",
+        DisplayMode::ElectrodeMv => "EL",
+        DisplayMode::SupplyVoltage => "UCC",
+        DisplayMode::AerationCountdown => "Air",
+        DisplayMode::Uptime => "UP",
+        DisplayMode::Alarm => "AL",
+        DisplayMode::TemperatureDay => "t24",
+        DisplayMode::PHDay => "PH24",
+        DisplayMode::Reset => "rSt",
+        #[cfg(feature = "wifi")]
+        DisplayMode::Wifi => "nEt",
+    }
+}
+
+// Low, high and mean in turn, each after its label
+fn display_summary(summary: &Summary, elapsed: u64) {
+    if summary.count == 0 {
+        display::display_text("----");
+        return;
+    }
     
-    // Is the display currently showing temperature?
-    pub fn is_showing_temperature(&self) -> bool {
-        self.mode == DisplayMode::Temperature
+    let (name, value) = match (elapsed / STATISTIC_TIME_MS) % 3 {
+        0 => ("Lo", summary.min),
+        1 => ("Hi", summary.max),
+        _ => ("AVG", summary.mean),
+    };
+    if elapsed % STATISTIC_TIME_MS < STATISTIC_LABEL_MS {
+        display::display_text(name);
+    } else {
+        display::display(value);
     }
-} 
\ No newline at end of file
+}
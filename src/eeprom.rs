@@ -0,0 +1,97 @@
+use ruduino::Register;
+use ruduino::interrupt::without_interrupts;
+
+// Register definitions for the EEPROM
+pub struct EECR;
+impl Register for EECR {
+    type T = u8;
+    const ADDRESS: *mut u8 = 0x3F as *mut u8;
+}
+
+// EEPROM Data Register
+pub struct EEDR;
+impl Register for EEDR {
+    type T = u8;
+    const ADDRESS: *mut u8 = 0x40 as *mut u8;
+}
+
+// EEPROM Address Register Low
+pub struct EEARL;
+impl Register for EEARL {
+    type T = u8;
+    const ADDRESS: *mut u8 = 0x41 as *mut u8;
+}
+
+// EEPROM Address Register High
+pub struct EEARH;
+impl Register for EEARH {
+    type T = u8;
+    const ADDRESS: *mut u8 = 0x42 as *mut u8;
+}
+
+// EECR bits
+pub const EEMPE: u8 = 1 << 2;  // EEPROM Master Write Enable
+pub const EEPE: u8 = 1 << 1;   // EEPROM Write Enable
+pub const EERE: u8 = 1 << 0;   // EEPROM Read Enable
+
+// EEPROM size (ATmega328P)
+// pub const SIZE: u16 = 1024;
+
+// EEPROM memory map
+pub const SETTINGS_ADDRESS: u16 = 0x000;  // Settings block (64 bytes)
+pub const HISTORY_ADDRESS: u16 = 0x040;   // History ring buffer
+pub const HISTORY_SIZE: u16 = 768;
+pub const RESETS_ADDRESS: u16 = 0x340;   // Reset counters (7 bytes)
+pub const CHECKPOINT_ADDRESS: u16 = 0x348; // State checkpoints, to the end
+pub const CHECKPOINT_SIZE: u16 = 184;
+
+// Is the EEPROM free to start a write? (a write takes ~3.4ms)
+pub fn is_ready() -> bool {
+    EECR::read() & EEPE == 0
+}
+
+// Read a byte
+pub fn read_byte(address: u16) -> u8 {
+    // Wait for any write in progress
+    while EECR::read() & EEPE != 0 {}
+    
+    EEARH::write((address >> 8) as u8);
+    EEARL::write(address as u8);
+    EECR::write(EERE);
+    EEDR::read()
+}
+
+// Write a byte, skipping the write if it already holds the value
+// (each cell is only rated for ~100,000 writes)
+pub fn update_byte(address: u16, data: u8) {
+    if read_byte(address) == data {
+        return;
+    }
+    
+    // Wait for any write in progress (a write takes ~3.4ms)
+    while EECR::read() & EEPE != 0 {}
+    
+    EEARH::write((address >> 8) as u8);
+    EEARL::write(address as u8);
+    EEDR::write(data);
+    
+    // EEPE must be set within four cycles of EEMPE, so no interrupts between
+    without_interrupts(|| {
+        EECR::write(EEMPE);
+        EECR::write(EEMPE | EEPE);
+    });
+}
+
+// Read a block of bytes
+pub fn read(address: u16, data: &mut [u8]) {
+    for (offset, byte) in data.iter_mut().enumerate() {
+        *byte = read_byte(address + offset as u16);
+    }
+}
+
+// Write a block of bytes, only touching the ones that changed
+pub fn update(address: u16, data: &[u8]) {
+    for (offset, &byte) in data.iter().enumerate() {
+        update_byte(address + offset as u16, byte);
+    }
+}
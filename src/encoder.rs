@@ -0,0 +1,115 @@
+use ruduino::{Pin, Register};
+use ruduino::cores::current::port::{C2, C3};
+use ruduino::interrupt::without_interrupts;
+
+// Quadrature rotary encoder for the local menu
+//
+// Channels A and B go to A2 and A3 (both on port C, so one pin-change
+// interrupt covers them) with the common pin to GND; the internal pull-ups
+// are used. The encoder's push switch replaces the MENU button on D6.
+pub type APin = C2;
+pub type BPin = C3;
+
+// Pin Change Interrupt Control Register
+pub struct PCICR;
+impl Register for PCICR {
+    type T = u8;
+    const ADDRESS: *mut u8 = 0x68 as *mut u8;
+}
+
+// Pin Change Mask Register 1 (port C)
+pub struct PCMSK1;
+impl Register for PCMSK1 {
+    type T = u8;
+    const ADDRESS: *mut u8 = 0x6C as *mut u8;
+}
+
+// PCICR bits
+pub const PCIE1: u8 = 1 << 1;  // Pin change interrupt enable for PCINT8-14
+
+// PCMSK1 bits
+pub const PCINT10: u8 = 1 << 2; // C2
+pub const PCINT11: u8 = 1 << 3; // C3
+
+// Quadrature steps per detent (click) of a typical mechanical encoder
+const STEPS_PER_DETENT: i8 = 4;
+
+// Acceleration: clicks arriving faster than these intervals count several times
+const FAST_DETENT_MS: u64 = 30;
+const FAST_MULTIPLIER: i16 = 10;
+const MEDIUM_DETENT_MS: u64 = 80;
+const MEDIUM_MULTIPLIER: i16 = 4;
+
+// Direction of each transition, indexed by (previous state << 2) | new state.
+// Invalid transitions (both channels changed, i.e. bounce) count as zero.
+const TRANSITIONS: [i8; 16] = [0, -1, 1, 0, 1, 0, 0, -1, -1, 0, 0, 1, 0, 1, -1, 0];
+
+// Decoder state, shared with the interrupt
+static mut STATE: u8 = 0;    // Last A/B levels as bits 1/0
+static mut POSITION: i8 = 0; // Quarter steps not yet collected
+
+// Read both channels as a 2-bit state
+fn read_state() -> u8 {
+    ((APin::is_high() as u8) << 1) | BPin::is_high() as u8
+}
+
+pub struct Encoder {
+    last_detent_time: u64,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Encoder {
+            last_detent_time: 0,
+        }
+    }
+    
+    // Configure the pins and enable the pin-change interrupt
+    pub fn initialize(&self) {
+        APin::set_input();
+        APin::set_high();
+        BPin::set_input();
+        BPin::set_high();
+        
+        unsafe {
+            STATE = read_state();
+        }
+        PCMSK1::write(PCMSK1::read() | PCINT10 | PCINT11);
+        PCICR::write(PCICR::read() | PCIE1);
+    }
+    
+    // Clicks turned since the last call (swap A and B to reverse the
+    // direction), multiplied when turned quickly
+    pub fn poll(&mut self, current_time: u64) -> i16 {
+        let detents = without_interrupts(|| unsafe {
+            let detents = POSITION / STEPS_PER_DETENT;
+            POSITION -= detents * STEPS_PER_DETENT;
+            detents
+        });
+        if detents == 0 {
+            return 0;
+        }
+        
+        let interval = current_time - self.last_detent_time;
+        self.last_detent_time = current_time;
+        
+        let multiplier = if interval < FAST_DETENT_MS {
+            FAST_MULTIPLIER
+        } else if interval < MEDIUM_DETENT_MS {
+            MEDIUM_MULTIPLIER
+        } else {
+            1
+        };
+        detents as i16 * multiplier
+    }
+}
+
+/// Pin change interrupt 1 handler (port C), decodes the encoder
+#[no_mangle]
+pub extern "avr-interrupt" fn __vector_4() {
+    let state = read_state();
+    unsafe {
+        POSITION = POSITION.wrapping_add(TRANSITIONS[((STATE << 2) | state) as usize]);
+        STATE = state;
+    }
+}
@@ -0,0 +1,547 @@
+use crate::sd::{SdCard, BLOCK_SIZE};
+use crate::watchdog;
+
+// Minimal FAT16/FAT32 file system for the data logger
+//
+// Files are kept in the root directory with 8.3 names and can only be
+// created and appended to. All operations share one 512 byte sector buffer
+// to stay within the 2KB of RAM. The directory entry is updated after every
+// append, so pulling the card at worst loses the row being written (and
+// leaves a lost cluster for the PC's disk check to clean up).
+//
+// No call scans the whole FAT, which takes minutes on a large card: the
+// free cluster count comes from the FAT32 FSInfo sector, kept up to date on
+// every allocation, or is counted COUNT_CLUSTERS at a time by update. A
+// free cluster is looked for SEARCH_CLUSTERS at a time, carrying on where
+// the last search stopped. Following a cluster chain is limited by the
+// length of the file or directory, and kicks the watchdog as it goes.
+
+// Marks an empty sector buffer
+const NO_SECTOR: u32 = 0xFFFF_FFFF;
+
+// Directory entries
+const ENTRY_SIZE: usize = 32;
+const DELETED: u8 = 0xE5;
+const ATTRIBUTE_ARCHIVE: u8 = 0x20;
+const ATTRIBUTE_SKIP: u8 = 0x18; // Directory or volume label (includes long name entries)
+
+// A directory holds at most 65536 entries
+const DIRECTORY_SECTORS: u32 = 65536 * ENTRY_SIZE as u32 / BLOCK_SIZE as u32;
+
+// FAT32 FSInfo sector
+const FSINFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FSINFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+// FAT entries looked at per update while counting free clusters, and per
+// allocation while looking for a free one
+const COUNT_CLUSTERS: u32 = 256;
+const SEARCH_CLUSTERS: u32 = 1024;
+
+// Why an operation failed
+#[derive(PartialEq, Copy, Clone)]
+pub enum Error {
+    Card,        // Card missing or not responding
+    Unsupported, // Not a FAT16 or FAT32 volume with 512 byte sectors
+    Full,        // No free cluster or directory entry left
+    Busy,        // Still looking for a free cluster, try again
+}
+
+// A file opened for appending
+pub struct File {
+    entry_sector: u32, // Directory entry location
+    entry_offset: usize,
+    first_cluster: u32, // 0 while the file is empty
+    last_cluster: u32,
+    pub size: u32,
+}
+
+pub struct Volume {
+    card: SdCard,
+    buffer: [u8; BLOCK_SIZE],
+    buffer_sector: u32, // Sector held in the buffer
+    fat32: bool,
+    sectors_per_cluster: u32,
+    fat_start: u32,
+    fat_size: u32,      // Sectors per FAT copy
+    fat_count: u32,
+    root_start: u32,    // FAT16: fixed root directory
+    root_sectors: u32,
+    root_cluster: u32,  // FAT32: root directory cluster chain
+    data_start: u32,
+    cluster_count: u32,
+    fsinfo_sector: u32, // FAT32: FSInfo sector, 0 if missing or damaged
+    free_clusters: Option<u32>, // None until counted
+    counted_clusters: u32,      // Counting so far: clusters looked at
+    counted_free: u32,          // and how many of them are free
+    next_free: u32,     // Where to carry on looking for a free cluster
+    search_left: u32,   // Clusters not looked at since the last allocation
+}
+
+impl Volume {
+    pub fn new() -> Self {
+        Volume {
+            card: SdCard::new(),
+            buffer: [0; BLOCK_SIZE],
+            buffer_sector: NO_SECTOR,
+            fat32: false,
+            sectors_per_cluster: 1,
+            fat_start: 0,
+            fat_size: 0,
+            fat_count: 0,
+            root_start: 0,
+            root_sectors: 0,
+            root_cluster: 0,
+            data_start: 0,
+            cluster_count: 0,
+            fsinfo_sector: 0,
+            free_clusters: None,
+            counted_clusters: 0,
+            counted_free: 0,
+            next_free: 2,
+            search_left: 0,
+        }
+    }
+    
+    // Initialize the card and read the volume layout from the first partition
+    // (or the whole card if it has no partition table)
+    pub fn mount(&mut self) -> Result<(), Error> {
+        self.buffer_sector = NO_SECTOR;
+        if !self.card.initialize() {
+            return Err(Error::Card);
+        }
+        
+        self.read(0)?;
+        if !self.is_boot_sector() {
+            // Master boot record: use the first partition
+            let start = u32_at(&self.buffer, 0x1C6);
+            self.read(start)?;
+            if !self.is_boot_sector() {
+                return Err(Error::Unsupported);
+            }
+        }
+        let start = self.buffer_sector;
+        
+        // BIOS parameter block
+        self.sectors_per_cluster = self.buffer[13] as u32;
+        let reserved_sectors = u16_at(&self.buffer, 14) as u32;
+        self.fat_count = self.buffer[16] as u32;
+        let root_entries = u16_at(&self.buffer, 17) as u32;
+        let total_sectors = match u16_at(&self.buffer, 19) {
+            0 => u32_at(&self.buffer, 32),
+            sectors => sectors as u32,
+        };
+        self.fat_size = match u16_at(&self.buffer, 22) {
+            0 => u32_at(&self.buffer, 36),
+            sectors => sectors as u32,
+        };
+        if self.sectors_per_cluster == 0 || self.fat_count == 0 {
+            return Err(Error::Unsupported);
+        }
+        
+        self.fat_start = start + reserved_sectors;
+        self.root_start = self.fat_start + self.fat_count * self.fat_size;
+        self.root_sectors = (root_entries * ENTRY_SIZE as u32 + BLOCK_SIZE as u32 - 1) / BLOCK_SIZE as u32;
+        self.data_start = self.root_start + self.root_sectors;
+        let data_sectors = total_sectors.saturating_sub(self.data_start - start);
+        self.cluster_count = data_sectors / self.sectors_per_cluster;
+        
+        // The cluster count alone decides the FAT type
+        if self.cluster_count < 4085 {
+            return Err(Error::Unsupported); // FAT12
+        }
+        self.fat32 = self.cluster_count >= 65525;
+        self.next_free = 2;
+        self.search_left = self.cluster_count;
+        self.fsinfo_sector = 0;
+        self.free_clusters = None;
+        self.counted_clusters = 0;
+        self.counted_free = 0;
+        
+        if self.fat32 {
+            self.root_cluster = u32_at(&self.buffer, 44);
+            let fsinfo_sector = start + u16_at(&self.buffer, 48) as u32;
+            self.read_fsinfo(fsinfo_sector)?;
+        }
+        Ok(())
+    }
+    
+    // Count free clusters a few at a time until the count is known; call
+    // regularly while mounted
+    pub fn update(&mut self) -> Result<(), Error> {
+        if self.free_clusters.is_some() {
+            return Ok(());
+        }
+        
+        for _ in 0..COUNT_CLUSTERS {
+            if self.counted_clusters == self.cluster_count {
+                self.free_clusters = Some(self.counted_free);
+                return self.write_fsinfo();
+            }
+            if self.fat_entry(2 + self.counted_clusters)? == 0 {
+                self.counted_free += 1;
+            }
+            self.counted_clusters += 1;
+        }
+        Ok(())
+    }
+    
+    // Free space on the card in bytes, if known yet
+    pub fn free_bytes(&self) -> Option<u64> {
+        self.free_clusters
+            .map(|free| free as u64 * self.sectors_per_cluster as u64 * BLOCK_SIZE as u64)
+    }
+    
+    // Highest number among files named <prefix><digits>.<extension>, e.g.
+    // LOG00042.CSV, or 0 if there are none
+    pub fn highest_number(&mut self, prefix: &[u8; 3], extension: &[u8; 3]) -> Result<u32, Error> {
+        let mut highest = 0;
+        self.scan_root(|entry| {
+            if is_file(entry) && entry[0..3] == prefix[..] && entry[8..11] == extension[..] {
+                let digits = &entry[3..8];
+                if digits.iter().all(u8::is_ascii_digit) {
+                    let number = digits.iter().fold(0, |n, digit| n * 10 + (digit - b'0') as u32);
+                    highest = highest.max(number);
+                }
+            }
+            false
+        })?;
+        Ok(highest)
+    }
+    
+    // Open a file in the root directory for appending, creating it if needed.
+    // `name` is in directory form: 8 characters of name and 3 of extension,
+    // padded with spaces.
+    pub fn open(&mut self, name: &[u8; 11]) -> Result<File, Error> {
+        if let Some((sector, offset)) = self.scan_root(|entry| is_file(entry) && entry[0..11] == name[..])? {
+            self.read(sector)?;
+            let entry = &self.buffer[offset..offset + ENTRY_SIZE];
+            let first_cluster = (u16_at(entry, 20) as u32) << 16 | u16_at(entry, 26) as u32;
+            let size = u32_at(entry, 28);
+            
+            // Appending starts at the end of the cluster chain, no longer
+            // than the file
+            let mut last_cluster = first_cluster;
+            if first_cluster != 0 {
+                let cluster_size = self.sectors_per_cluster * BLOCK_SIZE as u32;
+                for _ in 0..size / cluster_size {
+                    watchdog::kick();
+                    let next = self.fat_entry(last_cluster)?;
+                    if self.is_end_of_chain(next) {
+                        break;
+                    }
+                    last_cluster = next;
+                }
+            }
+            
+            return Ok(File {
+                entry_sector: sector,
+                entry_offset: offset,
+                first_cluster,
+                last_cluster,
+                size,
+            });
+        }
+        
+        // New file in the first free entry
+        let (sector, offset) = match self.scan_root(|entry| entry[0] == 0 || entry[0] == DELETED)? {
+            Some(location) => location,
+            None => self.extend_root()?,
+        };
+        self.read(sector)?;
+        let entry = &mut self.buffer[offset..offset + ENTRY_SIZE];
+        entry.fill(0);
+        entry[0..11].copy_from_slice(name);
+        entry[11] = ATTRIBUTE_ARCHIVE;
+        self.write(sector)?;
+        
+        Ok(File {
+            entry_sector: sector,
+            entry_offset: offset,
+            first_cluster: 0,
+            last_cluster: 0,
+            size: 0,
+        })
+    }
+    
+    // Append data to the end of a file
+    pub fn append(&mut self, file: &mut File, data: &[u8]) -> Result<(), Error> {
+        let cluster_size = self.sectors_per_cluster * BLOCK_SIZE as u32;
+        let mut written = 0;
+        
+        while written < data.len() {
+            let position = file.size % cluster_size;
+            if file.first_cluster == 0 || (position == 0 && file.size > 0) {
+                let cluster = self.allocate(file.last_cluster)?;
+                if file.first_cluster == 0 {
+                    file.first_cluster = cluster;
+                }
+                file.last_cluster = cluster;
+            }
+            
+            let sector = self.cluster_sector(file.last_cluster) + position / BLOCK_SIZE as u32;
+            let offset = (file.size % BLOCK_SIZE as u32) as usize;
+            if offset == 0 {
+                // Starting a new sector, nothing to keep
+                self.buffer.fill(0);
+                self.buffer_sector = NO_SECTOR;
+            } else {
+                self.read(sector)?;
+            }
+            
+            let count = (BLOCK_SIZE - offset).min(data.len() - written);
+            self.buffer[offset..offset + count].copy_from_slice(&data[written..written + count]);
+            self.write(sector)?;
+            
+            written += count;
+            file.size += count as u32;
+        }
+        
+        // Record the new size (and first cluster) in the directory entry
+        self.read(file.entry_sector)?;
+        let entry = &mut self.buffer[file.entry_offset..file.entry_offset + ENTRY_SIZE];
+        entry[20..22].copy_from_slice(&((file.first_cluster >> 16) as u16).to_le_bytes());
+        entry[26..28].copy_from_slice(&(file.first_cluster as u16).to_le_bytes());
+        entry[28..32].copy_from_slice(&file.size.to_le_bytes());
+        self.write(file.entry_sector)
+    }
+    
+    // Load a sector into the buffer, unless it is already there
+    fn read(&mut self, sector: u32) -> Result<(), Error> {
+        if sector == self.buffer_sector {
+            return Ok(());
+        }
+        
+        self.buffer_sector = NO_SECTOR;
+        if !self.card.read_block(sector, &mut self.buffer) {
+            return Err(Error::Card);
+        }
+        self.buffer_sector = sector;
+        Ok(())
+    }
+    
+    // Write the buffer to a sector
+    fn write(&mut self, sector: u32) -> Result<(), Error> {
+        if !self.card.write_block(sector, &self.buffer) {
+            self.buffer_sector = NO_SECTOR;
+            return Err(Error::Card);
+        }
+        self.buffer_sector = sector;
+        Ok(())
+    }
+    
+    // A FAT boot sector starts with a jump instruction
+    fn is_boot_sector(&self) -> bool {
+        (self.buffer[0] == 0xEB || self.buffer[0] == 0xE9)
+            && u16_at(&self.buffer, 11) == BLOCK_SIZE as u16
+            && self.buffer[510] == 0x55
+            && self.buffer[511] == 0xAA
+    }
+    
+    // Take the free cluster count and where to look for a free cluster from
+    // the FAT32 FSInfo sector. A count marked unknown (or out of range) is
+    // left to update to count.
+    fn read_fsinfo(&mut self, sector: u32) -> Result<(), Error> {
+        self.read(sector)?;
+        if !self.is_fsinfo() {
+            return Ok(());
+        }
+        self.fsinfo_sector = sector;
+        
+        let free = u32_at(&self.buffer, 488);
+        let next = u32_at(&self.buffer, 492);
+        if free != FSINFO_UNKNOWN && free <= self.cluster_count {
+            self.free_clusters = Some(free);
+        }
+        if next >= 2 && next < self.cluster_count + 2 {
+            self.next_free = next;
+        }
+        Ok(())
+    }
+    
+    // Store the free cluster count and the next free cluster in the FSInfo
+    // sector, so the next mount (or a PC) need not count
+    fn write_fsinfo(&mut self) -> Result<(), Error> {
+        if self.fsinfo_sector == 0 {
+            return Ok(());
+        }
+        
+        self.read(self.fsinfo_sector)?;
+        if !self.is_fsinfo() {
+            return Ok(());
+        }
+        let free = self.free_clusters.unwrap_or(FSINFO_UNKNOWN);
+        self.buffer[488..492].copy_from_slice(&free.to_le_bytes());
+        self.buffer[492..496].copy_from_slice(&self.next_free.to_le_bytes());
+        self.write(self.fsinfo_sector)
+    }
+    
+    fn is_fsinfo(&self) -> bool {
+        u32_at(&self.buffer, 0) == FSINFO_LEAD_SIGNATURE
+            && u32_at(&self.buffer, 484) == FSINFO_STRUCT_SIGNATURE
+    }
+    
+    // First sector of a data cluster
+    fn cluster_sector(&self, cluster: u32) -> u32 {
+        self.data_start + (cluster - 2) * self.sectors_per_cluster
+    }
+    
+    // Location of a cluster's entry in the first FAT: (sector, offset)
+    fn fat_location(&self, cluster: u32) -> (u32, usize) {
+        let offset = if self.fat32 { cluster * 4 } else { cluster * 2 };
+        (self.fat_start + offset / BLOCK_SIZE as u32, (offset % BLOCK_SIZE as u32) as usize)
+    }
+    
+    fn fat_entry(&mut self, cluster: u32) -> Result<u32, Error> {
+        let (sector, offset) = self.fat_location(cluster);
+        self.read(sector)?;
+        Ok(if self.fat32 {
+            u32_at(&self.buffer, offset) & 0x0FFF_FFFF
+        } else {
+            u16_at(&self.buffer, offset) as u32
+        })
+    }
+    
+    // Change a cluster's entry in every copy of the FAT
+    fn set_fat_entry(&mut self, cluster: u32, value: u32) -> Result<(), Error> {
+        let (sector, offset) = self.fat_location(cluster);
+        for copy in 0..self.fat_count {
+            let sector = sector + copy * self.fat_size;
+            self.read(sector)?;
+            if self.fat32 {
+                // The top 4 bits are reserved and must be kept
+                let value = (u32_at(&self.buffer, offset) & 0xF000_0000) | value;
+                self.buffer[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            } else {
+                self.buffer[offset..offset + 2].copy_from_slice(&(value as u16).to_le_bytes());
+            }
+            self.write(sector)?;
+        }
+        Ok(())
+    }
+    
+    // Also stops at damaged links so a corrupt chain cannot loop forever
+    fn is_end_of_chain(&self, value: u32) -> bool {
+        let end = if self.fat32 { 0x0FFF_FFF8 } else { 0xFFF8 };
+        value >= end || value < 2 || value >= self.cluster_count + 2
+    }
+    
+    // Claim a free cluster and link it after `previous` (0 for a new chain).
+    // Gives Busy if none turned up among the next SEARCH_CLUSTERS, and Full
+    // once every cluster has been looked at since the last allocation.
+    fn allocate(&mut self, previous: u32) -> Result<u32, Error> {
+        let end_of_chain = if self.fat32 { 0x0FFF_FFFF } else { 0xFFFF };
+        
+        for _ in 0..SEARCH_CLUSTERS {
+            if self.search_left == 0 {
+                return Err(Error::Full);
+            }
+            let cluster = self.next_free;
+            self.next_free = if cluster + 1 < self.cluster_count + 2 { cluster + 1 } else { 2 };
+            self.search_left -= 1;
+            
+            if self.fat_entry(cluster)? == 0 {
+                self.set_fat_entry(cluster, end_of_chain)?;
+                if previous != 0 {
+                    self.set_fat_entry(previous, cluster)?;
+                }
+                self.search_left = self.cluster_count;
+                
+                // A cluster already counted as free is not any more
+                if cluster - 2 < self.counted_clusters {
+                    self.counted_free = self.counted_free.saturating_sub(1);
+                }
+                if let Some(free) = self.free_clusters {
+                    self.free_clusters = Some(free.saturating_sub(1));
+                    self.write_fsinfo()?;
+                }
+                return Ok(cluster);
+            }
+        }
+        Err(Error::Busy)
+    }
+    
+    // Sector `index` of the root directory, or None past its end
+    fn root_sector(&mut self, index: u32) -> Result<Option<u32>, Error> {
+        if !self.fat32 {
+            return Ok(if index < self.root_sectors {
+                Some(self.root_start + index)
+            } else {
+                None
+            });
+        }
+        
+        // Follow the root directory's cluster chain
+        let mut cluster = self.root_cluster;
+        for _ in 0..index / self.sectors_per_cluster {
+            cluster = self.fat_entry(cluster)?;
+            if self.is_end_of_chain(cluster) {
+                return Ok(None);
+            }
+        }
+        Ok(Some(self.cluster_sector(cluster) + index % self.sectors_per_cluster))
+    }
+    
+    // Visit every root directory entry until `visit` returns true, giving
+    // the location (sector, offset) of that entry
+    fn scan_root<F: FnMut(&[u8]) -> bool>(&mut self, mut visit: F) -> Result<Option<(u32, usize)>, Error> {
+        for index in 0..DIRECTORY_SECTORS {
+            watchdog::kick();
+            let sector = match self.root_sector(index)? {
+                Some(sector) => sector,
+                None => break,
+            };
+            self.read(sector)?;
+            for offset in (0..BLOCK_SIZE).step_by(ENTRY_SIZE) {
+                if visit(&self.buffer[offset..offset + ENTRY_SIZE]) {
+                    return Ok(Some((sector, offset)));
+                }
+            }
+        }
+        Ok(None)
+    }
+    
+    // Add an empty cluster to a full FAT32 root directory; the FAT16 root
+    // directory has a fixed size
+    fn extend_root(&mut self) -> Result<(u32, usize), Error> {
+        if !self.fat32 {
+            return Err(Error::Full);
+        }
+        
+        let mut last = self.root_cluster;
+        let mut clusters = 1;
+        loop {
+            watchdog::kick();
+            let next = self.fat_entry(last)?;
+            if self.is_end_of_chain(next) {
+                break;
+            }
+            if clusters * self.sectors_per_cluster >= DIRECTORY_SECTORS {
+                return Err(Error::Full); // As large as a directory gets
+            }
+            last = next;
+            clusters += 1;
+        }
+        
+        let cluster = self.allocate(last)?;
+        let first_sector = self.cluster_sector(cluster);
+        self.buffer.fill(0);
+        for sector in first_sector..first_sector + self.sectors_per_cluster {
+            self.write(sector)?;
+        }
+        Ok((first_sector, 0))
+    }
+}
+
+// A regular file's directory entry
+fn is_file(entry: &[u8]) -> bool {
+    entry[0] != 0 && entry[0] != DELETED && entry[11] & ATTRIBUTE_SKIP == 0
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
@@ -0,0 +1,94 @@
+// Text composed in a fixed size buffer
+//
+// Used for the SD card's CSV rows, the LCD dashboard's lines and the numbers
+// in the Wi-Fi uplink's posts. Text past the end of the buffer is dropped,
+// and the rest of the buffer reads as spaces, as a display line needs.
+
+pub struct Buffer<const N: usize> {
+    text: [u8; N],
+    length: usize,
+}
+
+impl<const N: usize> Buffer<N> {
+    pub fn new() -> Self {
+        Buffer {
+            text: [b' '; N],
+            length: 0,
+        }
+    }
+    
+    pub fn push(&mut self, c: u8) {
+        if self.length < N {
+            self.text[self.length] = c;
+            self.length += 1;
+        }
+    }
+    
+    pub fn push_str(&mut self, s: &str) {
+        for c in s.bytes() {
+            self.push(c);
+        }
+    }
+    
+    // Continue at a column, padding with spaces
+    #[cfg(feature = "display-lcd")]
+    pub fn pad_to(&mut self, column: usize) {
+        while self.length < column {
+            self.push(b' ');
+        }
+    }
+    
+    pub fn push_integer(&mut self, mut value: u32) {
+        let mut digits = [0u8; 10];
+        let mut count = 0;
+        loop {
+            digits[count] = b'0' + (value % 10) as u8;
+            count += 1;
+            value /= 10;
+            if value == 0 {
+                break;
+            }
+        }
+        while count > 0 {
+            count -= 1;
+            self.push(digits[count]);
+        }
+    }
+    
+    // Push a number rounded to the given decimal places
+    pub fn push_fixed(&mut self, value: f32, decimal_places: u8) {
+        let mut scale = 1;
+        for _ in 0..decimal_places {
+            scale *= 10;
+        }
+        
+        let mut scaled = libm::roundf(value * scale as f32);
+        if scaled < 0.0 {
+            self.push(b'-');
+            scaled = -scaled;
+        }
+        
+        let scaled = scaled as u32;
+        self.push_integer(scaled / scale);
+        if decimal_places > 0 {
+            self.push(b'.');
+            let mut divisor = scale / 10;
+            while divisor > 0 {
+                self.push(b'0' + ((scaled / divisor) % 10) as u8);
+                divisor /= 10;
+            }
+        }
+    }
+    
+    // The text pushed so far
+    #[cfg(any(feature = "sd-logger", feature = "wifi"))]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.text[..self.length]
+    }
+    
+    // The whole buffer, padded with spaces
+    #[cfg(feature = "display-lcd")]
+    pub fn padded(&self) -> &[u8; N] {
+        &self.text
+    }
+}
@@ -0,0 +1,268 @@
+use ruduino::Pin;
+use ruduino::cores::current::port::{D3, D4};
+use ruduino::delay;
+use ruduino::interrupt::without_interrupts;
+use core::ptr::{read_volatile, write_volatile};
+use crate::display::{SegmentDisplay, MAX_BRIGHTNESS, PATTERN_ALL_ON};
+use crate::timer;
+#[cfg(feature = "display-spi")]
+use crate::spi;
+
+// 74HC595 shift register driving a 4-digit common cathode display, with the
+// digits multiplexed from the Timer0 interrupt, which also counts the
+// milliseconds for the timer module
+
+// Display pins for the bit-banged shift register (default)
+#[cfg(not(feature = "display-spi"))]
+mod pins {
+    use ruduino::cores::current::port::{B0, B1, B2, B3, B4};
+    
+    pub type DataPin = B0;    // 74HC595 DS
+    pub type LatchPin = B1;   // 74HC595 ST_CP
+    pub type ClockPin = B2;   // 74HC595 SH_CP
+    pub type Digit1Pin = B3;
+    pub type Digit2Pin = B4;
+}
+
+// Display pins for the hardware SPI shift register: data and clock move to
+// MOSI (B3) and SCK (B5), so the first two digits move to the freed B0/B1
+#[cfg(feature = "display-spi")]
+mod pins {
+    use ruduino::cores::current::port::{B0, B1, B2};
+    
+    pub type LatchPin = B2;   // 74HC595 ST_CP (also SPI SS, kept as output)
+    pub type Digit1Pin = B0;
+    pub type Digit2Pin = B1;
+}
+
+use pins::*;
+type Digit3Pin = D3;
+type Digit4Pin = D4;
+
+// Memory addresses for Timer0 registers
+const TCCR0A: *mut u8 = 0x44 as *mut u8;  // Timer/Counter0 Control Register A
+const TCCR0B: *mut u8 = 0x45 as *mut u8;  // Timer/Counter0 Control Register B
+const OCR0A: *mut u8 = 0x47 as *mut u8;   // Timer/Counter0 Output Compare Register A
+const OCR0B: *mut u8 = 0x48 as *mut u8;   // Timer/Counter0 Output Compare Register B
+const TIMSK0: *mut u8 = 0x6E as *mut u8;  // Timer/Counter0 Interrupt Mask Register
+
+// Timer0 bits
+const WGM01: u8 = 1 << 1;   // CTC mode (TCCR0A)
+const CS01: u8 = 1 << 1;    // Prescaler 64 (TCCR0B, with CS00)
+const CS00: u8 = 1 << 0;
+const OCIE0A: u8 = 1 << 1;  // Compare match A interrupt enable (TIMSK0)
+const OCIE0B: u8 = 1 << 2;  // Compare match B interrupt enable (TIMSK0)
+
+// Refresh one digit per millisecond: 16MHz / 64 / (249 + 1) = 1kHz,
+// so the whole display is redrawn 250 times a second
+const REFRESH_COMPARE: u8 = 249;
+
+// Brightness levels 1 to MAX_BRIGHTNESS - 1: the Timer0 count at which the
+// lit digit is switched off early by compare match B. Each step roughly
+// doubles the on-time since perceived brightness is logarithmic.
+const BRIGHTNESS_COMPARE: [u8; 7] = [2, 5, 11, 23, 47, 95, 170];
+
+// Frame read by the refresh interrupt
+static mut FRAME: [u8; 4] = [0, 0, 0, 0];
+static mut CURRENT_DIGIT: u8 = 0;
+
+pub struct Hc595Display;
+
+impl Hc595Display {
+    pub fn new() -> Self {
+        Hc595Display
+    }
+}
+
+impl SegmentDisplay for Hc595Display {
+    // Set up the pins, test the segments and start the refresh interrupt
+    fn initialize(&mut self) {
+        // Set up the shift register interface
+        initialize_shift_register();
+        
+        // Set digit pins as outputs
+        Digit1Pin::set_output();
+        Digit2Pin::set_output();
+        Digit3Pin::set_output();
+        Digit4Pin::set_output();
+        
+        // Initialize digit pins to high (inactive for common cathode)
+        all_digits_off();
+        
+        // Add a brief delay after initialization
+        delay::delay_ms(50);
+        
+        // Test each digit individually before the interrupt takes over the pins
+        test_all_segments();
+        
+        unsafe {
+            CURRENT_DIGIT = 0;
+            
+            // Timer0 in CTC mode with prescaler 64, compare match every 1ms
+            write_volatile(TCCR0A, WGM01);
+            write_volatile(OCR0A, REFRESH_COMPARE);
+            write_volatile(TCCR0B, CS01 | CS00);
+            
+            // Enable Timer0 compare match A interrupt (global interrupts are
+            // enabled once everything is initialized)
+            write_volatile(TIMSK0, read_volatile(TIMSK0) | OCIE0A);
+        }
+    }
+    
+    // Hand a complete frame to the refresh interrupt in one go, so it never
+    // shows a half-written frame
+    fn show(&mut self, frame: [u8; 4]) {
+        without_interrupts(|| unsafe {
+            FRAME = frame;
+        });
+    }
+    
+    // Dimming shortens the time each digit is lit within its 1ms multiplex slot
+    fn set_brightness(&mut self, level: u8) {
+        unsafe {
+            if level >= MAX_BRIGHTNESS {
+                // Full brightness: digits stay lit for the whole slot
+                write_volatile(TIMSK0, read_volatile(TIMSK0) & !OCIE0B);
+            } else {
+                let level = if level == 0 { 1 } else { level };
+                write_volatile(OCR0B, BRIGHTNESS_COMPARE[level as usize - 1]);
+                write_volatile(TIMSK0, read_volatile(TIMSK0) | OCIE0B);
+            }
+        }
+    }
+}
+
+// Test function that manually tests all display segments
+fn test_all_segments() {
+    // Test all digits with all segments lit
+    for digit in 0..4 {
+        // Turn off all digits
+        all_digits_off();
+        
+        // Send all segments on pattern
+        shift_out(PATTERN_ALL_ON);
+        
+        // Enable current digit only
+        digit_on(digit);
+        
+        // Hold for a visible time
+        delay::delay_ms(200);
+    }
+    
+    // Turn off all digits
+    all_digits_off();
+}
+
+// Turn off all digits (high is inactive for common cathode)
+fn all_digits_off() {
+    Digit1Pin::set_high();
+    Digit2Pin::set_high();
+    Digit3Pin::set_high();
+    Digit4Pin::set_high();
+}
+
+// Enable a single digit position
+fn digit_on(digit: u8) {
+    match digit {
+        0 => Digit1Pin::set_low(),
+        1 => Digit2Pin::set_low(),
+        2 => Digit3Pin::set_low(),
+        3 => Digit4Pin::set_low(),
+        _ => {}
+    }
+}
+
+// Set up the bit-banged shift register pins
+#[cfg(not(feature = "display-spi"))]
+fn initialize_shift_register() {
+    // Set pins as outputs
+    LatchPin::set_output();
+    ClockPin::set_output();
+    DataPin::set_output();
+    
+    // Initialize pins to low
+    LatchPin::set_low();
+    ClockPin::set_low();
+    DataPin::set_low();
+}
+
+// Set up the hardware SPI shift register interface
+#[cfg(feature = "display-spi")]
+fn initialize_shift_register() {
+    LatchPin::set_output();
+    LatchPin::set_low();
+    spi::initialize();
+}
+
+// Shift out data to the 74HC595
+//
+// Runs inside the refresh interrupt, so no delays: the 74HC595 needs ~20ns
+// pulses and each pin write already takes a 62.5ns cycle at 16MHz.
+#[cfg(not(feature = "display-spi"))]
+fn shift_out(data: u8) {
+    // Set latch low before shifting
+    LatchPin::set_low();
+    
+    // Shift out 8 bits MSB first
+    for i in (0..8).rev() {
+        // Set data bit
+        if (data & (1 << i)) != 0 {
+            DataPin::set_high();
+        } else {
+            DataPin::set_low();
+        }
+        
+        // Clock pulse
+        ClockPin::set_high();
+        ClockPin::set_low();
+    }
+    
+    // Set latch high to display
+    LatchPin::set_high();
+}
+
+// Shift out data to the 74HC595 over hardware SPI (about 1µs per byte)
+#[cfg(feature = "display-spi")]
+fn shift_out(data: u8) {
+    LatchPin::set_low();
+    spi::transfer(data);
+    LatchPin::set_high();
+}
+
+/// Timer0 compare match A interrupt handler, refreshes one digit per call
+#[no_mangle]
+pub extern "avr-interrupt" fn __vector_14() {
+    timer::tick();
+    refresh_next_digit();
+}
+
+/// Timer0 compare match B interrupt handler, ends the on-time when dimmed
+#[no_mangle]
+pub extern "avr-interrupt" fn __vector_15() {
+    all_digits_off();
+}
+
+// Show the next digit of the frame (called from the timer interrupt)
+fn refresh_next_digit() {
+    // Leave the current digit lit while the SD card has the bus
+    #[cfg(all(feature = "display-spi", feature = "sd-logger"))]
+    {
+        if spi::is_claimed() {
+            return;
+        }
+    }
+    
+    unsafe {
+        // Turn off all digits first
+        all_digits_off();
+        
+        // Shift out the pattern for the current position, decimal point included
+        shift_out(FRAME[CURRENT_DIGIT as usize]);
+        
+        // Enable only the current digit position
+        digit_on(CURRENT_DIGIT);
+        
+        // Move to next digit position
+        CURRENT_DIGIT = (CURRENT_DIGIT + 1) % 4;
+    }
+}
@@ -0,0 +1,181 @@
+use crate::eeprom;
+use crate::record_ring::{self, RecordRing};
+use crate::uart;
+use crate::sensor_manager::SensorValues;
+
+// History log in EEPROM
+//
+// The minimum, maximum and average temperature and pH over each interval
+// are stored as one record in a ring buffer, overwriting the oldest, so the
+// last day or so can be read back over serial even without an SD card.
+//
+// Record layout (in a record_ring slot):
+//
+//   0-1    uptime at the end of the interval (minutes, wraps after 45 days)
+//   2-7    temperature min, max, average (i16, 0.1 °C; NO_READING if none)
+//   8-13   pH min, max, average (u16, 0.01)
+//
+// Wear: every slot is written once per trip around the ring and unchanged
+// bytes are skipped, so with the default interval each cell sees about one
+// write a day and even MIN_INTERVAL_MIN stays far below the rated 100,000
+// writes over the life of the device.
+
+// Interval limits (minutes)
+pub const DEFAULT_INTERVAL_MIN: u16 = 30;
+pub const MIN_INTERVAL_MIN: u16 = 5;
+pub const MAX_INTERVAL_MIN: u16 = 1440;
+
+const DATA_SIZE: usize = 14;
+const RECORD_SIZE: usize = DATA_SIZE + record_ring::OVERHEAD;
+
+// Stored when no temperature could be read during the interval
+const NO_READING: i16 = i16::MIN;
+
+const CSV_HEADER: &str = "uptime_min,temp_min,temp_max,temp_avg,ph_min,ph_max,ph_avg\r\n";
+
+// Running min/max/average of one quantity
+struct Summary {
+    min: f32,
+    max: f32,
+    sum: f32,
+    count: u16,
+}
+
+impl Summary {
+    fn new() -> Self {
+        Summary {
+            min: f32::MAX,
+            max: f32::MIN,
+            sum: 0.0,
+            count: 0,
+        }
+    }
+    
+    fn add(&mut self, value: f32) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.count = self.count.saturating_add(1);
+    }
+    
+    // Min, max and average scaled to fixed point
+    fn scaled(&self, scale: f32) -> [i16; 3] {
+        if self.count == 0 {
+            return [NO_READING; 3];
+        }
+        let average = self.sum / self.count as f32;
+        [
+            libm::roundf(self.min * scale) as i16,
+            libm::roundf(self.max * scale) as i16,
+            libm::roundf(average * scale) as i16,
+        ]
+    }
+}
+
+pub struct History {
+    interval_ms: u64,
+    interval_start: u64,
+    temperature: Summary,
+    ph: Summary,
+    ring: RecordRing<RECORD_SIZE>,
+}
+
+impl History {
+    pub fn new(interval_min: u16) -> Self {
+        History {
+            interval_ms: interval_min as u64 * 60_000,
+            interval_start: 0,
+            temperature: Summary::new(),
+            ph: Summary::new(),
+            ring: RecordRing::new(eeprom::HISTORY_ADDRESS, eeprom::HISTORY_SIZE),
+        }
+    }
+    
+    // Find where the newest record is, to carry on after it
+    pub fn initialize(&mut self) {
+        self.ring.initialize();
+    }
+    
+    // Change the interval, starting a new one
+    pub fn set_interval(&mut self, interval_min: u16, current_time: u64) {
+        self.interval_ms = interval_min as u64 * 60_000;
+        self.interval_start = current_time;
+        self.temperature = Summary::new();
+        self.ph = Summary::new();
+    }
+    
+    // Include the readings of a completed measurement cycle
+    pub fn add(&mut self, values: &SensorValues) {
+        if !values.temperature_fault {
+            self.temperature.add(values.temperature);
+        }
+        self.ph.add(values.ph);
+    }
+    
+    // Close the interval when it is over and write records in the background
+    pub fn update(&mut self, current_time: u64) {
+        if self.ring.is_writing() {
+            self.ring.update();
+            return;
+        }
+        
+        if current_time >= self.interval_start + self.interval_ms {
+            if self.ph.count > 0 {
+                self.start_record(current_time);
+            }
+            self.interval_start = current_time;
+            self.temperature = Summary::new();
+            self.ph = Summary::new();
+        }
+    }
+    
+    // Send all records over serial as CSV, oldest first
+    pub fn dump(&self) {
+        uart::send_string(CSV_HEADER);
+        for age in (0..self.ring.slot_count()).rev() {
+            let mut record = [0u8; DATA_SIZE];
+            if !self.ring.read(age, &mut record) {
+                continue;
+            }
+            
+            uart::send_integer(u16_at(&record, 0), 10);
+            for field in 0..3 {
+                uart::send_byte(b',');
+                send_temperature(u16_at(&record, 2 + field * 2) as i16);
+            }
+            for field in 0..3 {
+                uart::send_byte(b',');
+                uart::send_decimal(u16_at(&record, 8 + field * 2), 2);
+            }
+            uart::send_string("\r\n");
+        }
+    }
+    
+    // Encode the finished interval and queue it for writing
+    fn start_record(&mut self, current_time: u64) {
+        let mut record = [0u8; DATA_SIZE];
+        record[0..2].copy_from_slice(&((current_time / 60_000) as u16).to_le_bytes());
+        for (field, value) in self.temperature.scaled(10.0).iter().enumerate() {
+            record[2 + field * 2..4 + field * 2].copy_from_slice(&value.to_le_bytes());
+        }
+        for (field, value) in self.ph.scaled(100.0).iter().enumerate() {
+            record[8 + field * 2..10 + field * 2].copy_from_slice(&value.to_le_bytes());
+        }
+        self.ring.start(&record);
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+// Temperature in tenths of a degree, empty if there was no reading
+fn send_temperature(tenths: i16) {
+    if tenths == NO_READING {
+        return;
+    }
+    if tenths < 0 {
+        uart::send_byte(b'-');
+    }
+    uart::send_decimal(tenths.unsigned_abs(), 1);
+}
@@ -0,0 +1,111 @@
+use ruduino::delay;
+use crate::twi;
+
+// HD44780 character LCD behind a PCF8574 I2C backpack
+//
+// The backpack drives the LCD in 4-bit mode. Each byte is sent as two
+// nibbles, each latched by pulsing EN, all in one I2C transaction.
+
+// LCD size and backpack address (0x27 for PCF8574, 0x3F for PCF8574A)
+pub const COLUMNS: usize = 20;
+pub const ROWS: usize = 4;
+pub const ADDRESS: u8 = 0x27;
+
+// PCF8574 pins
+const RS: u8 = 1 << 0;         // Register select (1 = data)
+const EN: u8 = 1 << 2;         // Enable, latches on the falling edge
+const BACKLIGHT: u8 = 1 << 3;  // Backlight transistor
+
+// HD44780 commands
+const CLEAR_DISPLAY: u8 = 0x01;
+const ENTRY_MODE_INCREMENT: u8 = 0x06;
+const DISPLAY_ON: u8 = 0x0C;            // Display on, cursor and blink off
+const FUNCTION_SET_4BIT_2LINE: u8 = 0x28;
+const SET_CGRAM_ADDRESS: u8 = 0x40;
+const SET_DDRAM_ADDRESS: u8 = 0x80;
+
+// DDRAM address of the start of each row
+const ROW_OFFSETS: [u8; 4] = [0x00, 0x40, COLUMNS as u8, 0x40 + COLUMNS as u8];
+
+pub struct Lcd {
+    backlight: u8, // BACKLIGHT when on, 0 when off
+}
+
+impl Lcd {
+    pub fn new() -> Self {
+        Lcd {
+            backlight: BACKLIGHT,
+        }
+    }
+    
+    // Run the 4-bit initialization sequence and clear the display
+    pub fn initialize(&mut self) {
+        twi::initialize();
+        
+        // Wait for the LCD to power up
+        delay::delay_ms(50);
+        
+        // Force 8-bit mode three times, then switch to 4-bit mode
+        self.write_nibble(0x03, 0);
+        delay::delay_ms(5);
+        self.write_nibble(0x03, 0);
+        delay::delay_us(150);
+        self.write_nibble(0x03, 0);
+        delay::delay_us(150);
+        self.write_nibble(0x02, 0);
+        
+        self.command(FUNCTION_SET_4BIT_2LINE);
+        self.command(DISPLAY_ON);
+        self.command(ENTRY_MODE_INCREMENT);
+        self.clear();
+    }
+    
+    // Clear the display and return the cursor home
+    pub fn clear(&mut self) {
+        self.command(CLEAR_DISPLAY);
+        delay::delay_ms(2);
+    }
+    
+    // Switch the backlight on or off
+    pub fn set_backlight(&mut self, on: bool) {
+        self.backlight = if on { BACKLIGHT } else { 0 };
+        twi::write(ADDRESS, &[self.backlight]);
+    }
+    
+    // Define one of the eight custom characters (codes 0-7) from 5x8 rows
+    pub fn create_char(&mut self, code: u8, rows: &[u8; 8]) {
+        self.command(SET_CGRAM_ADDRESS | ((code & 0x07) << 3));
+        for &row in rows {
+            self.write_byte(row, RS);
+        }
+    }
+    
+    // Write characters starting at a column and row
+    pub fn write_at(&mut self, column: usize, row: usize, text: &[u8]) {
+        self.command(SET_DDRAM_ADDRESS | (ROW_OFFSETS[row % 4] + column as u8));
+        for &c in text {
+            self.write_byte(c, RS);
+        }
+    }
+    
+    // Send an instruction
+    fn command(&mut self, command: u8) {
+        self.write_byte(command, 0);
+    }
+    
+    // Send a byte as two nibbles, high nibble first
+    fn write_byte(&mut self, byte: u8, mode: u8) {
+        let high = (byte & 0xF0) | mode | self.backlight;
+        let low = ((byte << 4) & 0xF0) | mode | self.backlight;
+        twi::write(ADDRESS, &[high | EN, high, low | EN, low]);
+        
+        // Most instructions take 37µs, already covered by the I2C transfer
+        // except for clear, which waits on its own
+    }
+    
+    // Send a single nibble (initialization only)
+    fn write_nibble(&mut self, nibble: u8, mode: u8) {
+        let data = (nibble << 4) | mode | self.backlight;
+        twi::write(ADDRESS, &[data | EN, data]);
+    }
+}
@@ -0,0 +1,215 @@
+use crate::alarm;
+use crate::fat::{Error, File, Volume};
+use crate::format::Buffer;
+use crate::sensor_manager::SensorValues;
+
+// Data logger writing a CSV row per measurement cycle to an SD card
+//
+// Each power up starts a new file, numbered after the highest LOGnnnnn.CSV
+// already on the card, and a new file is started after every 24 hours of
+// uptime, as counted by the millisecond timer. Rotation follows the uptime,
+// not the calendar: files do not start at midnight, even once a host has set
+// the clock.
+//
+// If the card is removed or a write fails, rows are dropped and the card is
+// tried again every RETRY_INTERVAL_MS; logging then carries on in the same
+// file.
+
+// Start a new file this often (ms)
+const FILE_PERIOD_MS: u64 = 86_400_000;
+
+// Time between attempts to mount a missing card (ms)
+const RETRY_INTERVAL_MS: u64 = 10_000;
+
+const FILE_PREFIX: &[u8; 3] = b"LOG";
+const FILE_EXTENSION: &[u8; 3] = b"CSV";
+const HEADER: &str = "uptime_s,temp_c,ph,ph_mv,vcc_v,temp_stable,ph_stable,alarm\r\n";
+
+// Longest row: every field at its widest
+const ROW_SIZE: usize = 64;
+
+// Logger state, as reported over serial
+#[derive(PartialEq, Copy, Clone)]
+pub enum Status {
+    NoCard,      // Missing, not responding, or not yet mounted
+    Ready,
+    Full,        // No space left, rows are dropped
+    Unsupported, // Card not formatted FAT16 or FAT32
+}
+
+pub struct SdLogger {
+    volume: Volume,
+    file: Option<File>,
+    status: Status,
+    file_number: Option<u32>, // Chosen when the card is first mounted
+    file_start_time: u64,
+    last_attempt_time: Option<u64>,
+    errors: u16,              // Failed mounts and writes since power up
+}
+
+impl SdLogger {
+    pub fn new() -> Self {
+        SdLogger {
+            volume: Volume::new(),
+            file: None,
+            status: Status::NoCard,
+            file_number: None,
+            file_start_time: 0,
+            last_attempt_time: None,
+            errors: 0,
+        }
+    }
+    
+    pub fn status(&self) -> Status {
+        self.status
+    }
+    
+    // Free space in whole megabytes (0 without a card), or None while it is
+    // still being counted
+    pub fn free_mb(&self) -> Option<u32> {
+        if self.status == Status::NoCard || self.status == Status::Unsupported {
+            return Some(0);
+        }
+        self.volume.free_bytes().map(|bytes| (bytes / 1_048_576) as u32)
+    }
+    
+    pub fn errors(&self) -> u16 {
+        self.errors
+    }
+    
+    // Append a row with the latest readings
+    pub fn log(&mut self, values: &SensorValues, current_time: u64) {
+        // Rotate every 24 hours of uptime
+        if current_time >= self.file_start_time + FILE_PERIOD_MS {
+            self.file_start_time += FILE_PERIOD_MS;
+            if let Some(number) = self.file_number {
+                self.file_number = Some(number + 1);
+                self.file = None;
+            }
+        }
+        
+        if self.status != Status::Ready && !self.mount(current_time) {
+            return;
+        }
+        
+        let row = format_row(values, current_time);
+        if let Err(error) = self.write(row.as_bytes()) {
+            self.fail(error);
+        }
+    }
+    
+    // Carry on counting the free space; call every main loop iteration
+    pub fn update(&mut self) {
+        if self.status == Status::Ready {
+            if let Err(error) = self.volume.update() {
+                self.fail(error);
+            }
+        }
+    }
+    
+    // Try to mount the card if the retry interval has passed
+    fn mount(&mut self, current_time: u64) -> bool {
+        if let Some(last_time) = self.last_attempt_time {
+            if current_time < last_time + RETRY_INTERVAL_MS {
+                return false;
+            }
+        }
+        self.last_attempt_time = Some(current_time);
+        
+        let result = self.volume.mount().and_then(|_| {
+            if self.file_number.is_none() {
+                let highest = self.volume.highest_number(FILE_PREFIX, FILE_EXTENSION)?;
+                self.file_number = Some(highest + 1);
+            }
+            Ok(())
+        });
+        
+        match result {
+            Ok(()) => {
+                self.status = Status::Ready;
+                self.file = None; // Reopen, the card may have been changed
+                true
+            },
+            Err(error) => {
+                self.fail(error);
+                false
+            }
+        }
+    }
+    
+    // Write a row, opening the file (with a header if it is new) first
+    fn write(&mut self, row: &[u8]) -> Result<(), Error> {
+        let mut file = match self.file.take() {
+            Some(file) => file,
+            None => {
+                let name = file_name(self.file_number.unwrap_or(0));
+                let mut file = self.volume.open(&name)?;
+                if file.size == 0 {
+                    self.volume.append(&mut file, HEADER.as_bytes())?;
+                }
+                file
+            }
+        };
+        
+        self.volume.append(&mut file, row)?;
+        self.file = Some(file);
+        Ok(())
+    }
+    
+    fn fail(&mut self, error: Error) {
+        self.file = None; // Reopen, dropping what was written of the row
+        self.status = match error {
+            Error::Card => Status::NoCard,
+            Error::Unsupported => Status::Unsupported,
+            Error::Full => Status::Full,
+            // The search for a free cluster carries on with the next row
+            Error::Busy => return,
+        };
+        self.errors = self.errors.saturating_add(1);
+    }
+}
+
+// Directory name of log file `number`, e.g. "LOG00042CSV"
+fn file_name(number: u32) -> [u8; 11] {
+    let mut name = [b' '; 11];
+    name[0..3].copy_from_slice(FILE_PREFIX);
+    let mut remaining = number % 100_000;
+    for position in (3..8).rev() {
+        name[position] = b'0' + (remaining % 10) as u8;
+        remaining /= 10;
+    }
+    name[8..11].copy_from_slice(FILE_EXTENSION);
+    name
+}
+
+// A CSV row being composed
+type Row = Buffer<ROW_SIZE>;
+
+// 1 or 0 for a CSV flag column
+fn flag(flag: bool) -> u8 {
+    if flag { b'1' } else { b'0' }
+}
+
+fn format_row(values: &SensorValues, current_time: u64) -> Row {
+    let mut row = Row::new();
+    row.push_integer((current_time / 1000) as u32);
+    row.push(b',');
+    // Left empty while the probe has failed, rather than the last reading
+    if !values.temperature_fault {
+        row.push_fixed(values.temperature, 1);
+    }
+    row.push(b',');
+    row.push_fixed(values.ph, 2);
+    row.push(b',');
+    row.push_fixed(values.ph_mv, 1);
+    row.push(b',');
+    row.push_fixed(values.supply_voltage, 2);
+    row.push(b',');
+    row.push(flag(values.temperature_stable));
+    row.push(b',');
+    row.push(flag(values.ph_stable));
+    row.push(b',');
+    row.push_integer(alarm::condition(values).code() as u32);
+    row.push_str("\r\n");
+    row
+}
@@ -2,67 +2,511 @@
 #![no_main]
 #![feature(abi_avr_interrupt)]
 #![feature(asm_experimental_arch)]
+// The Modbus and Wi-Fi builds have no text interface, leaving the history
+// dump, statistics report and text output helpers unused
+#![cfg_attr(any(feature = "modbus", feature = "wifi"), allow(dead_code))]
 
 use ruduino::delay;
 
 mod display;
+#[cfg(not(any(feature = "display-tm1637", feature = "display-max7219", feature = "display-lcd")))]
+mod hc595;
+#[cfg(feature = "display-tm1637")]
+mod tm1637;
+#[cfg(feature = "display-max7219")]
+mod max7219;
+#[cfg(feature = "display-lcd")]
+mod twi;
+#[cfg(feature = "display-lcd")]
+mod lcd;
+#[cfg(feature = "display-lcd")]
+mod dashboard;
+#[cfg(any(feature = "display-lcd", feature = "sd-logger", feature = "wifi"))]
+mod format;
 mod ph;
 mod adc;
 mod temperature;
 mod sensor_manager;
+#[cfg(not(feature = "display-lcd"))]
 mod display_controller;
 mod air;
+mod actuators;
+mod uart;
+#[cfg(not(any(feature = "modbus", feature = "wifi")))]
+mod telemetry;
+mod stability;
+mod statistics;
+mod alarm;
+#[cfg(not(any(feature = "modbus", feature = "wifi")))]
+mod command;
+#[cfg(not(any(feature = "modbus", feature = "wifi")))]
+mod protocol;
+#[cfg(feature = "modbus")]
+mod modbus;
+#[cfg(feature = "modbus")]
+mod modbus_request;
+#[cfg(feature = "wifi")]
+mod wifi;
+mod config;
+mod clock;
+mod timer;
+mod watchdog;
+mod stack;
+mod eeprom;
+mod settings;
+mod buttons;
+#[cfg(feature = "rotary-encoder")]
+mod encoder;
+mod menu;
+mod history;
+mod checkpoint;
+mod record_ring;
+mod crc;
+#[cfg(any(feature = "display-spi", feature = "display-max7219", feature = "sd-logger"))]
+mod spi;
+#[cfg(feature = "sd-logger")]
+mod sd;
+#[cfg(feature = "sd-logger")]
+mod fat;
+#[cfg(feature = "sd-logger")]
+mod logger;
 
 use sensor_manager::SensorManager;
-use display_controller::DisplayController;
+#[cfg(not(feature = "display-lcd"))]
+use display_controller::{DisplayController, DisplayMode, Page, LABEL_TIME_MS, STATISTIC_TIME_MS};
+#[cfg(not(any(feature = "modbus", feature = "wifi")))]
+use command::{Command, CommandReader, Source};
+#[cfg(not(any(feature = "modbus", feature = "wifi")))]
+use protocol::Event;
+#[cfg(feature = "modbus")]
+use modbus::{ModbusSlave, Request};
+#[cfg(not(feature = "wifi"))]
+use config::ConfigKey;
+use buttons::Buttons;
+use menu::{Action, Menu};
+use settings::Settings;
+use history::History;
+use checkpoint::Checkpoint;
+use core::arch::asm;
+
+// Display hardware, chosen with cargo features (74HC595 multiplex by default)
+#[cfg(not(any(feature = "display-tm1637", feature = "display-max7219", feature = "display-lcd")))]
+type Driver = hc595::Hc595Display;
+#[cfg(feature = "display-tm1637")]
+type Driver = tm1637::Tm1637Display;
+#[cfg(feature = "display-max7219")]
+type Driver = max7219::Max7219Display;
+
+// 7-segment displays cycle through pages, the character LCD shows a dashboard
+#[cfg(not(feature = "display-lcd"))]
+type Display = DisplayController<Driver>;
+#[cfg(feature = "display-lcd")]
+type Display = dashboard::LcdDashboard;
+
+#[cfg(any(
+    all(feature = "display-tm1637", feature = "display-max7219"),
+    all(feature = "display-tm1637", feature = "display-lcd"),
+    all(feature = "display-max7219", feature = "display-lcd"),
+))]
+compile_error!("Select only one display driver feature");
+
+// The SD card needs the SPI bus (which the bit-banged 74HC595 occupies) and A3
+#[cfg(all(
+    feature = "sd-logger",
+    not(any(feature = "display-spi", feature = "display-tm1637", feature = "display-max7219", feature = "display-lcd"))
+))]
+compile_error!("sd-logger needs display-spi or a display module that leaves the SPI pins free");
+#[cfg(all(feature = "sd-logger", feature = "rotary-encoder"))]
+compile_error!("sd-logger and rotary-encoder both use A3");
+#[cfg(all(feature = "modbus-de", feature = "display-lcd"))]
+compile_error!("modbus-de and display-lcd both use A4");
+#[cfg(all(feature = "modbus", feature = "wifi"))]
+compile_error!("modbus and wifi both need the serial port");
 
 // Constants for timing
-const DISPLAY_REFRESH_DELAY_MS: u64 = 2; // Delay between display refreshes (ms)
+const LOOP_DELAY_MS: u64 = 2; // Delay between main loop iterations (ms)
+#[cfg(not(feature = "display-lcd"))]
 const DISPLAY_TIME_PER_READING: u64 = 3000; // Display each reading for 3 seconds
+#[cfg(not(feature = "display-lcd"))]
+const DISPLAY_TIME_PER_STATUS: u64 = 2000; // Display each status page for 2 seconds
+#[cfg(not(feature = "display-lcd"))]
+const DISPLAY_TIME_PER_MESSAGE: u64 = 7000; // Long enough for a message to scroll through
+#[cfg(not(feature = "display-lcd"))]
+const DISPLAY_TIME_PER_STATISTICS: u64 = LABEL_TIME_MS + 3 * STATISTIC_TIME_MS; // Low, high and mean
+
+// Pages the display cycles through, each with its label and value
+#[cfg(not(feature = "display-lcd"))]
+static DISPLAY_PAGES: &[Page] = &[
+    Page { mode: DisplayMode::Temperature, duration_ms: DISPLAY_TIME_PER_READING },
+    Page { mode: DisplayMode::PH, duration_ms: DISPLAY_TIME_PER_READING },
+    Page { mode: DisplayMode::ElectrodeMv, duration_ms: DISPLAY_TIME_PER_STATUS },
+    Page { mode: DisplayMode::SupplyVoltage, duration_ms: DISPLAY_TIME_PER_STATUS },
+    Page { mode: DisplayMode::AerationCountdown, duration_ms: DISPLAY_TIME_PER_STATUS },
+    Page { mode: DisplayMode::Uptime, duration_ms: DISPLAY_TIME_PER_STATUS },
+    Page { mode: DisplayMode::Alarm, duration_ms: DISPLAY_TIME_PER_MESSAGE },
+    Page { mode: DisplayMode::TemperatureDay, duration_ms: DISPLAY_TIME_PER_STATISTICS },
+    Page { mode: DisplayMode::PHDay, duration_ms: DISPLAY_TIME_PER_STATISTICS },
+    Page { mode: DisplayMode::Reset, duration_ms: DISPLAY_TIME_PER_MESSAGE },
+    #[cfg(feature = "wifi")]
+    Page { mode: DisplayMode::Wifi, duration_ms: DISPLAY_TIME_PER_MESSAGE },
+];
+
+#[cfg(not(any(feature = "modbus", feature = "wifi")))]
+const TELEMETRY_INTERVAL_MS: u64 = 5000; // Send a telemetry line every 5 seconds
 
 #[no_mangle]
 pub extern "C" fn main() {
+    // Find out why we reset and start the watchdog before anything can hang,
+    // then put the outputs in their safe states before the reset is counted
+    // in EEPROM
+    watchdog::initialize();
+    actuators::initialize();
+    watchdog::count_reset();
+    
+    // See how much RAM the stack left unused before the reset, and paint it
+    // for the next time
+    stack::initialize();
+    
     // Create and initialize controllers
     let mut sensor_manager = SensorManager::new();
-    let mut display_controller = DisplayController::new(DISPLAY_TIME_PER_READING);
+    #[cfg(not(feature = "display-lcd"))]
+    let mut display_controller = DisplayController::new(Driver::new(), DISPLAY_PAGES);
+    #[cfg(feature = "display-lcd")]
+    let mut display_controller = Display::new();
+    #[cfg(not(any(feature = "modbus", feature = "wifi")))]
+    let mut commands = CommandReader::new();
+    #[cfg(feature = "modbus")]
+    let mut modbus = ModbusSlave::new();
+    #[cfg(feature = "wifi")]
+    let mut uplink = wifi::WifiUplink::new();
+    let mut buttons = Buttons::new();
+    let mut menu = Menu::new();
+    #[cfg(feature = "sd-logger")]
+    let mut logger = logger::SdLogger::new();
+    
+    // Restore the settings saved from the menu
+    let mut settings = settings::load();
+    sensor_manager.set_calibration(settings.calibration);
+    settings::apply_aeration(&settings);
+    let mut history = History::new(settings.history_interval_min);
+    history.initialize();
+    
+    // Carry on from before the reset: aeration phase, statistics and the
+    // acknowledged alarm
+    let mut checkpoint = Checkpoint::new();
+    checkpoint.restore(&mut sensor_manager.values);
     
     // Initialize hardware
     sensor_manager.initialize();
     display_controller.initialize();
-    air::initialize(); // Initialize the air module
+    #[cfg(not(any(feature = "modbus", feature = "wifi")))]
+    {
+        telemetry::initialize();
+        telemetry::report_reset(checkpoint.resumed());
+    }
+    #[cfg(feature = "modbus")]
+    modbus.initialize();
+    #[cfg(feature = "wifi")]
+    uplink.initialize();
+    buttons.initialize();
+    #[cfg(any(feature = "display-tm1637", feature = "display-max7219", feature = "display-lcd"))]
+    timer::initialize();
+    
+    // Enable global interrupts (millisecond tick, display refresh and serial
+    // receive)
+    unsafe {
+        asm!("sei");
+    }
     
     // Start reading temperature for initial display
     sensor_manager.start_initial_temperature_reading();
     
     // Time tracking
-    let mut current_time: u64 = 0;
+    let mut current_time = timer::millis();
+    #[cfg(not(any(feature = "modbus", feature = "wifi")))]
+    let mut last_telemetry_time: u64 = 0;
+    let mut last_cycle: u32 = 0;
+    #[cfg(not(any(feature = "modbus", feature = "wifi")))]
+    let mut last_alarm = alarm::Alarm::None;
 
     
     // Main loop
     loop {
-        // Update display with current sensor values
-        display_controller.update_display(&sensor_manager.values);
+        // Update display with current sensor values, or the menu if it is open
+        match menu.view(&sensor_manager.values, current_time) {
+            Some(view) => display_controller.show_menu(view, current_time),
+            None => display_controller.update_display(&sensor_manager.values, current_time),
+        }
         
-        // Update sensors - passing whether we're showing temperature
-        sensor_manager.update(
-            current_time, 
-            display_controller.is_showing_temperature()
-        );
+        // Update sensors; the menu only needs pH while calibrating
+        sensor_manager.update(current_time, menu.is_calibrating());
         
         // Check if it's time to switch display modes
-        display_controller.check_mode_switch(current_time);
+        if !menu.is_open() {
+            display_controller.check_mode_switch(current_time);
+        }
+        
+        // Short delay between iterations (the display refreshes itself)
+        delay::delay_ms(LOOP_DELAY_MS);
+        
+        // The loop is still running
+        watchdog::kick();
+        
+        // Catch up with the time spent in this iteration
+        current_time = timer::millis();
+
+        // Switch the bubbles according to the aeration schedule, unless
+        // something keeps the pump in its safe state
+        air::update(current_time);
+        actuators::update(&sensor_manager.values, current_time);
         
-        // Short delay for display timing
-        delay::delay_ms(DISPLAY_REFRESH_DELAY_MS);
+        // Handle the push buttons
+        menu.update(current_time);
+        if let Some(event) = buttons.poll(current_time) {
+            if let Some(action) = menu.handle(event, &sensor_manager.values, current_time) {
+                apply(
+                    action,
+                    &mut settings,
+                    &mut sensor_manager,
+                    &mut display_controller,
+                    current_time
+                );
+            }
+        }
+        
+        // Handle commands received over serial
+        #[cfg(not(any(feature = "modbus", feature = "wifi")))]
+        if let Some((command, source)) = commands.poll() {
+            execute(
+                command,
+                source,
+                &mut display_controller,
+                &mut sensor_manager,
+                &mut history,
+                &mut settings,
+                current_time
+            );
+        }
+        
+        // Answer the Modbus master
+        #[cfg(feature = "modbus")]
+        if let Some(request) = modbus.poll(settings.modbus_address as u8) {
+            serve(
+                request,
+                &modbus,
+                &mut display_controller,
+                &mut sensor_manager,
+                &mut history,
+                &mut settings,
+                current_time
+            );
+        }
+        
+        // Post the readings over Wi-Fi
+        #[cfg(feature = "wifi")]
+        uplink.update(&sensor_manager.values, &settings, current_time);
+        
+        // Record each completed measurement cycle in the history (and on
+        // the SD card)
+        if sensor_manager.values.cycles != last_cycle {
+            last_cycle = sensor_manager.values.cycles;
+            history.add(&sensor_manager.values);
+            #[cfg(feature = "sd-logger")]
+            logger.log(&sensor_manager.values, current_time);
+        }
+        #[cfg(feature = "sd-logger")]
+        logger.update();
+        history.update(current_time);
+        checkpoint.update(&sensor_manager.values, current_time);
+        
+        // Tell a binary host when the alarm condition changes
+        #[cfg(not(any(feature = "modbus", feature = "wifi")))]
+        {
+            let alarm = alarm::condition(&sensor_manager.values);
+            if alarm != last_alarm {
+                last_alarm = alarm;
+                protocol::notify(Event::Alarm, alarm.code() as u16);
+            }
+        }
         
-        // Update time counter
-        current_time += DISPLAY_REFRESH_DELAY_MS;
-
-        // Activate bubbles for 30 seconds every 10 minutes (600 seconds)
-        if (current_time % 600000) < 30000 {
-            air::activate_bubbles();
-        } else {
-            air::deactivate_bubbles();
+        // Periodically report readings over serial, as frames once the
+        // host talks binary
+        #[cfg(not(any(feature = "modbus", feature = "wifi")))]
+        if current_time >= last_telemetry_time + TELEMETRY_INTERVAL_MS {
+            if protocol::is_active() {
+                protocol::send_readings(None, &sensor_manager.values, current_time);
+            } else {
+                #[cfg(not(feature = "sd-logger"))]
+                telemetry::report(&sensor_manager.values, current_time);
+                #[cfg(feature = "sd-logger")]
+                telemetry::report_with_log(&sensor_manager.values, &logger, current_time);
+            }
+            last_telemetry_time = current_time;
         }
     }
 }
+
+// Carry out a menu action, saving any changed settings
+fn apply(
+    action: Action,
+    settings: &mut Settings,
+    sensor_manager: &mut SensorManager,
+    display_controller: &mut Display,
+    current_time: u64,
+) {
+    match action {
+        Action::NextPage => display_controller.next_page(current_time),
+        Action::PreviousPage => display_controller.previous_page(current_time),
+        Action::Calibrate(calibration) => {
+            settings.calibration = calibration;
+            sensor_manager.set_calibration(calibration);
+            settings::save(settings);
+            #[cfg(not(any(feature = "modbus", feature = "wifi")))]
+            for key in ConfigKey::CALIBRATION {
+                protocol::notify(Event::SettingChanged, key as u16);
+            }
+        },
+        Action::SetAeration(period_min, duration_s) => {
+            settings.aeration_period_min = period_min;
+            settings.aeration_duration_s = duration_s;
+            settings::apply_aeration(settings);
+            settings::save(settings);
+            #[cfg(not(any(feature = "modbus", feature = "wifi")))]
+            protocol::notify(Event::SettingChanged, ConfigKey::AerationPeriod as u16);
+            #[cfg(not(any(feature = "modbus", feature = "wifi")))]
+            protocol::notify(Event::SettingChanged, ConfigKey::AerationDuration as u16);
+        },
+        Action::AcknowledgeAlarm => alarm::acknowledge(&sensor_manager.values),
+    }
+}
+
+// Apply a serial command and acknowledge it
+#[cfg(not(any(feature = "modbus", feature = "wifi")))]
+fn execute(
+    command: Command,
+    source: Source,
+    display_controller: &mut Display,
+    sensor_manager: &mut SensorManager,
+    history: &mut History,
+    settings: &mut Settings,
+    current_time: u64,
+) {
+    match command {
+        Command::Brightness(level) => display_controller.set_brightness(level),
+        #[cfg(feature = "light-sensor")]
+        Command::AutoBrightness => display_controller.set_auto_brightness(),
+        Command::History => history.dump(),
+        Command::Statistics => telemetry::report_statistics(&sensor_manager.values),
+        Command::ResetStatistics => sensor_manager.reset_statistics(current_time),
+        Command::Endpoint => telemetry::report_endpoint(settings),
+        Command::SetText(text, value, length) => settings::save_text(settings, text, &value[..length as usize]),
+        Command::HistoryInterval(minutes) => {
+            settings.history_interval_min = minutes;
+            history.set_interval(minutes, current_time);
+            settings::save(settings);
+        },
+        Command::GetReadings(request) => {
+            // The readings are the answer
+            protocol::send_readings(Some(request), &sensor_manager.values, current_time);
+            return;
+        },
+        Command::SetClock(unix_time) => clock::set(unix_time, current_time),
+        Command::GetClock(request) => {
+            protocol::send_clock(request, clock::now(current_time));
+            return;
+        },
+        Command::GetConfig(request, key) => {
+            let value = config::get(key, display_controller, settings);
+            protocol::send_config(request, key, value);
+            return;
+        },
+        Command::SetCalibration(calibration) => {
+            let accepted = config::set_calibration(calibration, sensor_manager, settings);
+            command::reply(source, accepted);
+            return;
+        },
+        Command::SetConfig(key, value) => {
+            let accepted = config::set(
+                key,
+                value,
+                display_controller,
+                sensor_manager,
+                history,
+                settings,
+                current_time
+            );
+            command::reply(source, accepted);
+            return;
+        },
+    }
+    command::reply(source, true);
+}
+
+// Carry out a Modbus request and answer it
+#[cfg(feature = "modbus")]
+fn serve(
+    request: Request,
+    modbus: &ModbusSlave,
+    display_controller: &mut Display,
+    sensor_manager: &mut SensorManager,
+    history: &mut History,
+    settings: &mut Settings,
+    current_time: u64,
+) {
+    match request {
+        Request::ReadInput { start, count } => {
+            let registers = modbus::input_registers(&sensor_manager.values, current_time);
+            modbus.reply_registers(&registers[start..start + count]);
+        },
+        Request::ReadHolding { start, count } => {
+            let mut registers = [0; modbus::HOLDING_REGISTER_COUNT];
+            for (register, value) in (start..start + count).zip(registers.iter_mut()) {
+                if let Some(key) = ConfigKey::from_u8(register as u8 + 1) {
+                    *value = config::get(key, display_controller, settings);
+                }
+            }
+            modbus.reply_registers(&registers[..count]);
+        },
+        Request::Write { start, count, values } => {
+            // Calibration points are checked and saved together, so one
+            // write can move the whole line. Settings before a rejected
+            // value stay changed.
+            let mut calibration = settings.calibration;
+            let mut calibrating = false;
+            let mut accepted = true;
+            for (register, value) in (start..start + count).zip(values.iter()) {
+                accepted = match ConfigKey::from_u8(register as u8 + 1) {
+                    Some(key @ ConfigKey::CalibrationAdc1)
+                    | Some(key @ ConfigKey::CalibrationPh1)
+                    | Some(key @ ConfigKey::CalibrationAdc2)
+                    | Some(key @ ConfigKey::CalibrationPh2) => {
+                        calibrating = true;
+                        config::set_calibration_point(&mut calibration, key, *value)
+                    },
+                    Some(key) => config::set(
+                        key,
+                        *value,
+                        display_controller,
+                        sensor_manager,
+                        history,
+                        settings,
+                        current_time
+                    ),
+                    None => false,
+                };
+                if !accepted {
+                    break;
+                }
+            }
+            if accepted && calibrating {
+                accepted = config::set_calibration(calibration, sensor_manager, settings);
+            }
+            
+            if accepted {
+                modbus.reply_written();
+            } else {
+                modbus.reply_exception(modbus::Exception::BadValue);
+            }
+        },
+    }
+}
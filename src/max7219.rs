@@ -0,0 +1,102 @@
+use ruduino::Pin;
+use ruduino::delay;
+use crate::display::{SegmentDisplay, MAX_BRIGHTNESS, PATTERN_ALL_ON};
+use crate::spi;
+
+// MAX7219 8-digit display board (SPI)
+//
+// The MAX7219 multiplexes the digits itself, so a frame is only sent when it
+// changes. The four frame digits are shown on the leftmost four positions
+// (DIG7 to DIG4 on the common boards) and the rest are left blank.
+
+pub type LoadPin = spi::SSPin;  // LOAD/CS
+
+// Registers
+const REG_DIGIT0: u8 = 0x01;
+const REG_DECODE_MODE: u8 = 0x09;
+const REG_INTENSITY: u8 = 0x0A;
+const REG_SCAN_LIMIT: u8 = 0x0B;
+const REG_SHUTDOWN: u8 = 0x0C;
+const REG_DISPLAY_TEST: u8 = 0x0F;
+
+// Number of digits on the board
+const DIGITS: u8 = 8;
+
+pub struct Max7219Display {
+    frame: [u8; 4],
+}
+
+impl Max7219Display {
+    pub fn new() -> Self {
+        Max7219Display {
+            frame: [0; 4],
+        }
+    }
+    
+    // Send the frame to the leftmost four digits
+    fn write(&self) {
+        for position in 0..4 {
+            let digit = DIGITS - 1 - position;
+            write_register(REG_DIGIT0 + digit, to_max7219(self.frame[position as usize]));
+        }
+    }
+}
+
+impl SegmentDisplay for Max7219Display {
+    // Configure for raw segment data on all digits and run the display test
+    fn initialize(&mut self) {
+        LoadPin::set_output();
+        LoadPin::set_high();
+        spi::initialize();
+        
+        write_register(REG_SCAN_LIMIT, DIGITS - 1);
+        write_register(REG_DECODE_MODE, 0x00);
+        write_register(REG_INTENSITY, 0x0F);
+        for digit in 0..DIGITS {
+            write_register(REG_DIGIT0 + digit, 0x00);
+        }
+        write_register(REG_SHUTDOWN, 0x01);
+        
+        // All segments on for a moment
+        write_register(REG_DISPLAY_TEST, 0x01);
+        delay::delay_ms(800);
+        write_register(REG_DISPLAY_TEST, 0x00);
+        
+        self.frame = [PATTERN_ALL_ON & !0x80; 4];
+        self.write();
+    }
+    
+    fn show(&mut self, frame: [u8; 4]) {
+        if frame != self.frame {
+            self.frame = frame;
+            self.write();
+        }
+    }
+    
+    // The MAX7219 has 16 intensity steps (0-15), two per level
+    fn set_brightness(&mut self, level: u8) {
+        let level = if level > MAX_BRIGHTNESS { MAX_BRIGHTNESS } else { level };
+        let intensity = if level == 0 { 0 } else { level * 2 - 1 };
+        write_register(REG_INTENSITY, intensity);
+    }
+}
+
+// Write a register: address byte then data byte, latched on LOAD rising
+fn write_register(register: u8, data: u8) {
+    LoadPin::set_low();
+    spi::transfer(register);
+    spi::transfer(data);
+    LoadPin::set_high();
+}
+
+// Convert a frame pattern (a in bit 0 .. g in bit 6, DP in bit 7) to the
+// MAX7219 no-decode layout (DP in bit 7, a in bit 6 .. g in bit 0)
+fn to_max7219(pattern: u8) -> u8 {
+    let mut result = pattern & 0x80;
+    for segment in 0..7 {
+        if pattern & (1 << segment) != 0 {
+            result |= 1 << (6 - segment);
+        }
+    }
+    result
+}
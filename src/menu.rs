@@ -0,0 +1,272 @@
+use crate::air;
+use crate::alarm::{self, Alarm};
+use crate::buttons::Event;
+use crate::ph::Calibration;
+use crate::sensor_manager::SensorValues;
+
+// Local menu driven by the push buttons
+//
+// MENU opens the menu, UP/DOWN (or turning the encoder) choose an item and
+// MENU enters it. Values being edited blink on the display. Holding
+// MENU leaves the menu from anywhere, as does leaving the buttons alone for
+// TIMEOUT_MS (except during calibration, which can take a while to settle).
+//
+//   View  step through the display pages with UP/DOWN, MENU goes back
+//   CAL   two-point pH calibration: put the probe in the pH 7 buffer and
+//         press MENU once the reading has settled (decimal point on the
+//         right), then the same in the pH 4 buffer
+//   Air   aeration period in minutes, MENU, then duration in seconds, MENU
+//   ACK   acknowledge the active alarm
+//
+// The menu only decides what to do; the main loop carries out the returned
+// Action and saves the settings.
+
+// Leave the menu after this long without a button press
+const TIMEOUT_MS: u64 = 30_000;
+
+// How long a result ("done", "Err") is shown before the menu closes
+const MESSAGE_MS: u64 = 1500;
+
+// How long the name of a step is shown before its value
+const PROMPT_MS: u64 = 1500;
+
+// Aeration setting limits
+pub const MIN_PERIOD_MIN: u16 = 1;
+pub const MAX_PERIOD_MIN: u16 = 240;
+const DURATION_STEP_S: u16 = 5;
+pub const MAX_DURATION_S: u16 = 900;
+
+// Items in the order UP steps through them
+#[derive(PartialEq, Copy, Clone)]
+enum Item {
+    View,
+    Calibrate,
+    Aeration,
+    Acknowledge,
+}
+
+const ITEMS: [Item; 4] = [Item::View, Item::Calibrate, Item::Aeration, Item::Acknowledge];
+
+#[derive(PartialEq, Copy, Clone)]
+enum State {
+    Closed,
+    Select(usize),         // Choosing ITEMS[index]
+    View,                  // Stepping through the display pages
+    Buffer1,               // Waiting for a settled reading in CAL_BUFFER_1
+    Buffer2(u16),          // Buffer 1 ADC reading taken, waiting for CAL_BUFFER_2
+    Period(u16),           // Editing the aeration period (minutes)
+    Duration(u16, u16),    // Period chosen, editing the duration (seconds)
+    Message(&'static str), // Result of the last step
+}
+
+// What the display should show instead of the normal pages
+#[derive(Copy, Clone)]
+pub enum View {
+    Text(&'static str),
+    Edit(u16),          // Value being changed with UP/DOWN
+    Reading(f32, bool), // Live value and whether it has settled
+}
+
+// Changes for the main loop to carry out
+pub enum Action {
+    NextPage,
+    PreviousPage,
+    Calibrate(Calibration),
+    SetAeration(u16, u16), // Period (minutes), duration (seconds)
+    AcknowledgeAlarm,
+}
+
+pub struct Menu {
+    state: State,
+    state_time: u64, // When the current step was entered
+    input_time: u64, // Last button press
+}
+
+impl Menu {
+    pub fn new() -> Self {
+        Menu {
+            state: State::Closed,
+            state_time: 0,
+            input_time: 0,
+        }
+    }
+    
+    // Is the menu taking over the display?
+    pub fn is_open(&self) -> bool {
+        self.state != State::Closed
+    }
+    
+    // Is a calibration in progress? Only pH needs reading then
+    pub fn is_calibrating(&self) -> bool {
+        matches!(self.state, State::Buffer1 | State::Buffer2(_))
+    }
+    
+    // Close the menu after a timeout or once a result has been shown
+    pub fn update(&mut self, current_time: u64) {
+        match self.state {
+            State::Closed | State::Buffer1 | State::Buffer2(_) => {},
+            State::Message(_) => {
+                if current_time >= self.state_time + MESSAGE_MS {
+                    self.enter(State::Closed, current_time);
+                }
+            },
+            _ => {
+                if current_time >= self.input_time + TIMEOUT_MS {
+                    self.enter(State::Closed, current_time);
+                }
+            }
+        }
+    }
+    
+    // React to a button
+    pub fn handle(&mut self, event: Event, values: &SensorValues, current_time: u64) -> Option<Action> {
+        self.input_time = current_time;
+        
+        match event {
+            Event::MenuHeld => {
+                self.enter(State::Closed, current_time);
+                None
+            },
+            Event::Menu => self.confirm(values, current_time),
+            _ => self.adjust(steps(event), current_time),
+        }
+    }
+    
+    // What to show, or None for the normal display
+    pub fn view(&self, values: &SensorValues, current_time: u64) -> Option<View> {
+        let prompting = current_time < self.state_time + PROMPT_MS;
+        
+        match self.state {
+            State::Closed | State::View => None,
+            State::Select(index) => Some(View::Text(label(ITEMS[index]))),
+            // Prompts match CAL_BUFFER_1 and CAL_BUFFER_2
+            State::Buffer1 if prompting => Some(View::Text("buf7")),
+            State::Buffer2(_) if prompting => Some(View::Text("buf4")),
+            State::Buffer1 | State::Buffer2(_) => Some(View::Reading(values.ph, values.ph_stable)),
+            State::Period(_) if prompting => Some(View::Text("PEr")),
+            State::Period(period) => Some(View::Edit(period)),
+            State::Duration(..) if prompting => Some(View::Text("dur")),
+            State::Duration(_, duration) => Some(View::Edit(duration)),
+            State::Message(text) => Some(View::Text(text)),
+        }
+    }
+    
+    // MENU pressed: enter the chosen item or accept the value shown
+    fn confirm(&mut self, values: &SensorValues, current_time: u64) -> Option<Action> {
+        match self.state {
+            State::Closed | State::View => self.enter(State::Select(0), current_time),
+            State::Select(index) => return self.open(ITEMS[index], values, current_time),
+            
+            // Readings are only taken once they have settled
+            State::Buffer1 if values.ph_stable => {
+                self.enter(State::Buffer2(values.ph_adc), current_time);
+            },
+            State::Buffer2(adc_1) if values.ph_stable => {
+                let calibration = Calibration::from_buffers(adc_1, values.ph_adc);
+                if !calibration.is_valid() {
+                    self.enter(State::Message("Err"), current_time);
+                    return None;
+                }
+                self.enter(State::Message("done"), current_time);
+                return Some(Action::Calibrate(calibration));
+            },
+            
+            State::Period(period) => {
+                let duration = (air::duration_ms() / 1000) as u16;
+                let duration = duration.min(max_duration_s(period));
+                self.enter(State::Duration(period, duration), current_time);
+            },
+            State::Duration(period, duration) => {
+                self.enter(State::Message("done"), current_time);
+                return Some(Action::SetAeration(period, duration));
+            },
+            
+            State::Message(_) => self.enter(State::Closed, current_time),
+            _ => {}
+        }
+        None
+    }
+    
+    // UP/DOWN or the encoder moved by a number of steps (negative is down)
+    fn adjust(&mut self, steps: i16, current_time: u64) -> Option<Action> {
+        match self.state {
+            // One item or page at a time, however fast the encoder turns
+            State::Select(index) => {
+                let count = ITEMS.len() as i16;
+                let index = (index as i16 + steps.signum()).rem_euclid(count);
+                self.state = State::Select(index as usize);
+            },
+            State::View if steps > 0 => return Some(Action::NextPage),
+            State::View if steps < 0 => return Some(Action::PreviousPage),
+            
+            State::Period(period) => {
+                let period = (period as i16 + steps).clamp(MIN_PERIOD_MIN as i16, MAX_PERIOD_MIN as i16);
+                self.state = State::Period(period as u16);
+            },
+            State::Duration(period, duration) => {
+                let duration = duration as i32 + steps as i32 * DURATION_STEP_S as i32;
+                let duration = duration.clamp(0, max_duration_s(period) as i32);
+                self.state = State::Duration(period, duration as u16);
+            },
+            
+            State::Message(_) => self.enter(State::Closed, current_time),
+            _ => {}
+        }
+        None
+    }
+    
+    // Start the chosen item
+    fn open(&mut self, item: Item, values: &SensorValues, current_time: u64) -> Option<Action> {
+        match item {
+            Item::View => self.enter(State::View, current_time),
+            Item::Calibrate => self.enter(State::Buffer1, current_time),
+            Item::Aeration => {
+                let period = (air::period_ms() / 60_000) as u16;
+                let period = period.clamp(MIN_PERIOD_MIN, MAX_PERIOD_MIN);
+                self.enter(State::Period(period), current_time);
+            },
+            Item::Acknowledge => {
+                if alarm::active(values) == Alarm::None {
+                    self.enter(State::Message("none"), current_time);
+                } else {
+                    self.enter(State::Message("done"), current_time);
+                    return Some(Action::AcknowledgeAlarm);
+                }
+            }
+        }
+        None
+    }
+    
+    fn enter(&mut self, state: State, current_time: u64) {
+        self.state = state;
+        self.state_time = current_time;
+    }
+}
+
+// Steps for an UP/DOWN press or encoder turn
+fn steps(event: Event) -> i16 {
+    match event {
+        #[cfg(not(feature = "rotary-encoder"))]
+        Event::Up => 1,
+        #[cfg(not(feature = "rotary-encoder"))]
+        Event::Down => -1,
+        #[cfg(feature = "rotary-encoder")]
+        Event::Turn(clicks) => clicks,
+        _ => 0,
+    }
+}
+
+// Aeration may run for the whole period at most
+fn max_duration_s(period_min: u16) -> u16 {
+    (period_min as u32 * 60).min(MAX_DURATION_S as u32) as u16
+}
+
+// Name shown for each item
+fn label(item: Item) -> &'static str {
+    match item {
+        Item::View => "View",
+        Item::Calibrate => "CAL",
+        Item::Aeration => "Air",
+        Item::Acknowledge => "ACK",
+    }
+}
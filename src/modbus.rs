@@ -0,0 +1,238 @@
+#[cfg(feature = "modbus-de")]
+use ruduino::Pin;
+#[cfg(feature = "modbus-de")]
+use ruduino::cores::current::port::C4;
+use crate::uart;
+use crate::modbus_request::{crc16, parse};
+pub use crate::modbus_request::{Exception, Request, HOLDING_REGISTER_COUNT, INPUT_REGISTER_COUNT};
+use crate::actuators::{self, Output};
+use crate::alarm;
+use crate::watchdog;
+use crate::sensor_manager::SensorValues;
+
+// Modbus RTU slave
+//
+// With the modbus feature the serial port speaks Modbus RTU at 9600 baud,
+// 8 data bits, even parity, 1 stop bit, in place of the text commands and
+// telemetry. An RTU frame ends after 3.5 characters of silence. The main
+// loop polls at least every 2 ms, about two characters, so two polls in a
+// row without a new byte end a frame.
+//
+// Input registers (function 04):
+//
+//   0  temperature (0.1 °C, signed)
+//   1  pH (0.01)
+//   2  electrode potential (0.1 mV, signed)
+//   3  supply voltage (mV)
+//   4  alarm code (0 = none)
+//   5  aeration (1 = bubbles on)
+//   6  flags: bit 0 temperature stable, bit 1 pH stable, bit 2 probe fault
+//   7  electrode health (0 ok, 1 low slope, 2 offset, 3 slow response)
+//   8  uptime in seconds, high word
+//   9  uptime in seconds, low word
+//  10  last reset cause (0 unknown, 1 power on, 2 reset pin, 3 brownout,
+//      4 watchdog)
+//  11  watchdog resets counted in EEPROM
+//  12  brownout resets counted in EEPROM
+//
+// Holding registers (functions 03, 06 and 16) are the settings in
+// config::ConfigKey, register 0 being key 1. Writes to address 0
+// (broadcast) are carried out without a reply.
+
+// DE and /RE of an RS-485 transceiver, high while transmitting
+#[cfg(feature = "modbus-de")]
+pub type DirectionPin = C4;
+
+const BROADCAST: u8 = 0;
+
+// Longest frame handled: a write of all holding registers (29 bytes), or
+// the reply to a read of all input registers (31 bytes)
+const FRAME_SIZE: usize = 32;
+
+// Polls without a new byte that end a frame
+const IDLE_POLLS: u8 = 2;
+
+// Collects received bytes into frames and answers requests
+pub struct ModbusSlave {
+    frame: [u8; FRAME_SIZE],
+    length: usize,
+    damaged: bool,  // Too long, or a parity or framing error; dropped
+    idle_polls: u8,
+    address: u8,    // Address and function of the request being answered
+    function: u8,
+    echo: [u8; 4],  // Register and value/count, echoed by write replies
+}
+
+impl ModbusSlave {
+    pub fn new() -> Self {
+        ModbusSlave {
+            frame: [0; FRAME_SIZE],
+            length: 0,
+            damaged: false,
+            idle_polls: 0,
+            address: BROADCAST,
+            function: 0,
+            echo: [0; 4],
+        }
+    }
+    
+    pub fn initialize(&self) {
+        uart::initialize();
+        
+        #[cfg(feature = "modbus-de")]
+        {
+            DirectionPin::set_output();
+            DirectionPin::set_low();
+        }
+    }
+    
+    // Collect received bytes, returning a request for this slave once a
+    // valid frame has ended. Frames with a bad CRC or for other slaves are
+    // ignored; malformed requests are answered with an exception here.
+    pub fn poll(&mut self, address: u8) -> Option<Request> {
+        let mut received = false;
+        while let Some(byte) = uart::read_byte() {
+            received = true;
+            if self.length < FRAME_SIZE {
+                self.frame[self.length] = byte;
+                self.length += 1;
+            } else {
+                self.damaged = true;
+            }
+        }
+        if uart::take_error() {
+            self.damaged = true;
+        }
+        
+        if received || self.length == 0 {
+            self.idle_polls = 0;
+            return None;
+        }
+        self.idle_polls += 1;
+        if self.idle_polls < IDLE_POLLS {
+            return None;
+        }
+        
+        // The frame has ended
+        let length = self.length;
+        let damaged = self.damaged;
+        self.length = 0;
+        self.damaged = false;
+        self.idle_polls = 0;
+        
+        if damaged || length < 4 {
+            return None;
+        }
+        let crc = u16::from_le_bytes([self.frame[length - 2], self.frame[length - 1]]);
+        if crc16(&self.frame[..length - 2]) != crc {
+            return None;
+        }
+        if self.frame[0] != address && self.frame[0] != BROADCAST {
+            return None;
+        }
+        
+        self.address = self.frame[0];
+        self.function = self.frame[1];
+        let data = &self.frame[2..length - 2];
+        if data.len() >= 4 {
+            self.echo.copy_from_slice(&data[..4]);
+        }
+        
+        match parse(self.function, data) {
+            Ok(request) => Some(request),
+            Err(exception) => {
+                self.reply_exception(exception);
+                None
+            }
+        }
+    }
+    
+    // Answer a read with the register values
+    pub fn reply_registers(&self, registers: &[u16]) {
+        let mut pdu = [0; 2 + 2 * INPUT_REGISTER_COUNT];
+        pdu[0] = self.function;
+        pdu[1] = (registers.len() * 2) as u8;
+        for (index, register) in registers.iter().enumerate() {
+            let bytes = register.to_be_bytes();
+            pdu[2 + index * 2] = bytes[0];
+            pdu[3 + index * 2] = bytes[1];
+        }
+        self.send(&pdu[..2 + registers.len() * 2]);
+    }
+    
+    // Confirm a write: function 06 echoes register and value, function 16
+    // the start register and count
+    pub fn reply_written(&self) {
+        let echo = self.echo;
+        self.send(&[self.function, echo[0], echo[1], echo[2], echo[3]]);
+    }
+    
+    pub fn reply_exception(&self, exception: Exception) {
+        self.send(&[self.function | 0x80, exception as u8]);
+    }
+    
+    // Add address and CRC and send a reply, unless the request was broadcast
+    fn send(&self, pdu: &[u8]) {
+        if self.address == BROADCAST {
+            return;
+        }
+        
+        let mut frame = [0; FRAME_SIZE];
+        let length = pdu.len() + 3;
+        frame[0] = self.address;
+        frame[1..length - 2].copy_from_slice(pdu);
+        let crc = crc16(&frame[..length - 2]).to_le_bytes();
+        frame[length - 2] = crc[0];
+        frame[length - 1] = crc[1];
+        
+        #[cfg(feature = "modbus-de")]
+        {
+            DirectionPin::set_high();
+            uart::start_transmission();
+        }
+        
+        for &byte in &frame[..length] {
+            uart::send_byte(byte);
+        }
+        
+        // Hold the bus until the last stop bit is out
+        #[cfg(feature = "modbus-de")]
+        {
+            uart::wait_transmission_complete();
+            DirectionPin::set_low();
+        }
+    }
+}
+
+// The input registers for the current sensor values, rounded like the
+// telemetry
+pub fn input_registers(values: &SensorValues, current_time: u64) -> [u16; INPUT_REGISTER_COUNT] {
+    let mut flags = 0;
+    if values.temperature_stable {
+        flags |= 1 << 0;
+    }
+    if values.ph_stable {
+        flags |= 1 << 1;
+    }
+    if values.temperature_fault {
+        flags |= 1 << 2;
+    }
+    
+    let uptime = (current_time / 1000) as u32;
+    let resets = watchdog::reset_counts();
+    [
+        libm::roundf(values.temperature * 10.0) as i16 as u16,
+        libm::roundf(values.ph * 100.0) as u16,
+        libm::roundf(values.ph_mv * 10.0) as i16 as u16,
+        libm::roundf(values.supply_voltage * 1000.0) as u16,
+        alarm::condition(values).code() as u16,
+        actuators::is_on(Output::AirPump) as u16,
+        flags,
+        values.electrode_health as u16,
+        (uptime >> 16) as u16,
+        uptime as u16,
+        watchdog::reset_cause() as u16,
+        resets.watchdog,
+        resets.brownout,
+    ]
+}
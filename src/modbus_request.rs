@@ -0,0 +1,122 @@
+// Modbus RTU requests
+//
+// Checking a request against the register map, apart from the serial port
+// so the host companion's tests can build it too. Register addresses and
+// counts are 16 bits on the wire and checked as such, so no request can
+// reach past the map however the sum of start and count would overflow.
+
+// Function codes
+pub const READ_HOLDING_REGISTERS: u8 = 0x03;
+pub const READ_INPUT_REGISTERS: u8 = 0x04;
+pub const WRITE_SINGLE_REGISTER: u8 = 0x06;
+pub const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+
+pub const INPUT_REGISTER_COUNT: usize = 13;
+pub const HOLDING_REGISTER_COUNT: usize = 10;
+
+// Exception codes
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Exception {
+    UnknownFunction = 1, // ILLEGAL FUNCTION
+    OutsideMap = 2,      // ILLEGAL DATA ADDRESS, register outside the map
+    BadValue = 3,        // ILLEGAL DATA VALUE, bad count or setting out of range
+}
+
+#[derive(PartialEq, Debug)]
+pub enum Request {
+    ReadInput { start: usize, count: usize },
+    ReadHolding { start: usize, count: usize },
+    // Function 06 gives a count of 1
+    Write { start: usize, count: usize, values: [u16; HOLDING_REGISTER_COUNT] },
+}
+
+// Check a request's data (between function code and CRC) against the
+// register map
+pub fn parse(function: u8, data: &[u8]) -> Result<Request, Exception> {
+    let word = |offset: usize| u16::from_be_bytes([data[offset], data[offset + 1]]);
+    
+    match function {
+        READ_INPUT_REGISTERS | READ_HOLDING_REGISTERS => {
+            if data.len() != 4 {
+                return Err(Exception::BadValue);
+            }
+            let (start, count) = (word(0), word(2));
+            let size = if function == READ_INPUT_REGISTERS {
+                INPUT_REGISTER_COUNT
+            } else {
+                HOLDING_REGISTER_COUNT
+            };
+            if count == 0 || count > 125 {
+                return Err(Exception::BadValue);
+            }
+            if !fits(start, count, size) {
+                return Err(Exception::OutsideMap);
+            }
+            
+            let (start, count) = (start as usize, count as usize);
+            if function == READ_INPUT_REGISTERS {
+                Ok(Request::ReadInput { start, count })
+            } else {
+                Ok(Request::ReadHolding { start, count })
+            }
+        },
+        WRITE_SINGLE_REGISTER => {
+            if data.len() != 4 {
+                return Err(Exception::BadValue);
+            }
+            let start = word(0);
+            if !fits(start, 1, HOLDING_REGISTER_COUNT) {
+                return Err(Exception::OutsideMap);
+            }
+            
+            let mut values = [0; HOLDING_REGISTER_COUNT];
+            values[0] = word(2);
+            Ok(Request::Write { start: start as usize, count: 1, values })
+        },
+        WRITE_MULTIPLE_REGISTERS => {
+            if data.len() < 5 {
+                return Err(Exception::BadValue);
+            }
+            let (start, count) = (word(0), word(2));
+            let byte_count = data[4] as usize;
+            if count == 0 || count > 123 || byte_count != count as usize * 2 || data.len() != 5 + byte_count {
+                return Err(Exception::BadValue);
+            }
+            if !fits(start, count, HOLDING_REGISTER_COUNT) {
+                return Err(Exception::OutsideMap);
+            }
+            
+            let (start, count) = (start as usize, count as usize);
+            let mut values = [0; HOLDING_REGISTER_COUNT];
+            for (index, value) in values[..count].iter_mut().enumerate() {
+                *value = word(5 + index * 2);
+            }
+            Ok(Request::Write { start, count, values })
+        },
+        _ => Err(Exception::UnknownFunction),
+    }
+}
+
+// Do count registers from start lie within a map of size registers? Never
+// adds start and count, which could wrap.
+fn fits(start: u16, count: u16, size: usize) -> bool {
+    let (start, count) = (start as usize, count as usize);
+    count <= size && start <= size - count
+}
+
+// CRC-16/MODBUS (reflected polynomial 0xA001, start 0xFFFF), sent low
+// byte first
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 0x0001 != 0 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
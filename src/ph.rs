@@ -9,28 +9,210 @@
 // pub const PH_ALKALINE: f32 = 8.4; // Baking soda solution approximate pH
 
 // pH conversion parameters
-// These parameters map the ADC values to pH values
+// These parameters map the ADC values to pH values until the probe has been
+// calibrated with buffers (see Calibration)
 // Based on observations: Higher ADC = LOWER pH, Lower ADC = HIGHER pH
 pub const PH_MIN_ADC: u16 = 1020;  // ADC value corresponding to pH MIN
 pub const PH_MAX_ADC: u16 = 650;   // ADC value corresponding to pH MAX
 pub const PH_MIN: u16 = 200;       // pH 2.00 * 100
 pub const PH_MAX: u16 = 1400;      // pH 14.00 * 100
 
-// Convert raw ADC value to pH
-pub fn adc_to_ph(ph_raw: u16) -> u16 {
-    // Calculate pH using the calibrated formula - INVERTED relationship
-    if ph_raw >= PH_MIN_ADC {
-        // Above maximum ADC value, use minimum pH
-        PH_MIN
-    } else if ph_raw <= PH_MAX_ADC {
-        // Below minimum ADC value, use maximum pH
-        PH_MAX
+// Buffers used for two-point calibration (pH * 100)
+pub const CAL_BUFFER_1: u16 = 700; // Neutral buffer, measured first
+pub const CAL_BUFFER_2: u16 = 400; // Acid buffer
+
+// Smallest ADC difference between the two buffers for a usable calibration
+const MIN_CAL_ADC_SPAN: u16 = 20;
+
+// pH module analog front end
+// The module amplifies the electrode potential and adds the offset set by the
+// reference potentiometer: Po = MODULE_OFFSET_MV + MODULE_GAIN * E.
+// Measure MODULE_OFFSET_MV on Po with the BNC input shorted. The defaults
+// match the default calibration above (pH 7 at 0 mV, ideal Nernstian slope).
+pub const MODULE_OFFSET_MV: f32 = 4230.0; // Po with 0 mV at the electrode (mV)
+pub const MODULE_GAIN: f32 = 2.55;        // Amplifier gain of the module
+
+// Electrode health thresholds
+pub const MIN_SLOPE_PERCENT: f32 = 85.0;  // Below this the electrode is worn out
+pub const MAX_OFFSET_MV: f32 = 30.0;      // Acceptable |E| at pH 7
+pub const MAX_RESPONSE_MS: u64 = 30_000;  // Time allowed to settle after a step
+
+// Response time tracking parameters
+const RESPONSE_SAMPLE_MS: u64 = 1000;     // Interval between response samples
+const RESPONSE_STEP_MV: f32 = 20.0;       // Change per sample that starts a step
+const RESPONSE_SETTLED_MV: f32 = 1.0;     // Change per sample considered settled
+
+// ADC full scale and reference the calibration constants refer to
+const ADC_MAX: f32 = 1023.0;
+const ADC_REFERENCE_MV: f32 = crate::adc::NOMINAL_SUPPLY_MV as f32;
+
+// Electrode health classification
+#[derive(PartialEq, Copy, Clone)]
+pub enum ElectrodeHealth {
+    Ok,
+    LowSlope,     // Slope below MIN_SLOPE_PERCENT of Nernstian
+    Offset,       // Potential at pH 7 beyond MAX_OFFSET_MV
+    SlowResponse, // Last step took longer than MAX_RESPONSE_MS to settle
+}
+
+impl ElectrodeHealth {
+    // Short code sent in telemetry and uploads
+    pub fn code(self) -> &'static str {
+        match self {
+            ElectrodeHealth::Ok => "ok",
+            ElectrodeHealth::LowSlope => "slope",
+            ElectrodeHealth::Offset => "offset",
+            ElectrodeHealth::SlowResponse => "slow",
+        }
+    }
+}
+
+// Calibration line through the ADC readings of two known pH values
+#[derive(Copy, Clone)]
+pub struct Calibration {
+    pub adc_1: u16,
+    pub ph_1: u16, // pH * 100
+    pub adc_2: u16,
+    pub ph_2: u16, // pH * 100
+}
+
+impl Calibration {
+    // Used until the probe has been calibrated
+    pub const DEFAULT: Calibration = Calibration {
+        adc_1: PH_MIN_ADC,
+        ph_1: PH_MIN,
+        adc_2: PH_MAX_ADC,
+        ph_2: PH_MAX,
+    };
+    
+    // Calibration from the readings taken in CAL_BUFFER_1 and CAL_BUFFER_2
+    pub fn from_buffers(adc_1: u16, adc_2: u16) -> Calibration {
+        Calibration {
+            adc_1,
+            ph_1: CAL_BUFFER_1,
+            adc_2,
+            ph_2: CAL_BUFFER_2,
+        }
+    }
+    
+    // The readings must be far enough apart and, as the module inverts,
+    // the higher pH must give the lower ADC value
+    pub fn is_valid(&self) -> bool {
+        let (low_adc, high_adc) = if self.ph_1 < self.ph_2 {
+            (self.adc_2, self.adc_1)
+        } else {
+            (self.adc_1, self.adc_2)
+        };
+        self.ph_1 != self.ph_2 && high_adc >= low_adc + MIN_CAL_ADC_SPAN
+    }
+}
+
+// Convert raw ADC value to pH (* 100)
+pub fn adc_to_ph(ph_raw: u16, calibration: &Calibration) -> u16 {
+    // Linear interpolation (or extrapolation) along the calibration line,
+    // INVERTED relationship: higher ADC = lower pH
+    let adc_range = calibration.adc_1 as i32 - calibration.adc_2 as i32;
+    let ph_range = calibration.ph_2 as i32 - calibration.ph_1 as i32;
+    let adc_position = calibration.adc_1 as i32 - ph_raw as i32;
+    let ph = calibration.ph_1 as i32 + adc_position * ph_range / adc_range;
+    
+    // Limit to the pH scale
+    if ph < 0 {
+        0
+    } else if ph > 1400 {
+        1400
+    } else {
+        ph as u16
+    }
+}
+
+// Convert a (supply compensated) ADC reading to the electrode potential in mV
+pub fn adc_to_mv(ph_raw: u16) -> f32 {
+    let module_mv = ph_raw as f32 * ADC_REFERENCE_MV / ADC_MAX;
+    (module_mv - MODULE_OFFSET_MV) / MODULE_GAIN
+}
+
+// Ideal electrode slope at the given temperature (mV per pH unit)
+pub fn nernst_slope_mv(temperature: f32) -> f32 {
+    0.198_42 * (temperature + 273.15)
+}
+
+// Electrode slope implied by the calibration (mV per pH unit)
+pub fn calibrated_slope_mv(calibration: &Calibration) -> f32 {
+    let mv_range = adc_to_mv(calibration.adc_1) - adc_to_mv(calibration.adc_2);
+    let ph_range = (calibration.ph_2 as f32 - calibration.ph_1 as f32) / 100.0;
+    mv_range / ph_range
+}
+
+// Calibrated slope as a percentage of the ideal Nernstian slope
+pub fn slope_percent(calibration: &Calibration, temperature: f32) -> f32 {
+    calibrated_slope_mv(calibration) * 100.0 / nernst_slope_mv(temperature)
+}
+
+// Electrode potential at pH 7 implied by the calibration (mV)
+pub fn offset_mv(calibration: &Calibration) -> f32 {
+    // Walk along the calibration line from its first point to pH 7
+    let ph_1 = calibration.ph_1 as f32 / 100.0;
+    adc_to_mv(calibration.adc_1) - (7.0 - ph_1) * calibrated_slope_mv(calibration)
+}
+
+// Classify electrode health from its slope, offset and last response time
+pub fn health(slope_percent: f32, offset_mv: f32, response_ms: u64) -> ElectrodeHealth {
+    if slope_percent < MIN_SLOPE_PERCENT {
+        ElectrodeHealth::LowSlope
+    } else if libm::fabsf(offset_mv) > MAX_OFFSET_MV {
+        ElectrodeHealth::Offset
+    } else if response_ms > MAX_RESPONSE_MS {
+        ElectrodeHealth::SlowResponse
     } else {
-        // Linear interpolation between min and max
-        let adc_range = PH_MIN_ADC - PH_MAX_ADC;
-        let ph_range = PH_MAX - PH_MIN;
-        let adc_position = PH_MIN_ADC - ph_raw;
+        ElectrodeHealth::Ok
+    }
+}
+
+// Measures how long the electrode takes to settle after a step change,
+// e.g. when moving the probe from one buffer to another
+pub struct ResponseTracker {
+    last_sample_mv: f32,
+    last_sample_time: u64,
+    step_start: Option<u64>,
+    pub response_ms: u64, // Settling time of the last completed step
+}
+
+impl ResponseTracker {
+    pub fn new() -> Self {
+        ResponseTracker {
+            last_sample_mv: 0.0,
+            last_sample_time: 0,
+            step_start: None,
+            response_ms: 0,
+        }
+    }
+    
+    // Feed a new electrode potential reading
+    pub fn update(&mut self, mv: f32, current_time: u64) {
+        if current_time < self.last_sample_time + RESPONSE_SAMPLE_MS {
+            return;
+        }
+        
+        let change = libm::fabsf(mv - self.last_sample_mv);
+        self.last_sample_mv = mv;
+        self.last_sample_time = current_time;
         
-        PH_MIN + ((adc_position as u32 * ph_range as u32) / adc_range as u32) as u16
+        match self.step_start {
+            None => {
+                if change >= RESPONSE_STEP_MV {
+                    self.step_start = Some(current_time);
+                }
+            },
+            Some(start_time) => {
+                if change < RESPONSE_SETTLED_MV {
+                    self.response_ms = current_time - start_time;
+                    self.step_start = None;
+                } else if current_time - start_time > MAX_RESPONSE_MS {
+                    // Still drifting: report it as slow without waiting any longer
+                    self.response_ms = current_time - start_time;
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,306 @@
+use crate::uart;
+use crate::crc::crc16;
+use crate::alarm;
+use crate::command::Command;
+use crate::config::ConfigKey;
+use crate::ph::Calibration;
+use crate::sensor_manager::SensorValues;
+
+// Binary serial protocol
+//
+// Runs on the same UART as the text commands, for host programs that need
+// to poll and configure the device reliably. Each message is framed as
+//
+//   0x00  COBS(type, sequence, payload..., crc_hi, crc_lo)  0x00
+//
+// COBS removes every zero byte from the frame, so a zero always marks a
+// frame boundary and a receiver resynchronises after line noise at the next
+// one. Text lines never contain a zero, which is how the two interfaces tell
+// each other apart. The CRC is CRC-16/CCITT-FALSE (polynomial 0x1021, start
+// 0xFFFF) over type, sequence and payload. Values are little endian.
+//
+// Host to device:
+//
+//   0x01  GET_READINGS  -
+//   0x02  GET_CONFIG    key
+//   0x03  SET_CONFIG    key, value (u16)
+//   0x04  SET_CLOCK     Unix time (u32, s)
+//   0x05  GET_CLOCK     -
+//   0x06  SET_CALIBRATION
+//                       adc_1, ph_1, adc_2, ph_2 (u16 each, see
+//                       ph::Calibration), checked and saved as a whole
+//
+// Device to host:
+//
+//   0x80  ACK           request type, status (0 = ok)
+//   0x81  READINGS      temperature (i16, 0.1 °C), pH (u16, 0.01),
+//                       electrode mV (i16, 0.1 mV), supply (u16, mV),
+//                       flags, alarm code, electrode health, uptime (u32, s)
+//   0x82  CONFIG        key, value (u16)
+//   0x83  EVENT         event, value (u16)
+//   0x84  CLOCK         Unix time (u32, s; 0 = not set)
+//
+// READINGS flags: bit 0 temperature stable, bit 1 pH stable, bit 2
+// temperature probe fault. Electrode health: 0 ok, 1 low slope, 2 offset,
+// 3 slow response.
+//
+// Replies carry the sequence number of the request. Corrupted frames are
+// dropped without a reply, so the host retries after a timeout. Once the
+// host has sent a valid frame, the periodic telemetry and events are sent as
+// READINGS and EVENT frames with the device's own sequence number, until
+// the host sends a text line again.
+
+// Message types
+const GET_READINGS: u8 = 0x01;
+const GET_CONFIG: u8 = 0x02;
+const SET_CONFIG: u8 = 0x03;
+const SET_CLOCK: u8 = 0x04;
+const GET_CLOCK: u8 = 0x05;
+const SET_CALIBRATION: u8 = 0x06;
+const ACK: u8 = 0x80;
+const READINGS: u8 = 0x81;
+const CONFIG: u8 = 0x82;
+const EVENT: u8 = 0x83;
+const CLOCK: u8 = 0x84;
+
+// READINGS flags
+const FLAG_TEMPERATURE_STABLE: u8 = 1 << 0;
+const FLAG_PH_STABLE: u8 = 1 << 1;
+const FLAG_TEMPERATURE_FAULT: u8 = 1 << 2;
+
+// Longest unencoded frame (READINGS: 2 header + 15 payload + 2 CRC bytes).
+// Frames stay under 254 bytes, so COBS needs one code byte per zero only.
+const FRAME_SIZE: usize = 24;
+
+// Type and sequence number of a received request, for the reply
+#[derive(Copy, Clone)]
+pub struct Request {
+    message_type: u8,
+    sequence: u8,
+}
+
+// Result sent in an ACK
+#[derive(Copy, Clone)]
+pub enum Status {
+    Ok = 0,
+    UnknownType = 1, // Message type not understood
+    BadLength = 2,   // Payload too short or too long for the type
+    UnknownKey = 3,  // No such configuration key
+    Rejected = 4,    // Value out of range, nothing was changed
+}
+
+// Things the device reports without being asked
+#[derive(Copy, Clone)]
+pub enum Event {
+    Alarm = 1,          // Alarm condition changed, value is the new code
+    SettingChanged = 2, // Changed from the menu, value is the config key
+}
+
+// Set while the host talks binary
+static mut ACTIVE: bool = false;
+
+// Sequence number of the next message the device sends on its own
+static mut SEQUENCE: u8 = 0;
+
+// Switch the periodic output between frames and text lines
+pub fn set_active(active: bool) {
+    unsafe {
+        ACTIVE = active;
+    }
+}
+
+pub fn is_active() -> bool {
+    unsafe { ACTIVE }
+}
+
+// Decode a received frame (the bytes between two zero delimiters) in place
+// and parse it. Returns None for a corrupted frame, otherwise the request
+// with the command or the status to reject it with.
+pub fn receive(frame: &mut [u8]) -> Option<(Request, Result<Command, Status>)> {
+    let length = decode(frame)?;
+    if length < 4 {
+        return None;
+    }
+    
+    let crc = u16::from_be_bytes([frame[length - 2], frame[length - 1]]);
+    if crc16(&frame[..length - 2]) != crc {
+        return None;
+    }
+    
+    let request = Request {
+        message_type: frame[0],
+        sequence: frame[1],
+    };
+    Some((request, parse(request, &frame[2..length - 2])))
+}
+
+// Turn a checked message into a command
+fn parse(request: Request, payload: &[u8]) -> Result<Command, Status> {
+    match (request.message_type, payload) {
+        (GET_READINGS, []) => Ok(Command::GetReadings(request)),
+        (GET_CONFIG, [key]) => {
+            let key = ConfigKey::from_u8(*key).ok_or(Status::UnknownKey)?;
+            Ok(Command::GetConfig(request, key))
+        },
+        (SET_CONFIG, [key, low, high]) => {
+            let key = ConfigKey::from_u8(*key).ok_or(Status::UnknownKey)?;
+            Ok(Command::SetConfig(key, u16::from_le_bytes([*low, *high])))
+        },
+        (SET_CLOCK, [b0, b1, b2, b3]) => {
+            Ok(Command::SetClock(u32::from_le_bytes([*b0, *b1, *b2, *b3])))
+        },
+        (GET_CLOCK, []) => Ok(Command::GetClock(request)),
+        (SET_CALIBRATION, [a0, a1, b0, b1, c0, c1, d0, d1]) => {
+            Ok(Command::SetCalibration(Calibration {
+                adc_1: u16::from_le_bytes([*a0, *a1]),
+                ph_1: u16::from_le_bytes([*b0, *b1]),
+                adc_2: u16::from_le_bytes([*c0, *c1]),
+                ph_2: u16::from_le_bytes([*d0, *d1]),
+            }))
+        },
+        (GET_READINGS, _)
+        | (GET_CONFIG, _)
+        | (SET_CONFIG, _)
+        | (SET_CLOCK, _)
+        | (GET_CLOCK, _)
+        | (SET_CALIBRATION, _) => Err(Status::BadLength),
+        _ => Err(Status::UnknownType),
+    }
+}
+
+// Answer a request with its status
+pub fn send_ack(request: Request, status: Status) {
+    send_frame(ACK, request.sequence, &[request.message_type, status as u8]);
+}
+
+// Answer GET_CONFIG
+pub fn send_config(request: Request, key: ConfigKey, value: u16) {
+    let value = value.to_le_bytes();
+    send_frame(CONFIG, request.sequence, &[key as u8, value[0], value[1]]);
+}
+
+// Answer GET_CLOCK
+pub fn send_clock(request: Request, unix_time: Option<u32>) {
+    send_frame(CLOCK, request.sequence, &unix_time.unwrap_or(0).to_le_bytes());
+}
+
+// Send the current sensor values, in reply to GET_READINGS or on their own
+// (request None) in place of the telemetry line
+pub fn send_readings(request: Option<Request>, values: &SensorValues, current_time: u64) {
+    let mut flags = 0;
+    if values.temperature_stable {
+        flags |= FLAG_TEMPERATURE_STABLE;
+    }
+    if values.ph_stable {
+        flags |= FLAG_PH_STABLE;
+    }
+    if values.temperature_fault {
+        flags |= FLAG_TEMPERATURE_FAULT;
+    }
+    
+    // Rounded like the telemetry and the display, e.g. 25.3 is stored as
+    // 25.2999992
+    let temperature = (libm::roundf(values.temperature * 10.0) as i16).to_le_bytes();
+    let ph = (libm::roundf(values.ph * 100.0) as u16).to_le_bytes();
+    let ph_mv = (libm::roundf(values.ph_mv * 10.0) as i16).to_le_bytes();
+    let supply = (libm::roundf(values.supply_voltage * 1000.0) as u16).to_le_bytes();
+    let uptime = ((current_time / 1000) as u32).to_le_bytes();
+    
+    let payload = [
+        temperature[0], temperature[1],
+        ph[0], ph[1],
+        ph_mv[0], ph_mv[1],
+        supply[0], supply[1],
+        flags,
+        alarm::condition(values).code(),
+        values.electrode_health as u8,
+        uptime[0], uptime[1], uptime[2], uptime[3],
+    ];
+    
+    let sequence = match request {
+        Some(request) => request.sequence,
+        None => next_sequence(),
+    };
+    send_frame(READINGS, sequence, &payload);
+}
+
+// Report an event if the host talks binary
+pub fn notify(event: Event, value: u16) {
+    if !is_active() {
+        return;
+    }
+    
+    let value = value.to_le_bytes();
+    send_frame(EVENT, next_sequence(), &[event as u8, value[0], value[1]]);
+}
+
+fn next_sequence() -> u8 {
+    unsafe {
+        let sequence = SEQUENCE;
+        SEQUENCE = SEQUENCE.wrapping_add(1);
+        sequence
+    }
+}
+
+// Add the CRC, COBS encode and send a frame with its delimiters
+fn send_frame(message_type: u8, sequence: u8, payload: &[u8]) {
+    let mut frame = [0; FRAME_SIZE];
+    let length = payload.len() + 4;
+    frame[0] = message_type;
+    frame[1] = sequence;
+    frame[2..length - 2].copy_from_slice(payload);
+    let crc = crc16(&frame[..length - 2]).to_be_bytes();
+    frame[length - 2] = crc[0];
+    frame[length - 1] = crc[1];
+    
+    uart::send_byte(0);
+    
+    // Each run of non-zero bytes goes out behind a code byte giving its
+    // length + 1; the zero that ended the run is implied by the code
+    let mut start = 0;
+    loop {
+        let mut end = start;
+        while end < length && frame[end] != 0 {
+            end += 1;
+        }
+        
+        uart::send_byte((end - start + 1) as u8);
+        for &byte in &frame[start..end] {
+            uart::send_byte(byte);
+        }
+        
+        if end == length {
+            break;
+        }
+        start = end + 1;
+    }
+    
+    uart::send_byte(0);
+}
+
+// Undo COBS in place, returning the decoded length
+fn decode(frame: &mut [u8]) -> Option<usize> {
+    let mut read = 0;
+    let mut write = 0;
+    
+    while read < frame.len() {
+        let code = frame[read] as usize;
+        if code == 0 || read + code > frame.len() {
+            return None;
+        }
+        
+        for i in 1..code {
+            frame[write] = frame[read + i];
+            write += 1;
+        }
+        read += code;
+        
+        // A full run (0xFF) has no zero after it, nor has the last one
+        if code < 0xFF && read < frame.len() {
+            frame[write] = 0;
+            write += 1;
+        }
+    }
+    
+    Some(write)
+}
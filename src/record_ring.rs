@@ -0,0 +1,137 @@
+use crate::crc::crc16;
+use crate::eeprom;
+
+// Ring buffer of fixed size records in EEPROM
+//
+// Used by the history and the state checkpoints. Each slot holds:
+//
+//   0      sequence number (0-254)
+//   1-     record data
+//   last 2 CRC-16 of sequence number and data (big endian)
+//
+// A slot whose CRC does not match is empty: erased EEPROM, or a record cut
+// short by a power cut, which is never read back. The newest record is the
+// one whose successor does not hold the next sequence number, and new
+// records overwrite the oldest. A record is written one byte per update()
+// call so the main loop never waits the 3.4ms per byte, and unchanged bytes
+// are skipped, so writing a record writes each cell at most once.
+
+// Bytes a slot takes beyond its record data
+pub const OVERHEAD: usize = 3;
+
+// Sequence numbers run 0 to 254 so they can never look like erased EEPROM
+const SEQUENCE_LIMIT: u8 = 0xFF;
+
+// A ring of SIZE byte slots (OVERHEAD included)
+pub struct RecordRing<const SIZE: usize> {
+    address: u16,
+    slot_count: u16,
+    next_slot: u16,
+    next_sequence: u8,
+    record: [u8; SIZE],        // Slot being written
+    record_slot: u16,
+    write_step: Option<usize>, // Next byte of the slot to write
+}
+
+impl<const SIZE: usize> RecordRing<SIZE> {
+    // A ring filling the given EEPROM area
+    pub fn new(address: u16, size: u16) -> Self {
+        RecordRing {
+            address,
+            slot_count: size / SIZE as u16,
+            next_slot: 0,
+            next_sequence: 0,
+            record: [0; SIZE],
+            record_slot: 0,
+            write_step: None,
+        }
+    }
+    
+    // Find where the newest record is, to carry on after it. Returns false
+    // if the ring is empty.
+    pub fn initialize(&mut self) -> bool {
+        for slot in 0..self.slot_count {
+            let sequence = match self.read_slot(slot) {
+                Some(slot) => slot[0],
+                None => continue,
+            };
+            
+            // The newest record is the one not followed by its successor
+            let next_slot = (slot + 1) % self.slot_count;
+            let next_sequence = next_sequence(sequence);
+            if self.read_slot(next_slot).map(|slot| slot[0]) != Some(next_sequence) {
+                self.next_slot = next_slot;
+                self.next_sequence = next_sequence;
+                return true;
+            }
+        }
+        false
+    }
+    
+    // Number of records the ring holds
+    pub fn slot_count(&self) -> u16 {
+        self.slot_count
+    }
+    
+    // Read a record, 0 being the newest, into data. Returns false if that
+    // slot is empty.
+    pub fn read(&self, age: u16, data: &mut [u8]) -> bool {
+        let slot = (self.next_slot + self.slot_count - 1 - age % self.slot_count) % self.slot_count;
+        match self.read_slot(slot) {
+            Some(slot) => {
+                data[..SIZE - OVERHEAD].copy_from_slice(&slot[1..SIZE - 2]);
+                true
+            },
+            None => false,
+        }
+    }
+    
+    // Is a record still being written?
+    pub fn is_writing(&self) -> bool {
+        self.write_step.is_some()
+    }
+    
+    // Queue a record for writing after the newest
+    pub fn start(&mut self, data: &[u8]) {
+        self.record[0] = self.next_sequence;
+        self.record[1..SIZE - 2].copy_from_slice(&data[..SIZE - OVERHEAD]);
+        let crc = crc16(&self.record[..SIZE - 2]).to_be_bytes();
+        self.record[SIZE - 2..].copy_from_slice(&crc);
+        
+        self.record_slot = self.next_slot;
+        self.write_step = Some(0);
+        self.next_slot = (self.next_slot + 1) % self.slot_count;
+        self.next_sequence = next_sequence(self.next_sequence);
+    }
+    
+    // Write the next byte of a queued record, if the EEPROM is free
+    pub fn update(&mut self) {
+        if let Some(step) = self.write_step {
+            if eeprom::is_ready() {
+                let address = self.slot_address(self.record_slot);
+                eeprom::update_byte(address + step as u16, self.record[step]);
+                self.write_step = if step + 1 < SIZE { Some(step + 1) } else { None };
+            }
+        }
+    }
+    
+    // A slot's contents, if its CRC matches
+    fn read_slot(&self, slot: u16) -> Option<[u8; SIZE]> {
+        let mut data = [0u8; SIZE];
+        eeprom::read(self.slot_address(slot), &mut data);
+        let crc = u16::from_be_bytes([data[SIZE - 2], data[SIZE - 1]]);
+        if data[0] < SEQUENCE_LIMIT && crc16(&data[..SIZE - 2]) == crc {
+            Some(data)
+        } else {
+            None
+        }
+    }
+    
+    fn slot_address(&self, slot: u16) -> u16 {
+        self.address + slot * SIZE as u16
+    }
+}
+
+fn next_sequence(sequence: u8) -> u8 {
+    (sequence + 1) % SEQUENCE_LIMIT
+}
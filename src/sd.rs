@@ -0,0 +1,265 @@
+use ruduino::Pin;
+use ruduino::cores::current::port::C3;
+use ruduino::delay;
+use crate::spi;
+
+// SD card in SPI mode, sharing the hardware SPI bus with the display
+//
+// Supports standard (SDSC, byte addressed) and high capacity (SDHC/SDXC,
+// block addressed) cards. Every function returns false on failure, which
+// usually means the card has been removed.
+
+pub type CSPin = C3; // Card select (A3)
+
+pub const BLOCK_SIZE: usize = 512;
+
+// Commands
+const CMD0: u8 = 0;    // GO_IDLE_STATE
+const CMD8: u8 = 8;    // SEND_IF_COND
+const CMD16: u8 = 16;  // SET_BLOCKLEN
+const CMD17: u8 = 17;  // READ_SINGLE_BLOCK
+const CMD24: u8 = 24;  // WRITE_BLOCK
+const CMD55: u8 = 55;  // APP_CMD
+const CMD58: u8 = 58;  // READ_OCR
+const ACMD41: u8 = 41; // SD_SEND_OP_COND
+
+// R1 response bits
+const R1_IDLE: u8 = 0x01;
+const R1_ILLEGAL_COMMAND: u8 = 0x04;
+
+// Tokens
+const DATA_START: u8 = 0xFE;
+const DATA_ACCEPTED: u8 = 0x05; // Data response, masked with 0x1F
+
+// Timeouts
+const INIT_TIMEOUT_MS: u16 = 1000; // For the card to leave the idle state
+// In polls 100µs apart
+const READ_POLLS: u16 = 1_000;   // 100ms for a block to be read
+const BUSY_POLLS: u16 = 5_000;   // 500ms for a write to finish
+
+pub struct SdCard {
+    block_addressing: bool, // High capacity card
+}
+
+impl SdCard {
+    pub fn new() -> Self {
+        SdCard {
+            block_addressing: false,
+        }
+    }
+    
+    // Bring the card into SPI mode and ready it for block transfers
+    pub fn initialize(&mut self) -> bool {
+        CSPin::set_output();
+        CSPin::set_high();
+        spi::initialize();
+        spi::set_slow_clock(true);
+        
+        let ready = self.power_up();
+        spi::set_slow_clock(false);
+        ready
+    }
+    
+    fn power_up(&mut self) -> bool {
+        // At least 74 clocks with the card deselected
+        spi::claim();
+        for _ in 0..10 {
+            spi::transfer(0xFF);
+        }
+        spi::release();
+        
+        if self.command(CMD0, 0) != R1_IDLE {
+            return false;
+        }
+        
+        // Version 2 cards echo the check pattern, older ones reject CMD8
+        let mut version_2 = false;
+        select();
+        let response = send_command(CMD8, 0x1AA);
+        if response == R1_IDLE {
+            let mut r7 = [0u8; 4];
+            for byte in r7.iter_mut() {
+                *byte = spi::transfer(0xFF);
+            }
+            if r7[3] != 0xAA {
+                deselect();
+                return false;
+            }
+            version_2 = true;
+        }
+        deselect();
+        if !version_2 && response & R1_ILLEGAL_COMMAND == 0 {
+            return false;
+        }
+        
+        // Wait for the card to finish its own initialization
+        let argument = if version_2 { 0x4000_0000 } else { 0 }; // Host supports high capacity
+        let mut elapsed_ms = 0;
+        loop {
+            self.command(CMD55, 0);
+            if self.command(ACMD41, argument) == 0 {
+                break;
+            }
+            elapsed_ms += 1;
+            if elapsed_ms >= INIT_TIMEOUT_MS {
+                return false;
+            }
+            delay::delay_ms(1);
+        }
+        
+        // High capacity cards report it in the OCR
+        self.block_addressing = false;
+        if version_2 {
+            select();
+            if send_command(CMD58, 0) != 0 {
+                deselect();
+                return false;
+            }
+            let ocr = spi::transfer(0xFF);
+            for _ in 0..3 {
+                spi::transfer(0xFF);
+            }
+            deselect();
+            self.block_addressing = ocr & 0x40 != 0;
+        }
+        
+        // Standard capacity cards may default to another block size
+        self.block_addressing || self.command(CMD16, BLOCK_SIZE as u32) == 0
+    }
+    
+    // Read one 512 byte block
+    pub fn read_block(&mut self, block: u32, buffer: &mut [u8; BLOCK_SIZE]) -> bool {
+        if !wait_ready() {
+            return false;
+        }
+        
+        select();
+        let ok = send_command(CMD17, self.address(block)) == 0
+            && wait_for(DATA_START, READ_POLLS);
+        if ok {
+            for byte in buffer.iter_mut() {
+                *byte = spi::transfer(0xFF);
+            }
+            
+            // CRC, not checked
+            spi::transfer(0xFF);
+            spi::transfer(0xFF);
+        }
+        deselect();
+        ok
+    }
+    
+    // Write one 512 byte block and wait until the card has stored it
+    pub fn write_block(&mut self, block: u32, buffer: &[u8; BLOCK_SIZE]) -> bool {
+        if !wait_ready() {
+            return false;
+        }
+        
+        select();
+        let mut ok = send_command(CMD24, self.address(block)) == 0;
+        if ok {
+            spi::transfer(0xFF);
+            spi::transfer(DATA_START);
+            for &byte in buffer.iter() {
+                spi::transfer(byte);
+            }
+            
+            // Dummy CRC
+            spi::transfer(0xFF);
+            spi::transfer(0xFF);
+            
+            ok = spi::transfer(0xFF) & 0x1F == DATA_ACCEPTED;
+        }
+        deselect();
+        
+        ok && wait_ready()
+    }
+    
+    // Send a command in its own transaction and return the R1 response
+    fn command(&mut self, command: u8, argument: u32) -> u8 {
+        if command != CMD0 && !wait_ready() {
+            return 0xFF;
+        }
+        select();
+        let response = send_command(command, argument);
+        deselect();
+        response
+    }
+    
+    // Standard capacity cards take byte addresses
+    fn address(&self, block: u32) -> u32 {
+        if self.block_addressing {
+            block
+        } else {
+            block * BLOCK_SIZE as u32
+        }
+    }
+}
+
+// Select the card, keeping the display off the bus
+fn select() {
+    spi::claim();
+    CSPin::set_low();
+}
+
+fn deselect() {
+    CSPin::set_high();
+    spi::transfer(0xFF); // Lets the card release MISO
+    spi::release();
+}
+
+// Send a command frame and return the R1 response (0xFF if none)
+fn send_command(command: u8, argument: u32) -> u8 {
+    spi::transfer(0x40 | command);
+    spi::transfer((argument >> 24) as u8);
+    spi::transfer((argument >> 16) as u8);
+    spi::transfer((argument >> 8) as u8);
+    spi::transfer(argument as u8);
+    
+    // CRC only matters before SPI mode is fully entered
+    let crc = match command {
+        CMD0 => 0x95,
+        CMD8 => 0x87,
+        _ => 0x01,
+    };
+    spi::transfer(crc);
+    
+    // The response arrives within 8 bytes, with bit 7 clear
+    for _ in 0..8 {
+        let response = spi::transfer(0xFF);
+        if response & 0x80 == 0 {
+            return response;
+        }
+    }
+    0xFF
+}
+
+// Wait for a token while selected
+fn wait_for(token: u8, polls: u16) -> bool {
+    for _ in 0..polls {
+        let response = spi::transfer(0xFF);
+        if response == token {
+            return true;
+        }
+        if response != 0xFF {
+            return false; // Error token
+        }
+        delay::delay_us(100);
+    }
+    false
+}
+
+// Wait until the card is no longer busy. The card is deselected between
+// polls so the display can refresh during long writes.
+fn wait_ready() -> bool {
+    for _ in 0..BUSY_POLLS {
+        select();
+        let response = spi::transfer(0xFF);
+        deselect();
+        if response == 0xFF {
+            return true;
+        }
+        delay::delay_us(100);
+    }
+    false
+}
@@ -1,6 +1,20 @@
 use crate::temperature;
 use crate::adc;
 use crate::ph;
+use crate::stability::StabilityDetector;
+use crate::statistics::Statistics;
+
+// Stability detection settings: a reading is "settled" once it has stayed
+// within the tolerance for the whole duration
+pub const PH_STABLE_TOLERANCE: f32 = 0.02;          // pH units
+pub const PH_STABLE_DURATION_MS: u64 = 10_000;
+pub const TEMPERATURE_STABLE_TOLERANCE: f32 = 0.1;  // °C
+pub const TEMPERATURE_STABLE_DURATION_MS: u64 = 30_000;
+
+// The sensors take turns: pH and the supply voltage are read for one phase,
+// then the temperature for the next, so a measurement cycle takes two phases
+// whatever the display shows
+const SENSOR_PHASE_MS: u64 = 3000;
 
 // Sensor state enum to track sensor operations
 enum SensorState {
@@ -14,13 +28,33 @@ enum SensorState {
 // Current sensor values 
 pub struct SensorValues {
     pub temperature: f32,
+    pub temperature_fault: bool, // Last temperature read failed
     pub ph: f32,
+    pub ph_adc: u16,         // Supply compensated pH ADC reading
+    pub supply_voltage: f32, // Measured AVCC in volts
+    pub ph_mv: f32,          // Raw electrode potential in mV
+    pub ph_slope: f32,       // Calibrated slope as % of Nernstian
+    pub ph_offset_mv: f32,   // Electrode potential at pH 7 in mV
+    pub ph_response_ms: u64, // Settling time of the last pH step
+    pub electrode_health: ph::ElectrodeHealth,
+    pub ph_stable: bool,
+    pub temperature_stable: bool,
+    pub cycles: u32,         // Completed measurement cycles (pH, then temperature)
+    pub temperature_statistics: Statistics, // Min/max/mean, kept in 0.1 °C
+    pub ph_statistics: Statistics,          // Min/max/mean, kept in 0.01 pH
+    #[cfg(feature = "light-sensor")]
+    pub light_level: u16,    // Raw ambient light reading, higher is brighter
 }
 
 // The Sensor Manager handles all sensor-related operations
 pub struct SensorManager {
     state: SensorState,
     pub values: SensorValues,
+    calibration: ph::Calibration,
+    ph_read: bool,           // pH has been read since the last cycle ended
+    ph_response: ph::ResponseTracker,
+    ph_stability: StabilityDetector,
+    temperature_stability: StabilityDetector,
 }
 
 impl SensorManager {
@@ -29,13 +63,36 @@ impl SensorManager {
         // Initialize with default values
         let values = SensorValues {
             temperature: 25.0,
+            temperature_fault: false,
             ph: 7.0,
+            ph_adc: 0,
+            supply_voltage: adc::NOMINAL_SUPPLY_MV as f32 / 1000.0,
+            ph_mv: 0.0,
+            ph_slope: 100.0,
+            ph_offset_mv: 0.0,
+            ph_response_ms: 0,
+            electrode_health: ph::ElectrodeHealth::Ok,
+            ph_stable: false,
+            temperature_stable: false,
+            cycles: 0,
+            temperature_statistics: Statistics::new(10.0),
+            ph_statistics: Statistics::new(100.0),
+            #[cfg(feature = "light-sensor")]
+            light_level: 1023,
         };
         
         // Start in idle state
         SensorManager {
             state: SensorState::Idle,
             values,
+            calibration: ph::Calibration::DEFAULT,
+            ph_read: false,
+            ph_response: ph::ResponseTracker::new(),
+            ph_stability: StabilityDetector::new(PH_STABLE_TOLERANCE, PH_STABLE_DURATION_MS),
+            temperature_stability: StabilityDetector::new(
+                TEMPERATURE_STABLE_TOLERANCE,
+                TEMPERATURE_STABLE_DURATION_MS
+            ),
         }
     }
     
@@ -45,23 +102,37 @@ impl SensorManager {
         temperature::initialize();
     }
     
+    // Use a new pH calibration from the next reading on
+    pub fn set_calibration(&mut self, calibration: ph::Calibration) {
+        self.calibration = calibration;
+    }
+    
+    // Start the statistics over from now
+    pub fn reset_statistics(&mut self, current_time: u64) {
+        self.values.temperature_statistics.reset(current_time);
+        self.values.ph_statistics.reset(current_time);
+    }
+    
     // Start the initial temperature reading
     pub fn start_initial_temperature_reading(&mut self) {
         temperature::start_temperature_conversion();
         self.state = SensorState::TemperatureConverting(0);
     }
     
-    // Update sensor operations based on current state and display mode
-    pub fn update(&mut self, current_time: u64, is_showing_temperature: bool) {
+    // Update sensor operations based on the current state and phase. While
+    // calibrating only pH is read, so it follows the probe without gaps.
+    pub fn update(&mut self, current_time: u64, calibrating: bool) {
+        self.update_stability(current_time);
+        self.values.temperature_statistics.update(current_time);
+        self.values.ph_statistics.update(current_time);
+        
         match self.state {
             SensorState::Idle => {
                 // Idle state - no ongoing sensor operations
-                // Check if we need to start a new sensor reading based on display mode
-                if is_showing_temperature {
-                    // Start reading pH in the background for next switch
+                // Start the reading of the sensor whose phase it is
+                if calibrating || (current_time / SENSOR_PHASE_MS) % 2 == 0 {
                     self.state = SensorState::PHReading;
                 } else {
-                    // Start temperature conversion for next switch
                     temperature::start_temperature_conversion();
                     self.state = SensorState::TemperatureConverting(current_time);
                 }
@@ -78,8 +149,27 @@ impl SensorManager {
             
             SensorState::TemperatureReady => {
                 // Read the temperature value
-                if let Some(temp) = temperature::read_temperature_after_conversion() {
-                    self.values.temperature = temp as f32 / 10.0;
+                match temperature::read_temperature_after_conversion() {
+                    Some(temp) => {
+                        self.values.temperature = temp as f32 / 10.0;
+                        self.values.temperature_fault = false;
+                    },
+                    None => {
+                        // Keep the last good value but flag the failure
+                        self.values.temperature_fault = true;
+                    }
+                }
+                
+                // A cycle is complete once both have been read (the initial
+                // temperature reading comes before any pH). One statistics
+                // sample of each per cycle, as pH is read many times in between
+                if self.ph_read {
+                    if !self.values.temperature_fault {
+                        self.values.temperature_statistics.add(self.values.temperature);
+                    }
+                    self.values.ph_statistics.add(self.values.ph);
+                    self.values.cycles = self.values.cycles.wrapping_add(1);
+                    self.ph_read = false;
                 }
                 
                 // Move back to idle state
@@ -87,10 +177,29 @@ impl SensorManager {
             },
             
             SensorState::PHReading => {
-                // Read pH (this is fast, so we do it immediately)
-                let ph_raw = adc::read(adc::ADC0);
-                let ph_raw_value = ph::adc_to_ph(ph_raw);
+                // Measure the supply so the pH reading can be compensated for it
+                let supply_mv = adc::measure_supply_mv();
+                self.values.supply_voltage = supply_mv as f32 / 1000.0;
+                
+                // Read pH (this is fast, so we do it immediately). The first
+                // conversion after the bandgap is unreliable, so discard it
+                adc::read(adc::ADC0);
+                let ph_raw = adc::compensate(adc::read(adc::ADC0), supply_mv);
+                let ph_raw_value = ph::adc_to_ph(ph_raw, &self.calibration);
                 self.values.ph = ph_raw_value as f32 / 100.0;
+                self.values.ph_adc = ph_raw;
+                self.ph_read = true;
+                
+                // Ambient light, read alongside pH since it is just as quick
+                #[cfg(feature = "light-sensor")]
+                {
+                    self.values.light_level = adc::read(adc::ADC1);
+                }
+                
+                // Electrode diagnostics
+                self.values.ph_mv = ph::adc_to_mv(ph_raw);
+                self.ph_response.update(self.values.ph_mv, current_time);
+                self.update_electrode_health();
                 
                 // Mark pH as ready
                 self.state = SensorState::PHReady;
@@ -102,4 +211,24 @@ impl SensorManager {
             }
         }
     }
-} 
\ No newline at end of file
+    
+    // Refresh the electrode slope, offset and health classification
+    fn update_electrode_health(&mut self) {
+        self.values.ph_slope = ph::slope_percent(&self.calibration, self.values.temperature);
+        self.values.ph_offset_mv = ph::offset_mv(&self.calibration);
+        self.values.ph_response_ms = self.ph_response.response_ms;
+        self.values.electrode_health = ph::health(
+            self.values.ph_slope,
+            self.values.ph_offset_mv,
+            self.values.ph_response_ms
+        );
+    }
+    
+    // Sample the latest readings into the stability windows
+    fn update_stability(&mut self, current_time: u64) {
+        self.ph_stability.update(self.values.ph, current_time);
+        self.temperature_stability.update(self.values.temperature, current_time);
+        self.values.ph_stable = self.ph_stability.is_stable();
+        self.values.temperature_stable = self.temperature_stability.is_stable();
+    }
+}
\ No newline at end of file
@@ -0,0 +1,238 @@
+use core::ops::Range;
+
+use crate::air;
+use crate::eeprom;
+use crate::history;
+use crate::ph::Calibration;
+
+// Settings kept in EEPROM
+//
+// Layout at eeprom::SETTINGS_ADDRESS:
+//
+//   0      layout version
+//   1-8    pH calibration: adc_1, ph_1, adc_2, ph_2 (u16, little endian)
+//   9-10   aeration period (minutes)
+//   11-12  aeration duration (seconds)
+//   13-14  history interval (minutes), since version 0xA6
+//   15-16  Modbus slave address, since version 0xA7
+//   17-18  Wi-Fi post port, 0 for the build's default, since version 0xA8
+//   19-42  Wi-Fi post host, since version 0xA8
+//   43-58  Wi-Fi post path, since version 0xA8
+//   last   checksum of the bytes before it
+//
+// Each version adds fields after the last one's and moves the checksum
+// behind them. A block of an older version is read as far as it goes and
+// the fields it lacks get their defaults, so a firmware update keeps the
+// calibration. A blank (0xFF) or corrupted block gives the defaults, and a
+// field out of range gives its own default.
+//
+// Text settings are padded with zeros, and left empty for the build's
+// default. They are only kept in EEPROM, read when needed, to save RAM.
+
+// Block length of each layout version, oldest first; add a version for
+// every layout change and keep the old ones
+const VERSIONS: [(u8, usize); 4] = [(0xA5, 14), (0xA6, 16), (0xA7, 18), (0xA8, 60)];
+
+// The version written by save()
+const VERSION: u8 = VERSIONS[VERSIONS.len() - 1].0;
+const LENGTH: usize = VERSIONS[VERSIONS.len() - 1].1;
+
+// Modbus slave addresses (0 is broadcast, 248 and up are reserved)
+pub const DEFAULT_MODBUS_ADDRESS: u16 = 1;
+pub const MAX_MODBUS_ADDRESS: u16 = 247;
+
+// Longest Wi-Fi post host and path
+pub const HOST_SIZE: usize = 24;
+pub const PATH_SIZE: usize = 16;
+
+// Longest text setting
+pub const TEXT_SIZE: usize = HOST_SIZE;
+
+// Settings kept as text
+#[derive(Copy, Clone)]
+pub enum Text {
+    PostHost, // Server the Wi-Fi uplink posts to, a name or IP address
+    PostPath, // Path the readings are posted to
+}
+
+impl Text {
+    // Longest value
+    pub fn size(self) -> usize {
+        let range = self.range();
+        range.end - range.start
+    }
+    
+    // Can the value be saved? It may be empty, for the default.
+    pub fn accepts(self, value: &[u8]) -> bool {
+        value.len() <= self.size()
+            && value.iter().all(|&byte| byte.is_ascii_graphic() && byte != b'"' && byte != b'\\')
+            && match self {
+                Text::PostHost => true,
+                Text::PostPath => value.is_empty() || value[0] == b'/',
+            }
+    }
+    
+    // Where the value is in the block
+    fn range(self) -> Range<usize> {
+        match self {
+            Text::PostHost => 19..19 + HOST_SIZE,
+            Text::PostPath => 43..43 + PATH_SIZE,
+        }
+    }
+}
+
+pub struct Settings {
+    pub calibration: Calibration,
+    pub aeration_period_min: u16,
+    pub aeration_duration_s: u16,
+    pub history_interval_min: u16,
+    pub modbus_address: u16,
+    pub post_port: u16,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            calibration: Calibration::DEFAULT,
+            aeration_period_min: (air::AERATION_PERIOD_MS / 60_000) as u16,
+            aeration_duration_s: (air::AERATION_DURATION_MS / 1000) as u16,
+            history_interval_min: history::DEFAULT_INTERVAL_MIN,
+            modbus_address: DEFAULT_MODBUS_ADDRESS,
+            post_port: 0,
+        }
+    }
+}
+
+// Read the settings, falling back to the defaults for any not saved
+pub fn load() -> Settings {
+    let mut data = [0u8; LENGTH];
+    let mut settings = Settings::default();
+    let length = match read_block(&mut data) {
+        Some(length) => length,
+        None => return settings,
+    };
+    
+    // A field the saved version does not have reads as None
+    let word = |offset: usize| {
+        if offset + 2 < length {
+            Some(data[offset] as u16 | (data[offset + 1] as u16) << 8)
+        } else {
+            None
+        }
+    };
+    
+    // Never run with a calibration that would divide by zero
+    let calibration = Calibration {
+        adc_1: word(1).unwrap_or(0),
+        ph_1: word(3).unwrap_or(0),
+        adc_2: word(5).unwrap_or(0),
+        ph_2: word(7).unwrap_or(0),
+    };
+    if calibration.is_valid() {
+        settings.calibration = calibration;
+    }
+    if let (Some(period), Some(duration)) = (word(9), word(11)) {
+        if period != 0 {
+            settings.aeration_period_min = period;
+            settings.aeration_duration_s = duration;
+        }
+    }
+    if let Some(interval) = word(13).filter(|interval| *interval >= history::MIN_INTERVAL_MIN) {
+        settings.history_interval_min = interval;
+    }
+    if let Some(address) = word(15).filter(|address| (1..=MAX_MODBUS_ADDRESS).contains(address)) {
+        settings.modbus_address = address;
+    }
+    if let Some(port) = word(17) {
+        settings.post_port = port;
+    }
+    settings
+}
+
+// Read a text setting into value, returning its length: 0 if it was never
+// saved or is left to the default
+pub fn load_text(text: Text, value: &mut [u8; TEXT_SIZE]) -> usize {
+    let mut data = [0u8; LENGTH];
+    let range = text.range();
+    match read_block(&mut data) {
+        Some(length) if range.end < length => {},
+        _ => return 0,
+    }
+    
+    let field = &data[range];
+    let length = field.iter().position(|&byte| byte == 0).unwrap_or(field.len());
+    if !text.accepts(&field[..length]) {
+        return 0;
+    }
+    value[..length].copy_from_slice(&field[..length]);
+    length
+}
+
+// Write the settings; unchanged bytes are not rewritten
+pub fn save(settings: &Settings) {
+    write(settings, None);
+}
+
+// Change a text setting, which text.accepts(value), and write the settings
+pub fn save_text(settings: &Settings, text: Text, value: &[u8]) {
+    write(settings, Some((text, value)));
+}
+
+fn write(settings: &Settings, text: Option<(Text, &[u8])>) {
+    let words = [
+        settings.calibration.adc_1,
+        settings.calibration.ph_1,
+        settings.calibration.adc_2,
+        settings.calibration.ph_2,
+        settings.aeration_period_min,
+        settings.aeration_duration_s,
+        settings.history_interval_min,
+        settings.modbus_address,
+        settings.post_port,
+    ];
+    
+    // Keep the saved text settings, which are not held in RAM
+    let mut data = [0u8; LENGTH];
+    if read_block(&mut data) != Some(LENGTH) {
+        data = [0; LENGTH];
+    }
+    
+    data[0] = VERSION;
+    for (index, word) in words.iter().enumerate() {
+        data[1 + index * 2] = *word as u8;
+        data[2 + index * 2] = (*word >> 8) as u8;
+    }
+    if let Some((text, value)) = text {
+        let field = &mut data[text.range()];
+        for (index, byte) in field.iter_mut().enumerate() {
+            *byte = value.get(index).copied().unwrap_or(0);
+        }
+    }
+    data[LENGTH - 1] = checksum(&data[..LENGTH - 1]);
+    
+    eeprom::update(eeprom::SETTINGS_ADDRESS, &data);
+}
+
+// Apply the aeration schedule from the settings
+pub fn apply_aeration(settings: &Settings) {
+    air::set_schedule(
+        settings.aeration_period_min as u64 * 60_000,
+        settings.aeration_duration_s as u64 * 1000
+    );
+}
+
+// Read the block, returning the length of its version if it is valid
+fn read_block(data: &mut [u8; LENGTH]) -> Option<usize> {
+    eeprom::read(eeprom::SETTINGS_ADDRESS, data);
+    let &(_, length) = VERSIONS.iter().find(|(version, _)| *version == data[0])?;
+    if data[length - 1] == checksum(&data[..length - 1]) {
+        Some(length)
+    } else {
+        None
+    }
+}
+
+// Simple additive checksum, inverted so an all-zero block does not pass
+fn checksum(data: &[u8]) -> u8 {
+    !data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
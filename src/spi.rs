@@ -0,0 +1,104 @@
+use ruduino::Register;
+use ruduino::Pin;
+use ruduino::cores::current::port::{B2, B3, B4, B5};
+#[cfg(feature = "sd-logger")]
+use core::sync::atomic::{AtomicBool, Ordering};
+
+// Register definitions for SPI
+pub struct SPCR;
+impl Register for SPCR {
+    type T = u8;
+    const ADDRESS: *mut u8 = 0x4C as *mut u8;
+}
+
+// SPI Status Register
+pub struct SPSR;
+impl Register for SPSR {
+    type T = u8;
+    const ADDRESS: *mut u8 = 0x4D as *mut u8;
+}
+
+// SPI Data Register
+pub struct SPDR;
+impl Register for SPDR {
+    type T = u8;
+    const ADDRESS: *mut u8 = 0x4E as *mut u8;
+}
+
+// SPCR bits
+pub const SPE: u8 = 1 << 6;    // SPI Enable
+// pub const DORD: u8 = 1 << 5;   // Data Order (1 = LSB first)
+pub const MSTR: u8 = 1 << 4;   // Master Select
+// pub const CPOL: u8 = 1 << 3;   // Clock Polarity
+// pub const CPHA: u8 = 1 << 2;   // Clock Phase
+#[cfg(feature = "sd-logger")]
+pub const SPR1: u8 = 1 << 1;   // Clock Rate Select Bit 1
+#[cfg(feature = "sd-logger")]
+pub const SPR0: u8 = 1 << 0;   // Clock Rate Select Bit 0
+
+// SPSR bits
+pub const SPIF: u8 = 1 << 7;   // SPI Interrupt Flag (transfer complete)
+pub const SPI2X: u8 = 1 << 0;  // Double SPI Speed
+
+// Hardware SPI pins
+pub type SSPin = B2;    // Must stay an output in master mode
+pub type MOSIPin = B3;
+pub type MISOPin = B4;
+pub type SCKPin = B5;
+
+// Set while a device needs the bus for a multi-byte transaction (e.g. an SD
+// card command); the display refresh interrupt skips its transfer meanwhile
+#[cfg(feature = "sd-logger")]
+static CLAIMED: AtomicBool = AtomicBool::new(false);
+
+// Initialize the SPI peripheral as master, mode 0, MSB first, at 8MHz (F_CPU/2)
+pub fn initialize() {
+    // If SS were an input and pulled low the SPI would drop out of master mode
+    SSPin::set_output();
+    MOSIPin::set_output();
+    SCKPin::set_output();
+    MISOPin::set_input();
+    
+    SPCR::write(SPE | MSTR);
+    SPSR::write(SPI2X);
+}
+
+// Switch between the normal 8MHz clock and 125kHz (F_CPU/128), which SD
+// cards need until they are initialized
+#[cfg(feature = "sd-logger")]
+pub fn set_slow_clock(slow: bool) {
+    if slow {
+        SPCR::write(SPE | MSTR | SPR1 | SPR0);
+        SPSR::write(0);
+    } else {
+        SPCR::write(SPE | MSTR);
+        SPSR::write(SPI2X);
+    }
+}
+
+// Reserve the bus for the main loop
+#[cfg(feature = "sd-logger")]
+pub fn claim() {
+    CLAIMED.store(true, Ordering::Release);
+}
+
+#[cfg(feature = "sd-logger")]
+pub fn release() {
+    CLAIMED.store(false, Ordering::Release);
+}
+
+// Is a transaction in progress? (checked from the display interrupt)
+#[cfg(all(feature = "sd-logger", feature = "display-spi"))]
+pub fn is_claimed() -> bool {
+    CLAIMED.load(Ordering::Acquire)
+}
+
+// Send a byte and return the byte clocked in at the same time
+pub fn transfer(data: u8) -> u8 {
+    SPDR::write(data);
+    
+    // Wait for the transfer to complete (16 CPU cycles at F_CPU/2)
+    while SPSR::read() & SPIF == 0 {}
+    
+    SPDR::read()
+}
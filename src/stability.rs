@@ -0,0 +1,73 @@
+// Reading stability detection
+//
+// A reading is considered stable ("settled") once it has stayed within a
+// tolerance band for a full duration. The duration is covered by a sliding
+// window of evenly spaced samples, so it does not depend on how often the
+// sensor itself is read.
+
+// Number of samples in the sliding window
+const WINDOW_SIZE: usize = 10;
+
+pub struct StabilityDetector {
+    samples: [f32; WINDOW_SIZE],
+    count: usize,      // Number of valid samples in the window
+    next: usize,       // Index the next sample is written to
+    last_sample_time: Option<u64>,
+    tolerance: f32,    // Maximum spread (max - min) allowed in the window
+    duration_ms: u64,  // Time the reading must stay within tolerance
+    stable: bool,
+}
+
+impl StabilityDetector {
+    // Create a detector with the given tolerance and settling duration
+    pub fn new(tolerance: f32, duration_ms: u64) -> Self {
+        StabilityDetector {
+            samples: [0.0; WINDOW_SIZE],
+            count: 0,
+            next: 0,
+            last_sample_time: None,
+            tolerance,
+            duration_ms,
+            stable: false,
+        }
+    }
+    
+    // Feed the current value; it is sampled at duration / window size intervals
+    pub fn update(&mut self, value: f32, current_time: u64) {
+        let interval = self.duration_ms / (WINDOW_SIZE as u64 - 1);
+        if let Some(last_time) = self.last_sample_time {
+            if current_time < last_time + interval {
+                return;
+            }
+        }
+        self.last_sample_time = Some(current_time);
+        
+        self.samples[self.next] = value;
+        self.next = (self.next + 1) % WINDOW_SIZE;
+        if self.count < WINDOW_SIZE {
+            self.count += 1;
+        }
+        
+        self.stable = self.count == WINDOW_SIZE && self.spread() <= self.tolerance;
+    }
+    
+    // Has the value stayed within tolerance for the whole duration?
+    pub fn is_stable(&self) -> bool {
+        self.stable
+    }
+    
+    // Difference between the highest and lowest sample in the window
+    fn spread(&self) -> f32 {
+        let mut min = self.samples[0];
+        let mut max = self.samples[0];
+        for &sample in &self.samples[..self.count] {
+            if sample < min {
+                min = sample;
+            }
+            if sample > max {
+                max = sample;
+            }
+        }
+        max - min
+    }
+}
@@ -0,0 +1,56 @@
+use core::ptr::{addr_of_mut, read_volatile, write_volatile};
+
+// Stack high-water mark
+//
+// The statics sit at the bottom of RAM and the stack grows down from the
+// top, with the free RAM in between. At boot the free RAM is painted with
+// PAINT, and the bytes the stack never reaches keep it. A reset leaves RAM as
+// it was (the start-up code only clears the statics), so counting the
+// painted bytes before painting again gives the previous run's unused RAM:
+// how close its deepest call came to the statics. A power cut loses it.
+
+const PAINT: u8 = 0xA5;
+
+// Left unpainted below the stack pointer, for the painting's own frame
+const MARGIN: usize = 32;
+
+// Stack pointer
+const SPL: *const u8 = 0x5D as *const u8;
+const SPH: *const u8 = 0x5E as *const u8;
+
+extern "C" {
+    // End of the statics, from the avr-libc linker script
+    static mut __heap_start: u8;
+}
+
+// Bytes the stack never reached in the run before the reset
+static mut PREVIOUS_UNUSED: u16 = 0;
+
+// Count what the previous run left painted, then paint the free RAM again.
+// Call early at boot, before any deep calls.
+pub fn initialize() {
+    unsafe {
+        let start = addr_of_mut!(__heap_start);
+        let length = (stack_pointer() - MARGIN).saturating_sub(start as usize);
+        
+        let mut unused = 0;
+        while unused < length && read_volatile(start.add(unused)) == PAINT {
+            unused += 1;
+        }
+        PREVIOUS_UNUSED = unused as u16;
+        
+        for offset in 0..length {
+            write_volatile(start.add(offset), PAINT);
+        }
+    }
+}
+
+// Bytes of RAM the stack never reached before the last reset, meaningless
+// after a power-on reset
+pub fn previous_unused() -> u16 {
+    unsafe { PREVIOUS_UNUSED }
+}
+
+fn stack_pointer() -> usize {
+    unsafe { read_volatile(SPL) as usize | (read_volatile(SPH) as usize) << 8 }
+}
@@ -0,0 +1,221 @@
+// Running statistics of a measurement
+//
+// Minimum, maximum, mean and sample count since the last reset and over
+// rolling windows of the last hour and day. The windows are rings of
+// buckets to fit in RAM, so they cover the current bucket plus the
+// previous ones: 50-60 minutes for the hour, 22-24 hours for the day.
+// Buckets age with the uptime from the millisecond timer, so the windows
+// span real time however long the main loop takes. Values are kept in fixed
+// point (e.g. tenths of a degree). Each Statistics takes 210 bytes of RAM,
+// 185 of them in the buckets.
+
+const HOUR_BUCKETS: usize = 6;
+const HOUR_BUCKET_S: u32 = 600;     // 10 minutes
+const DAY_BUCKETS: usize = 12;
+const DAY_BUCKET_S: u32 = 7200;     // 2 hours
+
+// Bytes of the totals since the reset: min, max, mean (i16) and count
+// (u32), little endian. The mean rather than the sum, as it hardly changes
+// from one checkpoint to the next.
+pub const TOTALS_SIZE: usize = 10;
+
+// Statistics over one period, in the measurement's units
+#[derive(Copy, Clone)]
+pub struct Summary {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub count: u32, // 0 if there were no samples
+}
+
+// Samples collected in one bucket
+#[derive(Copy, Clone)]
+struct Bucket {
+    min: i16,
+    max: i16,
+    sum: i32,
+    count: u16,
+}
+
+impl Bucket {
+    const EMPTY: Bucket = Bucket {
+        min: i16::MAX,
+        max: i16::MIN,
+        sum: 0,
+        count: 0,
+    };
+    
+    fn add(&mut self, value: i16) {
+        if self.count == u16::MAX {
+            return;
+        }
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value as i32;
+        self.count += 1;
+    }
+}
+
+// A rolling window made of N buckets of BUCKET_S seconds
+struct Window<const N: usize, const BUCKET_S: u32> {
+    buckets: [Bucket; N],
+    index: u8,           // Bucket being filled
+    bucket_start_s: u32, // When it started (uptime in seconds)
+}
+
+impl<const N: usize, const BUCKET_S: u32> Window<N, BUCKET_S> {
+    fn new() -> Self {
+        Window {
+            buckets: [Bucket::EMPTY; N],
+            index: 0,
+            bucket_start_s: 0,
+        }
+    }
+    
+    // Empty every bucket, starting the first at the current time
+    fn reset(&mut self, current_time: u64) {
+        for bucket in self.buckets.iter_mut() {
+            *bucket = Bucket::EMPTY;
+        }
+        self.index = 0;
+        self.bucket_start_s = (current_time / 1000) as u32;
+    }
+    
+    // Move on to a fresh bucket once the current one has run its time
+    fn update(&mut self, current_time: u64) {
+        let current_s = (current_time / 1000) as u32;
+        let mut steps = 0;
+        while current_s >= self.bucket_start_s + BUCKET_S {
+            self.bucket_start_s += BUCKET_S;
+            if steps < N {
+                self.index = ((self.index as usize + 1) % N) as u8;
+                self.buckets[self.index as usize] = Bucket::EMPTY;
+                steps += 1;
+            }
+        }
+    }
+    
+    fn add(&mut self, value: i16) {
+        self.buckets[self.index as usize].add(value);
+    }
+    
+    // Min, max, sum and count over all buckets
+    fn totals(&self) -> (i16, i16, i64, u32) {
+        let mut totals = (i16::MAX, i16::MIN, 0i64, 0u32);
+        for bucket in self.buckets.iter().filter(|bucket| bucket.count > 0) {
+            totals.0 = totals.0.min(bucket.min);
+            totals.1 = totals.1.max(bucket.max);
+            totals.2 += bucket.sum as i64;
+            totals.3 += bucket.count as u32;
+        }
+        totals
+    }
+}
+
+pub struct Statistics {
+    scale: f32, // Fixed point units per measurement unit
+    min: i16,
+    max: i16,
+    sum: i64,
+    count: u32,
+    hour: Window<HOUR_BUCKETS, HOUR_BUCKET_S>,
+    day: Window<DAY_BUCKETS, DAY_BUCKET_S>,
+}
+
+impl Statistics {
+    // Statistics kept with the given resolution, e.g. 10.0 for tenths
+    pub fn new(scale: f32) -> Self {
+        Statistics {
+            scale,
+            min: i16::MAX,
+            max: i16::MIN,
+            sum: 0,
+            count: 0,
+            hour: Window::new(),
+            day: Window::new(),
+        }
+    }
+    
+    // Forget everything, starting the windows at the current time
+    pub fn reset(&mut self, current_time: u64) {
+        self.min = i16::MAX;
+        self.max = i16::MIN;
+        self.sum = 0;
+        self.count = 0;
+        self.hour.reset(current_time);
+        self.day.reset(current_time);
+    }
+    
+    // Age the rolling windows, call regularly even without new samples
+    pub fn update(&mut self, current_time: u64) {
+        self.hour.update(current_time);
+        self.day.update(current_time);
+    }
+    
+    pub fn add(&mut self, value: f32) {
+        let limit = i16::MAX as f32 / self.scale;
+        let value = libm::roundf(value.clamp(-limit, limit) * self.scale) as i16;
+        
+        if self.count < u32::MAX {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+            self.sum += value as i64;
+            self.count += 1;
+        }
+        self.hour.add(value);
+        self.day.add(value);
+    }
+    
+    // The totals since the reset, to be restored after a power cut
+    pub fn save_totals(&self) -> [u8; TOTALS_SIZE] {
+        let mean = if self.count > 0 {
+            libm::roundf(self.sum as f32 / self.count as f32) as i16
+        } else {
+            0
+        };
+        let mut data = [0u8; TOTALS_SIZE];
+        data[0..2].copy_from_slice(&self.min.to_le_bytes());
+        data[2..4].copy_from_slice(&self.max.to_le_bytes());
+        data[4..6].copy_from_slice(&mean.to_le_bytes());
+        data[6..10].copy_from_slice(&self.count.to_le_bytes());
+        data
+    }
+    
+    // Carry on from saved totals; the rolling windows start empty
+    pub fn restore_totals(&mut self, data: &[u8]) {
+        self.min = i16::from_le_bytes([data[0], data[1]]);
+        self.max = i16::from_le_bytes([data[2], data[3]]);
+        let mean = i16::from_le_bytes([data[4], data[5]]);
+        self.count = u32::from_le_bytes([data[6], data[7], data[8], data[9]]);
+        self.sum = mean as i64 * self.count as i64;
+    }
+    
+    pub fn since_reset(&self) -> Summary {
+        self.summary((self.min, self.max, self.sum, self.count))
+    }
+    
+    pub fn last_hour(&self) -> Summary {
+        self.summary(self.hour.totals())
+    }
+    
+    pub fn last_day(&self) -> Summary {
+        self.summary(self.day.totals())
+    }
+    
+    fn summary(&self, (min, max, sum, count): (i16, i16, i64, u32)) -> Summary {
+        if count == 0 {
+            return Summary {
+                min: 0.0,
+                max: 0.0,
+                mean: 0.0,
+                count: 0,
+            };
+        }
+        Summary {
+            min: min as f32 / self.scale,
+            max: max as f32 / self.scale,
+            mean: (sum as f32 / count as f32) / self.scale,
+            count,
+        }
+    }
+}
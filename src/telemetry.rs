@@ -0,0 +1,213 @@
+use crate::uart;
+use crate::sensor_manager::SensorValues;
+use crate::actuators::{self, Output};
+use crate::alarm;
+use crate::clock;
+use crate::watchdog::{self, ResetCause};
+use crate::stack;
+use crate::settings::{self, Settings, Text};
+use crate::statistics::{Statistics, Summary};
+#[cfg(feature = "sd-logger")]
+use crate::logger::{SdLogger, Status};
+
+// Telemetry output over UART
+//
+// Each report is a single line of space separated key=value pairs, e.g.
+// "temp=25.3 ph=7.01 vcc=4.98 mv=-0.4 slope=98.6 e0=0.6 resp=12000 electrode=ok
+// temp_stable=1 ph_stable=0 alarm=0", so it can be read in a terminal or parsed by a
+// host script. The pump state follows, e.g. " air=0 air_lockout=temp", and
+// once a host has set the clock, " time=<Unix time>".
+
+// Initialize the serial port used for telemetry
+pub fn initialize() {
+    uart::initialize();
+}
+
+// Send the current sensor values as one telemetry line
+#[cfg(not(feature = "sd-logger"))]
+pub fn report(values: &SensorValues, current_time: u64) {
+    send_readings(values, current_time);
+    uart::send_string("\r\n");
+}
+
+// Send the current sensor values and the SD card logger state as one
+// telemetry line, adding e.g. "sd=ok sd_free=3712 sd_errors=0" (without
+// sd_free while the free space is being counted)
+#[cfg(feature = "sd-logger")]
+pub fn report_with_log(values: &SensorValues, logger: &SdLogger, current_time: u64) {
+    send_readings(values, current_time);
+    uart::send_string(" sd=");
+    uart::send_string(match logger.status() {
+        Status::NoCard => "none",
+        Status::Ready => "ok",
+        Status::Full => "full",
+        Status::Unsupported => "format",
+    });
+    if let Some(free_mb) = logger.free_mb() {
+        uart::send_string(" sd_free=");
+        send_u32(free_mb);
+    }
+    uart::send_string(" sd_errors=");
+    uart::send_integer(logger.errors(), 10);
+    uart::send_string("\r\n");
+}
+
+// The sensor fields of a telemetry line
+fn send_readings(values: &SensorValues, current_time: u64) {
+    send_field("temp", values.temperature, 1);
+    uart::send_byte(b' ');
+    send_field("ph", values.ph, 2);
+    uart::send_byte(b' ');
+    send_field("vcc", values.supply_voltage, 2);
+    uart::send_byte(b' ');
+    send_field("mv", values.ph_mv, 1);
+    uart::send_byte(b' ');
+    send_field("slope", values.ph_slope, 1);
+    uart::send_byte(b' ');
+    send_field("e0", values.ph_offset_mv, 1);
+    uart::send_string(" resp=");
+    send_u32(values.ph_response_ms as u32);
+    uart::send_string(" electrode=");
+    uart::send_string(values.electrode_health.code());
+    uart::send_string(" temp_stable=");
+    send_flag(values.temperature_stable);
+    uart::send_string(" ph_stable=");
+    send_flag(values.ph_stable);
+    uart::send_string(" alarm=");
+    uart::send_integer(alarm::condition(values).code() as u16, 10);
+    uart::send_string(" air=");
+    send_flag(actuators::is_on(Output::AirPump));
+    uart::send_string(" air_lockout=");
+    uart::send_string(actuators::lockout(Output::AirPump).code());
+    if let Some(unix_time) = clock::now(current_time) {
+        uart::send_string(" time=");
+        send_u32(unix_time);
+    }
+}
+
+// Say why the monitor started, how often it has reset, whether it carried
+// on from a checkpoint and, unless the power was off, how many bytes of RAM
+// the stack never reached before the reset:
+// "reset cause=watchdog resets=12 watchdog=3 brownout=0 resumed=1 stack_free=310"
+pub fn report_reset(resumed: bool) {
+    let counts = watchdog::reset_counts();
+    uart::send_string("reset cause=");
+    uart::send_string(watchdog::reset_cause().code());
+    uart::send_string(" resets=");
+    uart::send_integer(counts.total, 10);
+    uart::send_string(" watchdog=");
+    uart::send_integer(counts.watchdog, 10);
+    uart::send_string(" brownout=");
+    uart::send_integer(counts.brownout, 10);
+    uart::send_string(" resumed=");
+    send_flag(resumed);
+    if watchdog::reset_cause() != ResetCause::PowerOn {
+        uart::send_string(" stack_free=");
+        uart::send_integer(stack::previous_unused(), 10);
+    }
+    uart::send_string("\r\n");
+}
+
+// Send the min/max/mean statistics, one line per measurement and window:
+// "stats ph window=24h min=6.91 max=7.12 mean=7.02 count=14400"
+pub fn report_statistics(values: &SensorValues) {
+    send_statistics("temp", &values.temperature_statistics, 1);
+    send_statistics("ph", &values.ph_statistics, 2);
+}
+
+// Report the Wi-Fi uplink's saved endpoint, e.g.
+// "post host=192.168.1.20 port=8080 path=default", where "default" leaves
+// it to the build
+pub fn report_endpoint(settings: &Settings) {
+    uart::send_string("post host=");
+    send_text(Text::PostHost);
+    uart::send_string(" port=");
+    if settings.post_port == 0 {
+        uart::send_string("default");
+    } else {
+        send_u32(settings.post_port as u32);
+    }
+    uart::send_string(" path=");
+    send_text(Text::PostPath);
+    uart::send_string("\r\n");
+}
+
+fn send_text(text: Text) {
+    let mut value = [0; settings::TEXT_SIZE];
+    let length = settings::load_text(text, &mut value);
+    if length == 0 {
+        uart::send_string("default");
+    }
+    for &byte in &value[..length] {
+        uart::send_byte(byte);
+    }
+}
+
+fn send_statistics(name: &str, statistics: &Statistics, decimal_places: u8) {
+    send_summary(name, "all", &statistics.since_reset(), decimal_places);
+    send_summary(name, "1h", &statistics.last_hour(), decimal_places);
+    send_summary(name, "24h", &statistics.last_day(), decimal_places);
+}
+
+fn send_summary(name: &str, window: &str, summary: &Summary, decimal_places: u8) {
+    uart::send_string("stats ");
+    uart::send_string(name);
+    uart::send_string(" window=");
+    uart::send_string(window);
+    
+    // Without samples only the count is meaningful
+    if summary.count > 0 {
+        uart::send_byte(b' ');
+        send_field("min", summary.min, decimal_places);
+        uart::send_byte(b' ');
+        send_field("max", summary.max, decimal_places);
+        uart::send_byte(b' ');
+        send_field("mean", summary.mean, decimal_places);
+    }
+    uart::send_string(" count=");
+    send_u32(summary.count);
+    uart::send_string("\r\n");
+}
+
+// Send a key=value pair with the value rounded to the given decimal places
+fn send_field(key: &str, value: f32, decimal_places: u8) {
+    uart::send_string(key);
+    uart::send_byte(b'=');
+    send_signed_decimal(value, decimal_places);
+}
+
+// Send a float as a fixed-point decimal, including a sign when negative
+fn send_signed_decimal(value: f32, decimal_places: u8) {
+    let mut scale = 1.0;
+    for _ in 0..decimal_places {
+        scale *= 10.0;
+    }
+    
+    let mut scaled = libm::roundf(value * scale);
+    if scaled < 0.0 {
+        uart::send_byte(b'-');
+        scaled = -scaled;
+    }
+    
+    // Clamp to what fits in the u16 formatter
+    if scaled > u16::MAX as f32 {
+        scaled = u16::MAX as f32;
+    }
+    
+    uart::send_decimal(scaled as u16, decimal_places);
+}
+
+// Send a boolean as 1 or 0
+fn send_flag(value: bool) {
+    uart::send_byte(if value { b'1' } else { b'0' });
+}
+
+// Send an unsigned integer that may exceed the u16 formatter
+fn send_u32(value: u32) {
+    if value > u16::MAX as u32 {
+        send_u32(value / 10);
+        uart::send_byte(b'0' + (value % 10) as u8);
+    } else {
+        uart::send_integer(value as u16, 10);
+    }
+}
@@ -1,5 +1,6 @@
 use ruduino::Pin;
 use ruduino::cores::current::port;
+use ruduino::interrupt::without_interrupts;
 
 // Temperature sensor pin
 pub type DS18B20Pin = port::D2;  // T2 pin for Dallas one-wire temperature sensor
@@ -25,13 +26,16 @@ pub fn reset() -> bool {
     DS18B20Pin::set_low();
     ruduino::delay::delay_us(500);
     
-    // Release the bus and wait for the presence pulse
-    DS18B20Pin::set_input();
-    DS18B20Pin::set_high(); // Enable pull-up
-    ruduino::delay::delay_us(70);
-    
-    // Read the bus state (low = device present)
-    let device_present = !DS18B20Pin::is_high();
+    // Release the bus and wait for the presence pulse. Interrupts are held
+    // off so the display refresh cannot push the sample past the pulse.
+    let device_present = without_interrupts(|| {
+        DS18B20Pin::set_input();
+        DS18B20Pin::set_high(); // Enable pull-up
+        ruduino::delay::delay_us(70);
+        
+        // Read the bus state (low = device present)
+        !DS18B20Pin::is_high()
+    });
     
     // Wait for the reset sequence to finish
     ruduino::delay::delay_us(410);
@@ -46,21 +50,24 @@ pub fn write_byte(mut byte: u8) {
         let bit = byte & 0x01;
         byte >>= 1;
         
-        DS18B20Pin::set_output();
-        
-        if bit == 0 {
-            // Write 0: Pull low for 60-120µs
-            DS18B20Pin::set_low();
-            ruduino::delay::delay_us(70);
-            DS18B20Pin::set_high();
-            ruduino::delay::delay_us(5);
-        } else {
-            // Write 1: Pull low for 1-15µs, then release
-            DS18B20Pin::set_low();
-            ruduino::delay::delay_us(10);
-            DS18B20Pin::set_high();
-            ruduino::delay::delay_us(55);
-        }
+        // Each time slot is timing critical, keep the display refresh out of it
+        without_interrupts(|| {
+            DS18B20Pin::set_output();
+            
+            if bit == 0 {
+                // Write 0: Pull low for 60-120µs
+                DS18B20Pin::set_low();
+                ruduino::delay::delay_us(70);
+                DS18B20Pin::set_high();
+                ruduino::delay::delay_us(5);
+            } else {
+                // Write 1: Pull low for 1-15µs, then release
+                DS18B20Pin::set_low();
+                ruduino::delay::delay_us(10);
+                DS18B20Pin::set_high();
+                ruduino::delay::delay_us(55);
+            }
+        });
     }
 }
 
@@ -69,19 +76,22 @@ pub fn read_byte() -> u8 {
     let mut byte: u8 = 0;
     
     for i in 0..8 {
-        DS18B20Pin::set_output();
-        
-        // Initiate read time slot with a low pulse
-        DS18B20Pin::set_low();
-        ruduino::delay::delay_us(5);
-        
-        // Release the bus
-        DS18B20Pin::set_input();
-        DS18B20Pin::set_high(); // Enable pull-up
-        ruduino::delay::delay_us(10);
-        
-        // Read the bit value
-        let bit = if DS18B20Pin::is_high() { 1 } else { 0 };
+        // Each time slot is timing critical, keep the display refresh out of it
+        let bit = without_interrupts(|| {
+            DS18B20Pin::set_output();
+            
+            // Initiate read time slot with a low pulse
+            DS18B20Pin::set_low();
+            ruduino::delay::delay_us(5);
+            
+            // Release the bus
+            DS18B20Pin::set_input();
+            DS18B20Pin::set_high(); // Enable pull-up
+            ruduino::delay::delay_us(10);
+            
+            // Read the bit value
+            if DS18B20Pin::is_high() { 1 } else { 0 }
+        });
         byte |= bit << i;
         
         // Wait for time slot to complete
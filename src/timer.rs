@@ -0,0 +1,66 @@
+use ruduino::interrupt::without_interrupts;
+
+// Millisecond time base
+//
+// Uptime counted by a 1kHz timer interrupt, so the clock, the schedules and
+// the statistics keep real time however long a main loop iteration takes
+// (telemetry, 1-Wire, EEPROM, LCD and SD card writes). The 74HC595 display
+// already multiplexes from a 1kHz Timer0 interrupt, which counts the
+// milliseconds as well; the display modules leave the timers free, so
+// Timer2 ticks for them.
+
+// Milliseconds since the interrupts were enabled
+static mut MILLISECONDS: u64 = 0;
+
+// Milliseconds since start-up; the interrupt must not update the count
+// halfway through reading its 8 bytes
+pub fn millis() -> u64 {
+    without_interrupts(|| unsafe { MILLISECONDS })
+}
+
+// Count one millisecond (called from the 1kHz interrupt)
+pub fn tick() {
+    unsafe {
+        MILLISECONDS += 1;
+    }
+}
+
+// Timer2 tick for the display modules
+#[cfg(any(feature = "display-tm1637", feature = "display-max7219", feature = "display-lcd"))]
+mod timer2 {
+    use core::ptr::{read_volatile, write_volatile};
+    
+    // Memory addresses for Timer2 registers
+    const TCCR2A: *mut u8 = 0xB0 as *mut u8;  // Timer/Counter2 Control Register A
+    const TCCR2B: *mut u8 = 0xB1 as *mut u8;  // Timer/Counter2 Control Register B
+    const OCR2A: *mut u8 = 0xB3 as *mut u8;   // Timer/Counter2 Output Compare Register A
+    const TIMSK2: *mut u8 = 0x70 as *mut u8;  // Timer/Counter2 Interrupt Mask Register
+    
+    // Timer2 bits
+    const WGM21: u8 = 1 << 1;   // CTC mode (TCCR2A)
+    const CS22: u8 = 1 << 2;    // Prescaler 64 (TCCR2B)
+    const OCIE2A: u8 = 1 << 1;  // Compare match A interrupt enable (TIMSK2)
+    
+    // 16MHz / 64 / (249 + 1) = 1kHz
+    const TICK_COMPARE: u8 = 249;
+    
+    // Start the tick (global interrupts are enabled once everything is
+    // initialized)
+    pub fn initialize() {
+        unsafe {
+            write_volatile(TCCR2A, WGM21);
+            write_volatile(OCR2A, TICK_COMPARE);
+            write_volatile(TCCR2B, CS22);
+            write_volatile(TIMSK2, read_volatile(TIMSK2) | OCIE2A);
+        }
+    }
+    
+    /// Timer2 compare match A interrupt handler, counts the milliseconds
+    #[no_mangle]
+    pub extern "avr-interrupt" fn __vector_7() {
+        super::tick();
+    }
+}
+
+#[cfg(any(feature = "display-tm1637", feature = "display-max7219", feature = "display-lcd"))]
+pub use timer2::initialize;
@@ -0,0 +1,135 @@
+use ruduino::Pin;
+use ruduino::cores::current::port::{B0, B1};
+use ruduino::delay;
+use crate::display::{SegmentDisplay, MAX_BRIGHTNESS, PATTERN_ALL_ON};
+
+// TM1637 4-digit display module (2-wire interface)
+//
+// The TM1637 multiplexes the digits itself, so a frame is only sent when it
+// changes. Its two lines are open drain with pull-ups on the module: a line
+// is driven low as an output and released high by switching it to an input.
+
+pub type ClockPin = B0;  // CLK
+pub type DataPin = B1;   // DIO
+
+// Commands
+const DATA_AUTO_INCREMENT: u8 = 0x40;  // Write display data, auto address increment
+const ADDRESS_DIGIT0: u8 = 0xC0;       // Start writing at the first digit
+const DISPLAY_ON: u8 = 0x88;           // Display on, brightness in bits 0-2
+
+// Half clock period (µs), keeps the bus well under its 250kHz limit
+const BIT_DELAY_US: u64 = 5;
+
+pub struct Tm1637Display {
+    frame: [u8; 4],
+    brightness: u8, // TM1637 brightness 0-7
+}
+
+impl Tm1637Display {
+    pub fn new() -> Self {
+        Tm1637Display {
+            frame: [0; 4],
+            brightness: 7,
+        }
+    }
+    
+    // Send the frame and display control command
+    fn write(&self) {
+        start();
+        write_byte(DATA_AUTO_INCREMENT);
+        stop();
+        
+        start();
+        write_byte(ADDRESS_DIGIT0);
+        for &pattern in &self.frame {
+            write_byte(pattern);
+        }
+        stop();
+        
+        start();
+        write_byte(DISPLAY_ON | self.brightness);
+        stop();
+    }
+}
+
+impl SegmentDisplay for Tm1637Display {
+    // Release both lines and light all segments for a moment as a test
+    fn initialize(&mut self) {
+        clock(true);
+        data(true);
+        delay::delay_ms(50);
+        
+        self.frame = [PATTERN_ALL_ON; 4];
+        self.write();
+        delay::delay_ms(800);
+    }
+    
+    fn show(&mut self, frame: [u8; 4]) {
+        if frame != self.frame {
+            self.frame = frame;
+            self.write();
+        }
+    }
+    
+    // The TM1637 has 8 brightness steps (0-7), one per level
+    fn set_brightness(&mut self, level: u8) {
+        let level = if level > MAX_BRIGHTNESS { MAX_BRIGHTNESS } else { level };
+        self.brightness = if level == 0 { 0 } else { level - 1 };
+        self.write();
+    }
+}
+
+// Clock line high (released) or low. Released lines are inputs with the
+// internal pull-up off, so the module pull-up takes them high.
+fn clock(high: bool) {
+    if high {
+        ClockPin::set_input();
+        ClockPin::set_low();
+    } else {
+        ClockPin::set_output();
+        ClockPin::set_low();
+    }
+    delay::delay_us(BIT_DELAY_US);
+}
+
+// Data line high (released) or low
+fn data(high: bool) {
+    if high {
+        DataPin::set_input();
+        DataPin::set_low();
+    } else {
+        DataPin::set_output();
+        DataPin::set_low();
+    }
+    delay::delay_us(BIT_DELAY_US);
+}
+
+// Start condition: data falls while the clock is high
+fn start() {
+    clock(true);
+    data(true);
+    data(false);
+}
+
+// Stop condition: data rises while the clock is high
+fn stop() {
+    clock(false);
+    data(false);
+    clock(true);
+    data(true);
+}
+
+// Write a byte LSB first and clock in the acknowledge bit
+fn write_byte(byte: u8) {
+    for i in 0..8 {
+        clock(false);
+        data(byte & (1 << i) != 0);
+        clock(true);
+    }
+    
+    // Acknowledge: the TM1637 pulls data low during the ninth clock
+    clock(false);
+    data(true);
+    clock(true);
+    clock(false);
+}
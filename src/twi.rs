@@ -0,0 +1,105 @@
+use ruduino::Register;
+
+// Register definitions for the TWI (I2C) interface
+pub struct TWBR;
+impl Register for TWBR {
+    type T = u8;
+    const ADDRESS: *mut u8 = 0xB8 as *mut u8;
+}
+
+// TWI Status Register
+pub struct TWSR;
+impl Register for TWSR {
+    type T = u8;
+    const ADDRESS: *mut u8 = 0xB9 as *mut u8;
+}
+
+// TWI Data Register
+pub struct TWDR;
+impl Register for TWDR {
+    type T = u8;
+    const ADDRESS: *mut u8 = 0xBB as *mut u8;
+}
+
+// TWI Control Register
+pub struct TWCR;
+impl Register for TWCR {
+    type T = u8;
+    const ADDRESS: *mut u8 = 0xBC as *mut u8;
+}
+
+// TWCR bits
+pub const TWINT: u8 = 1 << 7;  // TWI Interrupt Flag (operation complete)
+pub const TWSTA: u8 = 1 << 5;  // START Condition
+pub const TWSTO: u8 = 1 << 4;  // STOP Condition
+pub const TWEN: u8 = 1 << 2;   // TWI Enable
+
+// Status codes (TWSR with the prescaler bits masked off)
+const STATUS_START: u8 = 0x08;
+const STATUS_REPEATED_START: u8 = 0x10;
+const STATUS_ADDRESS_ACK: u8 = 0x18;  // SLA+W sent, ACK received
+const STATUS_DATA_ACK: u8 = 0x28;     // Data sent, ACK received
+
+// 100kHz bus: 16MHz / (16 + 2 * 72) with prescaler 1
+const BIT_RATE: u8 = 72;
+
+// Polls to wait for an operation before giving up (a stuck bus must not hang
+// the main loop)
+const TIMEOUT_POLLS: u16 = 2000;
+
+// Initialize the TWI as bus master at 100kHz on SDA (C4/A4) and SCL (C5/A5)
+pub fn initialize() {
+    TWSR::write(0); // Prescaler 1
+    TWBR::write(BIT_RATE);
+    TWCR::write(TWEN);
+}
+
+// Write bytes to a device, returning false if it did not acknowledge
+pub fn write(address: u8, data: &[u8]) -> bool {
+    let mut ok = start() && send(address << 1, STATUS_ADDRESS_ACK);
+    for &byte in data {
+        if !ok {
+            break;
+        }
+        ok = send(byte, STATUS_DATA_ACK);
+    }
+    stop();
+    ok
+}
+
+// Send a START condition
+fn start() -> bool {
+    TWCR::write(TWINT | TWSTA | TWEN);
+    if !wait() {
+        return false;
+    }
+    let status = status();
+    status == STATUS_START || status == STATUS_REPEATED_START
+}
+
+// Send a byte and check for the expected status
+fn send(byte: u8, expected_status: u8) -> bool {
+    TWDR::write(byte);
+    TWCR::write(TWINT | TWEN);
+    wait() && status() == expected_status
+}
+
+// Send a STOP condition
+fn stop() {
+    TWCR::write(TWINT | TWSTO | TWEN);
+}
+
+// Wait for the current operation to complete
+fn wait() -> bool {
+    for _ in 0..TIMEOUT_POLLS {
+        if TWCR::read() & TWINT != 0 {
+            return true;
+        }
+    }
+    false
+}
+
+// Current bus status
+fn status() -> u8 {
+    TWSR::read() & 0xF8
+}
@@ -1,4 +1,9 @@
 use ruduino::Register;
+use core::sync::atomic::{AtomicU8, Ordering};
+#[cfg(feature = "modbus")]
+use core::sync::atomic::AtomicBool;
+#[cfg(feature = "modbus")]
+use ruduino::interrupt::without_interrupts;
 
 // UART configuration
 pub const BAUD_RATE: u32 = 9600;
@@ -48,18 +53,39 @@ impl Register for UDR0 {
 }
 
 // UCSR0A bits
-pub const RXC0: u8 = 1 << 7;   // USART Receive Complete
+// pub const RXC0: u8 = 1 << 7;   // USART Receive Complete
+#[cfg(feature = "modbus-de")]
 pub const TXC0: u8 = 1 << 6;   // USART Transmit Complete
 pub const UDRE0: u8 = 1 << 5;  // USART Data Register Empty
+#[cfg(feature = "modbus")]
+pub const FE0: u8 = 1 << 4;    // Frame Error
+#[cfg(feature = "modbus")]
+pub const DOR0: u8 = 1 << 3;   // Data OverRun
+#[cfg(feature = "modbus")]
+pub const UPE0: u8 = 1 << 2;   // USART Parity Error
 
 // UCSR0B bits
+pub const RXCIE0: u8 = 1 << 7; // RX Complete Interrupt Enable
 pub const RXEN0: u8 = 1 << 4;  // Receiver Enable
 pub const TXEN0: u8 = 1 << 3;  // Transmitter Enable
 
 // UCSR0C bits
+#[cfg(feature = "modbus")]
+pub const UPM01: u8 = 1 << 5;  // Parity Mode bit 1 (even parity)
 pub const UCSZ01: u8 = 1 << 2; // Character Size bit 1
 pub const UCSZ00: u8 = 1 << 1; // Character Size bit 0
 
+// Receive ring buffer, filled by the RX interrupt so bytes are not lost
+// while the main loop is busy (e.g. sending telemetry)
+const RX_BUFFER_SIZE: u8 = 32;
+static mut RX_BUFFER: [u8; RX_BUFFER_SIZE as usize] = [0; RX_BUFFER_SIZE as usize];
+static RX_HEAD: AtomicU8 = AtomicU8::new(0); // Next write position (interrupt)
+static RX_TAIL: AtomicU8 = AtomicU8::new(0); // Next read position (main loop)
+
+// A byte was damaged or lost since the last take_error
+#[cfg(feature = "modbus")]
+static RX_ERROR: AtomicBool = AtomicBool::new(false);
+
 // Initialize the UART
 pub fn initialize() {
     // Set baud rate
@@ -67,11 +93,75 @@ pub fn initialize() {
     UBRR0H::write((ubrr >> 8) as u8);
     UBRR0L::write(ubrr as u8);
     
-    // Enable transmitter
-    UCSR0B::write(TXEN0);
+    // Enable transmitter, receiver and the receive interrupt
+    UCSR0B::write(TXEN0 | RXEN0 | RXCIE0);
     
-    // Set frame format: 8 data bits, 1 stop bit, no parity
+    // Set frame format: 8 data bits, 1 stop bit, no parity (even parity
+    // for Modbus RTU)
+    #[cfg(not(feature = "modbus"))]
     UCSR0C::write(UCSZ01 | UCSZ00);
+    #[cfg(feature = "modbus")]
+    UCSR0C::write(UPM01 | UCSZ01 | UCSZ00);
+}
+
+/// USART receive complete interrupt handler, queues the received byte
+#[no_mangle]
+pub extern "avr-interrupt" fn __vector_18() {
+    // The error flags belong to the byte in UDR0, so read them first
+    #[cfg(feature = "modbus")]
+    {
+        if UCSR0A::read() & (FE0 | DOR0 | UPE0) != 0 {
+            RX_ERROR.store(true, Ordering::Relaxed);
+        }
+    }
+    let data = UDR0::read();
+    let head = RX_HEAD.load(Ordering::Relaxed);
+    let next = (head + 1) % RX_BUFFER_SIZE;
+    
+    // Drop the byte if the buffer is full
+    if next != RX_TAIL.load(Ordering::Relaxed) {
+        unsafe {
+            RX_BUFFER[head as usize] = data;
+        }
+        RX_HEAD.store(next, Ordering::Release);
+    } else {
+        #[cfg(feature = "modbus")]
+        RX_ERROR.store(true, Ordering::Relaxed);
+    }
+}
+
+// Take the next received byte, if any
+pub fn read_byte() -> Option<u8> {
+    let tail = RX_TAIL.load(Ordering::Relaxed);
+    if tail == RX_HEAD.load(Ordering::Acquire) {
+        return None;
+    }
+    
+    let data = unsafe { RX_BUFFER[tail as usize] };
+    RX_TAIL.store((tail + 1) % RX_BUFFER_SIZE, Ordering::Relaxed);
+    Some(data)
+}
+
+// Check and clear the receive error flag
+#[cfg(feature = "modbus")]
+pub fn take_error() -> bool {
+    without_interrupts(|| {
+        let error = RX_ERROR.load(Ordering::Relaxed);
+        RX_ERROR.store(false, Ordering::Relaxed);
+        error
+    })
+}
+
+// Clear the transmit complete flag (by writing a one) before sending
+#[cfg(feature = "modbus-de")]
+pub fn start_transmission() {
+    UCSR0A::write(TXC0);
+}
+
+// Wait until the last byte has left the shift register
+#[cfg(feature = "modbus-de")]
+pub fn wait_transmission_complete() {
+    while UCSR0A::read() & TXC0 == 0 {}
 }
 
 // Send a single byte over UART
@@ -0,0 +1,198 @@
+use ruduino::Register;
+use ruduino::interrupt::without_interrupts;
+use core::arch::asm;
+use crate::eeprom;
+
+// Watchdog supervision and reset cause
+//
+// The watchdog resets the AVR unless the main loop kicks it within TIMEOUT,
+// so a hung 1-Wire transfer or ADC wait cannot freeze the monitor. The
+// longest legitimate pause (a full history dump over serial) takes ~2s.
+// Work that grows with the SD card, like counting its free space, is spread
+// over loop iterations; the loops that remain are limited by the size of a
+// file or directory and kick the watchdog themselves.
+//
+// Why the AVR last reset is read from MCUSR at boot and counted in EEPROM,
+// at eeprom::RESETS_ADDRESS:
+//
+//   0      MAGIC
+//   1-2    all resets (u16, little endian)
+//   3-4    watchdog resets
+//   5-6    brownout resets
+//
+// Counts stop at 65535. Bootloaders that clear MCUSR themselves (Optiboot)
+// leave nothing to read, and those resets show as unknown.
+
+// MCU Status Register
+pub struct MCUSR;
+impl Register for MCUSR {
+    type T = u8;
+    const ADDRESS: *mut u8 = 0x54 as *mut u8;
+}
+
+// Watchdog Timer Control Register
+pub struct WDTCSR;
+impl Register for WDTCSR {
+    type T = u8;
+    const ADDRESS: *mut u8 = 0x60 as *mut u8;
+}
+
+// MCUSR bits
+pub const WDRF: u8 = 1 << 3;   // Watchdog Reset Flag
+pub const BORF: u8 = 1 << 2;   // Brown-out Reset Flag
+pub const EXTRF: u8 = 1 << 1;  // External Reset Flag
+pub const PORF: u8 = 1 << 0;   // Power-on Reset Flag
+
+// WDTCSR bits
+pub const WDP3: u8 = 1 << 5;   // Prescaler bit 3
+pub const WDCE: u8 = 1 << 4;   // Change Enable
+pub const WDE: u8 = 1 << 3;    // System Reset Enable
+
+// 4s timeout (512K cycles of the 128kHz watchdog oscillator)
+const TIMEOUT: u8 = WDP3;
+
+// Marks a written counter block; change it when the layout changes
+const MAGIC: u8 = 0x5B;
+
+const LENGTH: usize = 7;
+
+// Why the AVR last reset
+#[derive(PartialEq, Copy, Clone)]
+pub enum ResetCause {
+    Unknown,
+    PowerOn,
+    External,  // Reset pin (or the bootloader after an upload)
+    Brownout,  // Supply dropped below the BOD level
+    Watchdog,  // The main loop stopped kicking the watchdog
+}
+
+impl ResetCause {
+    // Code sent over serial
+    pub fn code(self) -> &'static str {
+        match self {
+            ResetCause::Unknown => "unknown",
+            ResetCause::PowerOn => "power",
+            ResetCause::External => "external",
+            ResetCause::Brownout => "brownout",
+            ResetCause::Watchdog => "watchdog",
+        }
+    }
+    
+    // Message shown on the display
+    pub fn message(self) -> &'static str {
+        match self {
+            ResetCause::Unknown => "unknown",
+            ResetCause::PowerOn => "power on",
+            ResetCause::External => "reset button",
+            ResetCause::Brownout => "brownout",
+            ResetCause::Watchdog => "watchdog",
+        }
+    }
+    
+    // The monitor did not mean to reset
+    pub fn is_fault(self) -> bool {
+        self == ResetCause::Brownout || self == ResetCause::Watchdog
+    }
+}
+
+// Resets counted in EEPROM
+#[derive(Copy, Clone)]
+pub struct ResetCounts {
+    pub total: u16,
+    pub watchdog: u16,
+    pub brownout: u16,
+}
+
+static mut RESET_CAUSE: ResetCause = ResetCause::Unknown;
+static mut RESET_COUNTS: ResetCounts = ResetCounts { total: 0, watchdog: 0, brownout: 0 };
+
+// Read and clear the reset flags and start the watchdog. Call first thing at
+// boot: after a watchdog reset it is still running with its shortest
+// timeout, and stays on until WDRF is cleared.
+pub fn initialize() {
+    let flags = MCUSR::read();
+    MCUSR::write(0);
+    enable();
+    
+    // Power on sets the brownout flag as well, so it is checked first
+    let cause = if flags & PORF != 0 {
+        ResetCause::PowerOn
+    } else if flags & WDRF != 0 {
+        ResetCause::Watchdog
+    } else if flags & BORF != 0 {
+        ResetCause::Brownout
+    } else if flags & EXTRF != 0 {
+        ResetCause::External
+    } else {
+        ResetCause::Unknown
+    };
+    unsafe {
+        RESET_CAUSE = cause;
+    }
+}
+
+// Add the reset to the counts in EEPROM. Call once the outputs are in their
+// safe states, as each EEPROM byte written takes 3.4ms.
+pub fn count_reset() {
+    unsafe {
+        RESET_COUNTS = update_counts(RESET_CAUSE);
+    }
+}
+
+// Why the AVR last reset
+pub fn reset_cause() -> ResetCause {
+    unsafe { RESET_CAUSE }
+}
+
+// Resets so far, including the last one
+pub fn reset_counts() -> ResetCounts {
+    unsafe { RESET_COUNTS }
+}
+
+// Start the watchdog with TIMEOUT
+fn enable() {
+    // The new setting must be written within four cycles of WDCE
+    without_interrupts(|| {
+        kick();
+        WDTCSR::write(WDCE | WDE);
+        WDTCSR::write(WDE | TIMEOUT);
+    });
+}
+
+// Restart the watchdog timeout
+pub fn kick() {
+    unsafe {
+        asm!("wdr");
+    }
+}
+
+// Read the counts from EEPROM and write them back with the reset added
+fn update_counts(cause: ResetCause) -> ResetCounts {
+    let mut data = [0u8; LENGTH];
+    eeprom::read(eeprom::RESETS_ADDRESS, &mut data);
+    
+    let word = |offset: usize| data[offset] as u16 | (data[offset + 1] as u16) << 8;
+    let mut counts = if data[0] == MAGIC {
+        ResetCounts {
+            total: word(1),
+            watchdog: word(3),
+            brownout: word(5),
+        }
+    } else {
+        ResetCounts { total: 0, watchdog: 0, brownout: 0 }
+    };
+    
+    counts.total = counts.total.saturating_add(1);
+    match cause {
+        ResetCause::Watchdog => counts.watchdog = counts.watchdog.saturating_add(1),
+        ResetCause::Brownout => counts.brownout = counts.brownout.saturating_add(1),
+        _ => {},
+    }
+    
+    data[0] = MAGIC;
+    data[1..3].copy_from_slice(&counts.total.to_le_bytes());
+    data[3..5].copy_from_slice(&counts.watchdog.to_le_bytes());
+    data[5..7].copy_from_slice(&counts.brownout.to_le_bytes());
+    eeprom::update(eeprom::RESETS_ADDRESS, &data);
+    counts
+}
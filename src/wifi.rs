@@ -0,0 +1,563 @@
+use crate::uart;
+use crate::alarm;
+use crate::format::Buffer;
+use crate::sensor_manager::SensorValues;
+use crate::settings::{self, Settings, Text};
+
+// Wi-Fi uplink through an ESP-01
+//
+// An ESP8266 (or ESP32) module running the stock AT firmware takes the place
+// of the host on the serial port. The uplink joins the network and POSTs the
+// readings as JSON to an HTTP endpoint every UPLOAD_INTERVAL_MS, e.g.
+//
+//   {"device":"algae","temp":25.3,"ph":7.01,"mv":-0.4,"vcc":4.98,
+//    "temp_stable":true,"ph_stable":false,"electrode":"ok","alarm":0,"uptime":3605}
+//
+// with "temp" null while the probe has failed. Nothing waits in a loop: each
+// update sends at most one command and reads what the module has answered so
+// far, so the display and the sensors keep running. A failed attempt is
+// retried after a back-off that doubles from RETRY_MIN_MS to RETRY_MAX_MS, and
+// after REJOIN_FAILURES failed posts in a row the module is set up and joins
+// the network again.
+//
+// The network is fixed when building, from the environment variables
+// ALGAE_WIFI_SSID and ALGAE_WIFI_PASSWORD. Without them the module joins the
+// network it has saved, as the AT firmware keeps the last one it joined. The
+// endpoint comes from the settings, set over serial before the wifi build
+// takes the port, and whatever is not set there from ALGAE_POST_HOST,
+// ALGAE_POST_PORT (80) and ALGAE_POST_PATH (/readings). ALGAE_DEVICE_NAME
+// (algae) names the device in the posts.
+
+const SSID: Option<&str> = option_env!("ALGAE_WIFI_SSID");
+const PASSWORD: &str = match option_env!("ALGAE_WIFI_PASSWORD") {
+    Some(password) => password,
+    None => "", // Open network
+};
+const HOST: &str = match option_env!("ALGAE_POST_HOST") {
+    Some(host) => host,
+    None => "", // Only from the settings
+};
+const PORT: &str = match option_env!("ALGAE_POST_PORT") {
+    Some(port) => port,
+    None => "80",
+};
+const PATH: &str = match option_env!("ALGAE_POST_PATH") {
+    Some(path) => path,
+    None => "/readings",
+};
+const DEVICE_NAME: &str = match option_env!("ALGAE_DEVICE_NAME") {
+    Some(name) => name,
+    None => "algae",
+};
+
+// Timing
+const START_DELAY_MS: u64 = 2000;         // The module boots after power up
+const UPLOAD_INTERVAL_MS: u64 = 60_000;   // Between successful posts
+const RETRY_MIN_MS: u64 = 5000;           // First back-off after a failure
+const RETRY_MAX_MS: u64 = 300_000;        // Longest back-off
+const REJOIN_FAILURES: u8 = 3;            // Failed posts before joining again
+
+// How long to wait for each answer
+const PROBE_TIMEOUT_MS: u64 = 1000;
+const COMMAND_TIMEOUT_MS: u64 = 2000;
+const JOIN_TIMEOUT_MS: u64 = 20_000;
+const CONNECT_TIMEOUT_MS: u64 = 10_000;
+const SEND_TIMEOUT_MS: u64 = 5000;
+const RESPONSE_TIMEOUT_MS: u64 = 10_000;
+const CLOSE_TIMEOUT_MS: u64 = 5000;
+
+// Longest line kept from the module; the HTTP status line is the one that
+// matters and comes early in a "+IPD,<length>:" line
+const LINE_SIZE: usize = 48;
+
+// One number in the request, composed before it is sent
+type Field = Buffer<16>;
+
+// Uplink state shown on the display
+#[derive(PartialEq, Copy, Clone)]
+pub enum Status {
+    Starting,  // Waiting for the module to boot
+    NoModule,  // The module does not answer
+    Joining,   // Setting up the module and joining the network
+    NoNetwork, // The network could not be joined
+    NoServer,  // No host to post to is set
+    Online,    // Joined, nothing posted yet
+    Sent,      // The last post was accepted
+    Failed,    // The last post failed
+}
+
+impl Status {
+    // Message shown on the display
+    pub fn message(self) -> &'static str {
+        match self {
+            Status::Starting => "start",
+            Status::NoModule => "no module",
+            Status::Joining => "joining",
+            Status::NoNetwork => "no network",
+            Status::NoServer => "no server",
+            Status::Online => "online",
+            Status::Sent => "sent",
+            Status::Failed => "post failed",
+        }
+    }
+    
+    // Four character form for the LCD dashboard
+    pub fn code(self) -> &'static str {
+        match self {
+            Status::Starting => "strt",
+            Status::NoModule => "ESP?",
+            Status::Joining => "join",
+            Status::NoNetwork => "net?",
+            Status::NoServer => "srv?",
+            Status::Online => "up",
+            Status::Sent => "sent",
+            Status::Failed => "fail",
+        }
+    }
+    
+    // Something needs looking at
+    pub fn is_problem(self) -> bool {
+        matches!(self, Status::NoModule | Status::NoNetwork | Status::NoServer | Status::Failed)
+    }
+}
+
+static mut STATUS: Status = Status::Starting;
+
+// The uplink state, for the display
+pub fn status() -> Status {
+    unsafe { STATUS }
+}
+
+fn set_status(status: Status) {
+    unsafe {
+        STATUS = status;
+    }
+}
+
+// What the uplink is waiting for
+#[derive(PartialEq, Copy, Clone)]
+enum State {
+    Idle,        // The next attempt
+    Probe,       // OK to AT
+    EchoOff,     // OK to ATE0
+    StationMode, // OK to AT+CWMODE=1
+    Join,        // OK to AT+CWJAP, or to AT+CWJAP? on the saved network
+    Connect,     // OK to AT+CIPSTART
+    Prompt,      // The > prompt after AT+CIPSEND
+    Request,     // SEND OK after the request
+    Response,    // The HTTP status line
+    Close,       // The server closing the connection
+}
+
+// Readings taken when the request length is counted, so the request sent
+// matches it
+#[derive(Copy, Clone)]
+struct Report {
+    temperature: Option<f32>,
+    ph: f32,
+    ph_mv: f32,
+    supply_voltage: f32,
+    temperature_stable: bool,
+    ph_stable: bool,
+    electrode: &'static str,
+    alarm: u8,
+    uptime_s: u32,
+}
+
+pub struct WifiUplink {
+    state: State,
+    deadline: u64,          // When the current state times out
+    next_attempt_time: u64, // When to start the next attempt while idle
+    joined: bool,           // The module is on the network
+    failures: u8,           // Failed attempts since the last post
+    accepted: bool,         // The server answered the post with 2xx
+    report: Report,
+    line: [u8; LINE_SIZE],
+    line_length: usize,
+}
+
+impl WifiUplink {
+    pub fn new() -> Self {
+        WifiUplink {
+            state: State::Idle,
+            deadline: 0,
+            next_attempt_time: START_DELAY_MS,
+            joined: false,
+            failures: 0,
+            accepted: false,
+            report: Report {
+                temperature: None,
+                ph: 0.0,
+                ph_mv: 0.0,
+                supply_voltage: 0.0,
+                temperature_stable: false,
+                ph_stable: false,
+                electrode: "ok",
+                alarm: 0,
+                uptime_s: 0,
+            },
+            line: [0; LINE_SIZE],
+            line_length: 0,
+        }
+    }
+    
+    // Initialize the serial port the module is on
+    pub fn initialize(&self) {
+        uart::initialize();
+    }
+    
+    // Read the module's answers and take the next step when one is due
+    pub fn update(&mut self, values: &SensorValues, settings: &Settings, current_time: u64) {
+        while let Some(byte) = uart::read_byte() {
+            match byte {
+                // The prompt for the request has no line ending
+                b'>' if self.state == State::Prompt => {
+                    write_request(&mut Uart, &self.report);
+                    self.wait(State::Request, current_time + SEND_TIMEOUT_MS);
+                },
+                b'\n' => {
+                    self.handle_line(values, settings, current_time);
+                    self.line_length = 0;
+                },
+                b'\r' => {},
+                _ => {
+                    if self.line_length < LINE_SIZE {
+                        self.line[self.line_length] = byte;
+                        self.line_length += 1;
+                    }
+                }
+            }
+        }
+        
+        if self.state == State::Idle {
+            if current_time >= self.next_attempt_time {
+                self.start(settings, current_time);
+            }
+        } else if current_time >= self.deadline {
+            if self.state == State::Close && self.accepted {
+                // Answered but left open: close it ourselves
+                uart::send_string("AT+CIPCLOSE\r\n");
+                self.finish(current_time);
+            } else {
+                self.fail(current_time);
+            }
+        }
+    }
+    
+    // Begin an attempt: set up and join first if needed, otherwise post
+    fn start(&mut self, settings: &Settings, current_time: u64) {
+        if self.joined {
+            self.connect(settings, current_time);
+        } else {
+            if status() != Status::NoModule && status() != Status::NoNetwork {
+                set_status(Status::Joining);
+            }
+            uart::send_string("AT\r\n");
+            self.wait(State::Probe, current_time + PROBE_TIMEOUT_MS);
+        }
+    }
+    
+    // Act on one complete line from the module
+    fn handle_line(&mut self, values: &SensorValues, settings: &Settings, current_time: u64) {
+        let line = &self.line[..self.line_length];
+        
+        // The module lost the access point; posts fail until it is joined again
+        if line == b"WIFI DISCONNECT" {
+            self.joined = false;
+            return;
+        }
+        
+        match self.state {
+            State::Idle => {},
+            State::Probe | State::EchoOff | State::StationMode | State::Join => {
+                if line == b"OK" {
+                    self.next_setup_step(settings, current_time);
+                } else if line == b"ERROR" || line == b"FAIL" || line == b"No AP" {
+                    self.fail(current_time);
+                }
+            },
+            State::Connect => {
+                if line == b"OK" {
+                    // Count the request, then wait for the prompt to send it
+                    self.report = report(values, current_time);
+                    let mut length = Counter(0);
+                    write_request(&mut length, &self.report);
+                    uart::send_string("AT+CIPSEND=");
+                    integer(&mut Uart, length.0);
+                    uart::send_string("\r\n");
+                    self.wait(State::Prompt, current_time + SEND_TIMEOUT_MS);
+                } else if line == b"ERROR" || line == b"ALREADY CONNECTED" || line == b"CLOSED" {
+                    self.fail(current_time);
+                }
+            },
+            State::Prompt => {
+                if line == b"ERROR" {
+                    self.fail(current_time);
+                }
+            },
+            State::Request => {
+                if line == b"SEND OK" {
+                    self.accepted = false;
+                    self.wait(State::Response, current_time + RESPONSE_TIMEOUT_MS);
+                } else if line == b"SEND FAIL" || line == b"ERROR" || line == b"CLOSED" {
+                    self.fail(current_time);
+                }
+            },
+            State::Response => {
+                if line.starts_with(b"+IPD,") {
+                    // The status line follows the colon: "HTTP/1.1 200 OK"
+                    if let Some(colon) = line.iter().position(|&byte| byte == b':') {
+                        let status_line = &line[colon + 1..];
+                        self.accepted = status_line.starts_with(b"HTTP/1.") && status_line.get(9) == Some(&b'2');
+                    }
+                    self.wait(State::Close, current_time + CLOSE_TIMEOUT_MS);
+                } else if line == b"CLOSED" {
+                    self.fail(current_time);
+                }
+            },
+            State::Close => {
+                if line == b"CLOSED" {
+                    self.finish(current_time);
+                }
+            },
+        }
+    }
+    
+    // Send the next setup command once the previous one is answered
+    fn next_setup_step(&mut self, settings: &Settings, current_time: u64) {
+        match self.state {
+            State::Probe => {
+                uart::send_string("ATE0\r\n");
+                self.wait(State::EchoOff, current_time + COMMAND_TIMEOUT_MS);
+            },
+            State::EchoOff => {
+                uart::send_string("AT+CWMODE=1\r\n");
+                self.wait(State::StationMode, current_time + COMMAND_TIMEOUT_MS);
+            },
+            State::StationMode => {
+                match SSID {
+                    Some(ssid) => {
+                        uart::send_string("AT+CWJAP=\"");
+                        send_quoted(ssid);
+                        uart::send_string("\",\"");
+                        send_quoted(PASSWORD);
+                        uart::send_string("\"\r\n");
+                    },
+                    // Answered "No AP" until the saved network is joined
+                    None => uart::send_string("AT+CWJAP?\r\n"),
+                }
+                self.wait(State::Join, current_time + JOIN_TIMEOUT_MS);
+            },
+            _ => {
+                // Joined: post straight away
+                self.joined = true;
+                set_status(Status::Online);
+                self.connect(settings, current_time);
+            }
+        }
+    }
+    
+    // Open the connection to the server
+    fn connect(&mut self, settings: &Settings, current_time: u64) {
+        let mut host = [0; settings::TEXT_SIZE];
+        let host = endpoint(Text::PostHost, &mut host, HOST);
+        if host.is_empty() {
+            // Nothing will change that without a restart
+            set_status(Status::NoServer);
+            self.state = State::Idle;
+            self.next_attempt_time = current_time + RETRY_MAX_MS;
+            return;
+        }
+        
+        uart::send_string("AT+CIPSTART=\"TCP\",\"");
+        send_quoted(host);
+        uart::send_string("\",");
+        match settings.post_port {
+            0 => uart::send_string(PORT),
+            port => integer(&mut Uart, port as u32),
+        }
+        uart::send_string("\r\n");
+        self.wait(State::Connect, current_time + CONNECT_TIMEOUT_MS);
+    }
+    
+    // The server has closed the connection after answering
+    fn finish(&mut self, current_time: u64) {
+        if self.accepted {
+            self.failures = 0;
+            set_status(Status::Sent);
+            self.state = State::Idle;
+            self.next_attempt_time = current_time + UPLOAD_INTERVAL_MS;
+        } else {
+            self.fail(current_time);
+        }
+    }
+    
+    // Give up on this attempt and back off before the next
+    fn fail(&mut self, current_time: u64) {
+        let status = match self.state {
+            State::Probe | State::EchoOff | State::StationMode => Status::NoModule,
+            State::Join => Status::NoNetwork,
+            _ => {
+                // Drop whatever is left of the connection, without waiting
+                uart::send_string("AT+CIPCLOSE\r\n");
+                Status::Failed
+            }
+        };
+        set_status(status);
+        
+        self.failures = self.failures.saturating_add(1);
+        if status != Status::Failed || self.failures >= REJOIN_FAILURES {
+            self.joined = false;
+        }
+        
+        self.state = State::Idle;
+        self.next_attempt_time = current_time + retry_delay(self.failures);
+    }
+    
+    fn wait(&mut self, state: State, deadline: u64) {
+        self.state = state;
+        self.deadline = deadline;
+    }
+}
+
+// Back-off after the given number of failures in a row
+fn retry_delay(failures: u8) -> u64 {
+    let doublings = failures.saturating_sub(1).min(8);
+    (RETRY_MIN_MS << doublings).min(RETRY_MAX_MS)
+}
+
+fn report(values: &SensorValues, current_time: u64) -> Report {
+    Report {
+        temperature: if values.temperature_fault { None } else { Some(values.temperature) },
+        ph: values.ph,
+        ph_mv: values.ph_mv,
+        supply_voltage: values.supply_voltage,
+        temperature_stable: values.temperature_stable,
+        ph_stable: values.ph_stable,
+        electrode: values.electrode_health.code(),
+        alarm: alarm::condition(values).code(),
+        uptime_s: (current_time / 1000) as u32,
+    }
+}
+
+// A text setting, or the build's default if it is not set
+fn endpoint<'a>(text: Text, value: &'a mut [u8; settings::TEXT_SIZE], default: &'a str) -> &'a str {
+    let length = settings::load_text(text, value);
+    if length == 0 {
+        return default;
+    }
+    core::str::from_utf8(&value[..length]).unwrap_or(default)
+}
+
+// Send text inside a quoted AT command argument
+fn send_quoted(text: &str) {
+    for byte in text.bytes() {
+        if byte == b'"' || byte == b',' || byte == b'\\' {
+            uart::send_byte(b'\\');
+        }
+        uart::send_byte(byte);
+    }
+}
+
+// Where the request is written: counted first for AT+CIPSEND, then sent
+trait Sink {
+    fn byte(&mut self, byte: u8);
+    
+    fn bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.byte(byte);
+        }
+    }
+    
+    fn text(&mut self, text: &str) {
+        self.bytes(text.as_bytes());
+    }
+}
+
+struct Counter(u32);
+
+impl Sink for Counter {
+    fn byte(&mut self, _byte: u8) {
+        self.0 += 1;
+    }
+}
+
+struct Uart;
+
+impl Sink for Uart {
+    fn byte(&mut self, byte: u8) {
+        uart::send_byte(byte);
+    }
+}
+
+fn write_request(sink: &mut impl Sink, report: &Report) {
+    let mut length = Counter(0);
+    write_body(&mut length, report);
+    
+    let mut path = [0; settings::TEXT_SIZE];
+    let mut host = [0; settings::TEXT_SIZE];
+    sink.text("POST ");
+    sink.text(endpoint(Text::PostPath, &mut path, PATH));
+    sink.text(" HTTP/1.1\r\nHost: ");
+    sink.text(endpoint(Text::PostHost, &mut host, HOST));
+    sink.text("\r\nContent-Type: application/json\r\nContent-Length: ");
+    integer(sink, length.0);
+    sink.text("\r\nConnection: close\r\n\r\n");
+    write_body(sink, report);
+}
+
+fn write_body(sink: &mut impl Sink, report: &Report) {
+    sink.text("{\"device\":");
+    string(sink, DEVICE_NAME);
+    sink.text(",\"temp\":");
+    match report.temperature {
+        Some(temperature) => decimal(sink, temperature, 1),
+        None => sink.text("null"),
+    }
+    sink.text(",\"ph\":");
+    decimal(sink, report.ph, 2);
+    sink.text(",\"mv\":");
+    decimal(sink, report.ph_mv, 1);
+    sink.text(",\"vcc\":");
+    decimal(sink, report.supply_voltage, 2);
+    sink.text(",\"temp_stable\":");
+    boolean(sink, report.temperature_stable);
+    sink.text(",\"ph_stable\":");
+    boolean(sink, report.ph_stable);
+    sink.text(",\"electrode\":");
+    string(sink, report.electrode);
+    sink.text(",\"alarm\":");
+    integer(sink, report.alarm as u32);
+    sink.text(",\"uptime\":");
+    integer(sink, report.uptime_s);
+    sink.text("}");
+}
+
+fn integer(sink: &mut impl Sink, value: u32) {
+    let mut field = Field::new();
+    field.push_integer(value);
+    sink.bytes(field.as_bytes());
+}
+
+// A number rounded to the given decimal places, with a sign when negative
+fn decimal(sink: &mut impl Sink, value: f32, decimal_places: u8) {
+    let mut field = Field::new();
+    field.push_fixed(value, decimal_places);
+    sink.bytes(field.as_bytes());
+}
+
+// A JSON string
+fn string(sink: &mut impl Sink, text: &str) {
+    sink.byte(b'"');
+    for byte in text.bytes() {
+        if byte == b'"' || byte == b'\\' {
+            sink.byte(b'\\');
+        }
+        sink.byte(byte);
+    }
+    sink.byte(b'"');
+}
+
+fn boolean(sink: &mut impl Sink, value: bool) {
+    sink.text(if value { "true" } else { "false" });
+}